@@ -0,0 +1,115 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use chip8::bus::Bus;
+use chip8::cpu::{Backend, CPU};
+use chip8::graphics::{Graphics, GraphicsDevice};
+use chip8::instructions::Instruction;
+use chip8::memory::PROGRAM_START;
+
+
+const FRAMES: usize = 60;
+const INSTRUCTIONS_PER_FRAME: usize = 10;
+
+// Register arithmetic in a tight loop
+static ARITHMETIC: [u8; 22] = [
+    0x60, 0x00, 0x61, 0x01,                         // LD V0, 0; LD V1, 1
+    0x80, 0x14, 0x81, 0x15, 0x82, 0x16, 0x82, 0x1E, // ADD V0, V1; SUB V1, V1; SHR V2, V1; SHL V2, V1
+    0x83, 0x03, 0x73, 0x01, 0xA3, 0x00, 0xF3, 0x1E, // XOR V3, V0; ADD V3, 1; LD I, 0x300; ADD I, V3
+    0x12, 0x04                                      // JP 0x204
+];
+
+// Draws the font digits across the screen, one row after another
+static SPRITES: [u8; 24] = [
+    0x60, 0x00, 0x61, 0x00, 0x62, 0x00,             // LD V0, 0; LD V1, 0; LD V2, 0
+    0xF2, 0x29, 0xD0, 0x15, 0x70, 0x08, 0x72, 0x01, // LD F, V2; DRW V0, V1, 5; ADD V0, 8; ADD V2, 1
+    0x30, 0x40, 0x12, 0x06,                         // SE V0, 0x40; JP 0x206
+    0x60, 0x00, 0x71, 0x06, 0x12, 0x06              // LD V0, 0; ADD V1, 6; JP 0x206
+];
+
+// Calls a subroutine that converts a counter to BCD and reads the digits back
+static SUBROUTINES: [u8; 22] = [
+    0xA3, 0x00, 0x22, 0x10, 0x74, 0x01, 0x12, 0x02, // LD I, 0x300; CALL 0x210; ADD V4, 1; JP 0x202
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xF4, 0x33, 0xF2, 0x65, 0x00, 0xEE              // LD B, V4; LD V2, [I]; RET
+];
+
+fn get_cpu(program: &[u8]) -> CPU {
+    let mut cpu = CPU::new(Bus::new());
+    cpu.bus.memory.load_rom(program).unwrap();
+    cpu.set_pc(&PROGRAM_START);
+    cpu
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(0x10000));
+    group.bench_function("all opcodes", |b| b.iter(|| {
+        for opcode in 0..=0xFFFFu16 {
+            black_box(Instruction::fetch_opcode(black_box(&opcode)));
+        }
+    }));
+    group.finish();
+}
+
+fn execute(c: &mut Criterion) {
+    let instructions = [
+        ("load", Instruction::LoadRegister{register: 1, value: 0x42}),
+        ("add", Instruction::AddRegisterToRegister{destination_register: 1, source_register: 2}),
+        ("shift", Instruction::ShiftLeft{destination_register: 1, source_register: 2}),
+        ("skip", Instruction::SkipIfRegistersEqual{register_1: 1, register_2: 2}),
+        ("jump", Instruction::Jump{address: 0x200}),
+        ("random", Instruction::GenerateRandomData{register: 1, value: 0xFF}),
+        ("draw", Instruction::DisplaySpriteAtLocation{x: 1, y: 2, n: 5}),
+        ("bcd", Instruction::StoreBCDValueOfRegisterToI{register: 1}),
+        ("store", Instruction::StoreNRegistersToMemory{n: 7}),
+        ("read", Instruction::ReadNRegistersFromMemory{n: 7})
+    ];
+    let mut group = c.benchmark_group("execute");
+    for (name, ins) in instructions.iter() {
+        let mut cpu = get_cpu(&[0x00, 0xE0]);
+        cpu.I = 0x300;
+        group.bench_function(*name, |b| b.iter(|| {
+            cpu.PC = PROGRAM_START;
+            cpu.execute_opcode(black_box(*ins));
+        }));
+    }
+    let mut cpu = get_cpu(&[0x00, 0xE0]);
+    group.bench_function("call and return", |b| b.iter(|| {
+        cpu.execute_opcode(black_box(Instruction::Call{address: 0x300}));
+        cpu.execute_opcode(black_box(Instruction::Return));
+    }));
+    group.finish();
+}
+
+fn graphics(c: &mut Criterion) {
+    let sprite = [0xFF; 15];
+    let mut group = c.benchmark_group("graphics");
+    let mut graphics = Graphics::new();
+    for (name, x, y, wrap) in [("aligned", 8, 8, false), ("unaligned", 13, 8, false), ("clipped", 60, 28, false),
+                               ("wrapped", 60, 28, true)].iter() {
+        group.bench_function(*name, |b| b.iter(|| {
+            black_box(graphics.draw_sprite(black_box(x), black_box(y), &sprite, *wrap));
+        }));
+    }
+    group.bench_function("clear", |b| b.iter(|| graphics.clear()));
+    group.finish();
+}
+
+fn frames(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frames");
+    group.throughput(Throughput::Elements((FRAMES * INSTRUCTIONS_PER_FRAME) as u64));
+    for (name, program) in [("arithmetic", &ARITHMETIC[..]), ("sprites", &SPRITES[..]), ("subroutines", &SUBROUTINES[..])].iter() {
+        for backend in [Backend::Interpreter, Backend::Threaded].iter() {
+            let mut cpu = get_cpu(program);
+            cpu.set_backend(*backend);
+            group.bench_with_input(BenchmarkId::new(*name, format!("{:?}", backend)), backend, |b, _| b.iter(|| {
+                for _ in 0..FRAMES {
+                    cpu.run_frame(INSTRUCTIONS_PER_FRAME);
+                }
+            }));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode, execute, graphics, frames);
+criterion_main!(benches);
@@ -0,0 +1,7 @@
+#![no_main]
+// ROM bytes through the control-flow analysis and the recompiler
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    chip8::fuzz::analyse(data);
+});
@@ -0,0 +1,7 @@
+#![no_main]
+// ROM bytes and key events run on every platform, quirk combination and backend, see chip8::fuzz::execute
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    chip8::fuzz::execute(data);
+});
@@ -0,0 +1,311 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::fmt::Write;
+use byteorder::{BigEndian, ByteOrder};
+use crate::instructions::Instruction;
+
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EdgeKind {
+    FallThrough,
+    Jump,
+    Call,
+    Skip            // Taken when a skip instruction skips
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Edge {
+    pub from: u16,  // Start of the block the edge leaves
+    pub to: u16,
+    pub kind: EdgeKind
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct BasicBlock {
+    pub start: u16,
+    pub instructions: Vec<(u16, u16, Instruction)>,    // Address, opcode and decoded instruction
+    pub unresolved: bool                                // Ends with a BNNN jump whose target depends on V0
+}
+
+impl BasicBlock {
+    // Address just past the last instruction
+    pub fn end(&self) -> u16 {
+        self.start.wrapping_add(2 * self.instructions.len() as u16)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Subroutine {
+    pub entry: u16,
+    pub blocks: Vec<u16>        // Starts of the blocks reachable from the entry without following calls
+}
+
+// Control-flow graph recovered by following every statically known path from the entry point. Bytes that
+// are never reached are taken to be data.
+pub struct ControlFlowGraph {
+    pub start: u16,
+    pub blocks: BTreeMap<u16, BasicBlock>,
+    pub edges: Vec<Edge>,
+    pub subroutines: Vec<Subroutine>,       // The entry point first, then every call target
+    pub data_references: BTreeSet<u16>,     // ANNN targets inside the ROM
+    code: Vec<bool>                         // Per ROM byte
+}
+
+// Where control can go after the instruction at pc, and whether the instruction ends its block
+fn successors(pc: u16, ins: &Instruction) -> (Vec<(u16, EdgeKind)>, bool) {
+    let next = pc.wrapping_add(2);
+    match ins {
+        Instruction::Jump{address} => (vec![(*address, EdgeKind::Jump)], true),
+        Instruction::Call{address} => (vec![(*address, EdgeKind::Call), (next, EdgeKind::FallThrough)], true),
+        Instruction::Return | Instruction::JumpToLocationAndOffset0{..} | Instruction::InvalidInstruction => (vec![], true),
+        Instruction::SkipIfEqual{..} | Instruction::SkipIfNotEqual{..} | Instruction::SkipIfRegistersEqual{..} |
+        Instruction::SkipIfRegistersNotEqual{..} | Instruction::SkipIfPressedKeyEqualToRegister{..} |
+        Instruction::DontSkipIfPressedKeyEqualToRegister{..} =>
+            (vec![(next, EdgeKind::FallThrough), (pc.wrapping_add(4), EdgeKind::Skip)], true),
+        _ => (vec![(next, EdgeKind::FallThrough)], false)
+    }
+}
+
+impl ControlFlowGraph {
+    pub fn build(rom: &[u8], start: u16) -> ControlFlowGraph {
+        let end = start as usize + rom.len();
+        let in_rom = |address: u16| address >= start && address as usize + 2 <= end;
+        let decode = |address: u16| {
+            let opcode = BigEndian::read_u16(&rom[(address - start) as usize..]);
+            (opcode, Instruction::fetch_opcode(&opcode))
+        };
+
+        // Find every reachable instruction and the addresses that start a block
+        let mut visited = HashSet::new();
+        let mut leaders = BTreeSet::new();
+        let mut calls = BTreeSet::new();
+        let mut data_references = BTreeSet::new();
+        let mut queue = VecDeque::new();
+        leaders.insert(start);
+        queue.push_back(start);
+        while let Some(pc) = queue.pop_front() {
+            if !in_rom(pc) || !visited.insert(pc) {
+                continue;
+            }
+            let (_, ins) = decode(pc);
+            if let Instruction::SetAddressRegister{value} = ins {
+                if value >= start && (value as usize) < end {
+                    data_references.insert(value);
+                }
+            }
+            let (targets, ends_block) = successors(pc, &ins);
+            for (target, kind) in targets {
+                if ends_block {
+                    leaders.insert(target);
+                }
+                if kind == EdgeKind::Call {
+                    calls.insert(target);
+                }
+                queue.push_back(target);
+            }
+        }
+
+        // Split the instructions into blocks at the leaders
+        let mut code = vec![false; rom.len()];
+        let mut blocks = BTreeMap::new();
+        let mut edges = Vec::new();
+        for leader in leaders.iter().copied().filter(|leader| visited.contains(leader)) {
+            let mut block = BasicBlock {start: leader, instructions: Vec::new(), unresolved: false};
+            let mut pc = leader;
+            loop {
+                let (opcode, ins) = decode(pc);
+                code[(pc - start) as usize] = true;
+                code[(pc - start) as usize + 1] = true;
+                let (targets, ends_block) = successors(pc, &ins);
+                block.unresolved = matches!(ins, Instruction::JumpToLocationAndOffset0{..});
+                block.instructions.push((pc, opcode, ins));
+                let next = pc.wrapping_add(2);
+                if ends_block || leaders.contains(&next) || !visited.contains(&next) {
+                    for (to, kind) in targets {
+                        if in_rom(to) {
+                            edges.push(Edge {from: leader, to, kind});
+                        }
+                    }
+                    break;
+                }
+                pc = next;
+            }
+            blocks.insert(leader, block);
+        }
+
+        let mut graph = ControlFlowGraph {start, blocks, edges, subroutines: Vec::new(), data_references, code};
+        let entries: Vec<u16> = std::iter::once(start).chain(calls.into_iter().filter(|entry| *entry != start)).collect();
+        graph.subroutines = entries.into_iter()
+            .filter(|entry| graph.blocks.contains_key(entry))
+            .map(|entry| Subroutine {entry, blocks: graph.reachable(entry)})
+            .collect();
+        graph
+    }
+
+    // Blocks reachable without following calls, in address order
+    fn reachable(&self, entry: u16) -> Vec<u16> {
+        let mut seen = BTreeSet::new();
+        let mut queue = vec![entry];
+        while let Some(block) = queue.pop() {
+            if !seen.insert(block) {
+                continue;
+            }
+            for edge in self.edges.iter().filter(|edge| edge.from == block && edge.kind != EdgeKind::Call) {
+                if self.blocks.contains_key(&edge.to) {
+                    queue.push(edge.to);
+                }
+            }
+        }
+        seen.into_iter().collect()
+    }
+
+    pub fn is_code(&self, address: u16) -> bool {
+        address >= self.start && self.code.get((address - self.start) as usize).copied().unwrap_or(false)
+    }
+
+    pub fn unresolved(&self) -> Vec<u16> {
+        self.blocks.values().filter(|block| block.unresolved).map(|block| block.end().wrapping_sub(2)).collect()
+    }
+
+    // Ranges of bytes that were never reached as code, first and last address
+    pub fn data_ranges(&self) -> Vec<(u16, u16)> {
+        let mut ranges = Vec::new();
+        let mut range_start = None;
+        for (offset, is_code) in self.code.iter().chain(std::iter::once(&true)).enumerate() {
+            let address = self.start as usize + offset;
+            match (is_code, range_start) {
+                (false, None) => range_start = Some(address),
+                (true, Some(from)) => {
+                    ranges.push((from as u16, (address - 1) as u16));
+                    range_start = None;
+                },
+                _ => {}
+            }
+        }
+        ranges
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        writeln!(report, "{} blocks, {} edges, {} subroutines", self.blocks.len(), self.edges.len(),
+                 self.subroutines.len()).unwrap();
+        writeln!(report, "\nSubroutines").unwrap();
+        for subroutine in &self.subroutines {
+            writeln!(report, "  {:#05X} {:>4} blocks", subroutine.entry, subroutine.blocks.len()).unwrap();
+        }
+        writeln!(report, "\nUnresolved jumps").unwrap();
+        let unresolved = self.unresolved();
+        if unresolved.is_empty() {
+            writeln!(report, "  none").unwrap();
+        }
+        for address in unresolved {
+            writeln!(report, "  {:#05X}", address).unwrap();
+        }
+        writeln!(report, "\nData").unwrap();
+        for (first, last) in self.data_ranges() {
+            let referenced = self.data_references.range(first..=last).count();
+            writeln!(report, "  {:#05X}-{:#05X} {:>5} bytes {:>3} references", first, last, last - first + 1, referenced).unwrap();
+        }
+        report
+    }
+
+    // Graphviz DOT with one node per block. Subroutine entries are bold, blocks ending in an unresolved jump
+    // red, calls dashed and taken skips dotted.
+    pub fn to_dot(&self) -> String {
+        let entries: HashSet<u16> = self.subroutines.iter().map(|subroutine| subroutine.entry).collect();
+        let mut dot = String::from("digraph cfg {\n    node [shape=box fontname=\"monospace\"];\n");
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (address, opcode, ins) in &block.instructions {
+                write!(label, "{:#05X}  {:04X}  {}\\l", address, opcode, ins.name()).unwrap();
+            }
+            let mut style = Vec::new();
+            if entries.contains(&block.start) {
+                style.push("style=bold");
+            }
+            if block.unresolved {
+                style.push("color=red");
+            }
+            let style: String = style.iter().map(|attribute| format!(" {}", attribute)).collect();
+            writeln!(dot, "    b{:03X} [label=\"{}\"{}];", block.start, label, style).unwrap();
+        }
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::FallThrough | EdgeKind::Jump => "",
+                EdgeKind::Call => " [style=dashed]",
+                EdgeKind::Skip => " [style=dotted]"
+            };
+            writeln!(dot, "    b{:03X} -> b{:03X}{};", edge.from, edge.to, style).unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 0x200: call 0x20A, then skip on V0 == 0 over a jump to the data, loop forever. 0x20A returns.
+    static ROM: [u8; 14] = [
+        0x22, 0x0A,     // 0x200 CALL 0x20A
+        0x30, 0x00,     // 0x202 SE V0, 0
+        0xA2, 0x0C,     // 0x204 LD I, 0x20C
+        0x12, 0x06,     // 0x206 JP 0x206
+        0xFF, 0xFF,     // 0x208 data
+        0x00, 0xEE,     // 0x20A RET
+        0x12, 0x34      // 0x20C data
+    ];
+
+    #[test]
+    fn test_blocks() {
+        let graph = ControlFlowGraph::build(&ROM, 0x200);
+        let starts: Vec<u16> = graph.blocks.keys().copied().collect();
+        assert_eq!(starts, vec![0x200, 0x202, 0x204, 0x206, 0x20A]);
+        assert_eq!(graph.blocks[&0x204].end(), 0x206);
+    }
+
+    #[test]
+    fn test_edges() {
+        let graph = ControlFlowGraph::build(&ROM, 0x200);
+        assert!(graph.edges.contains(&Edge {from: 0x200, to: 0x20A, kind: EdgeKind::Call}));
+        assert!(graph.edges.contains(&Edge {from: 0x200, to: 0x202, kind: EdgeKind::FallThrough}));
+        assert!(graph.edges.contains(&Edge {from: 0x202, to: 0x206, kind: EdgeKind::Skip}));
+        assert!(graph.edges.contains(&Edge {from: 0x206, to: 0x206, kind: EdgeKind::Jump}));
+        assert!(!graph.edges.iter().any(|edge| edge.from == 0x20A));
+    }
+
+    #[test]
+    fn test_subroutines() {
+        let graph = ControlFlowGraph::build(&ROM, 0x200);
+        assert_eq!(graph.subroutines, vec![
+            Subroutine {entry: 0x200, blocks: vec![0x200, 0x202, 0x204, 0x206]},
+            Subroutine {entry: 0x20A, blocks: vec![0x20A]}
+        ]);
+    }
+
+    #[test]
+    fn test_code_and_data() {
+        let graph = ControlFlowGraph::build(&ROM, 0x200);
+        assert!(graph.is_code(0x206));
+        assert!(!graph.is_code(0x208));
+        assert_eq!(graph.data_ranges(), vec![(0x208, 0x209), (0x20C, 0x20D)]);
+        assert_eq!(graph.data_references.iter().copied().collect::<Vec<u16>>(), vec![0x20C]);
+    }
+
+    #[test]
+    fn test_unresolved_jump() {
+        let graph = ControlFlowGraph::build(&[0x60, 0x02, 0xB2, 0x04, 0x12, 0x04], 0x200);
+        assert_eq!(graph.unresolved(), vec![0x202]);
+        assert!(!graph.is_code(0x204));
+        assert!(graph.to_dot().contains("b200 [label=\"0x200  6002  LoadRegister\\l0x202  B204  JumpToLocationAndOffset0\\l\" style=bold color=red];"));
+    }
+
+    #[test]
+    fn test_target_outside_rom() {
+        let graph = ControlFlowGraph::build(&[0x13, 0x00, 0x01], 0x200);
+        assert_eq!(graph.blocks.len(), 1);
+        assert!(graph.edges.is_empty());
+        assert_eq!(graph.data_ranges(), vec![(0x202, 0x202)]);
+    }
+}
@@ -3,32 +3,44 @@ use crate::input::Input;
 use crate::graphics::Graphics;
 use crate::sound::Sound;
 
-pub struct Bus {
-    pub memory: Memory,
+// Components are generic so tests can swap in recording or fault injecting implementations. The defaults
+// are the real components.
+pub struct Bus<M = Memory, I = Input, G = Graphics, S = Sound> {
+    pub memory: M,
 
-    pub input: Input,
+    pub input: I,
 
-    pub graphics: Graphics,
+    pub graphics: G,
 
-    pub sound: Sound,
+    pub sound: S,
 }
 
 impl Bus {
 
-    pub fn mock() -> Bus {
-        Bus {
-            memory: Memory::mock(),
-            input: Input::mock(),
-            graphics: Graphics::mock(),
-            sound: Sound::mock()
-        }
-    }
     pub fn new() -> Bus {
         Bus {
             memory: Memory::new(),
             input: Input::new(),
             graphics: Graphics::new(),
-            sound: Sound::mock(),
+            sound: Sound::new(),
         }
     }
-}
\ No newline at end of file
+}
+
+impl Default for Bus {
+    fn default() -> Bus {
+        Bus::new()
+    }
+}
+
+impl<M, I, G, S> Bus<M, I, G, S> {
+
+    pub fn from_parts(memory: M, input: I, graphics: G, sound: S) -> Bus<M, I, G, S> {
+        Bus {
+            memory,
+            input,
+            graphics,
+            sound,
+        }
+    }
+}
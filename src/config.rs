@@ -0,0 +1,540 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use sdl2::controller::{Axis, Button};
+use sdl2::keyboard::{Keycode, Scancode};
+use crate::controller::{ControllerProfile, Direction};
+use crate::database::RomSettings;
+use crate::filter::FilterConfig;
+use crate::font::{Font, FontError, FontSet};
+use crate::keymap::{Keymap, Preset};
+use crate::memory::AccessPolicy;
+use crate::palette::{Palette, PaletteConfig, PaletteError};
+use crate::platform::{Platform, QuirkOverrides, Quirks};
+
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    UnknownPreset(String),
+    UnknownPlatform(String),
+    UnknownHostKey(String),
+    UnknownButton(String),
+    UnknownAxis(String),
+    UnknownFont(String),
+    Font(PathBuf, FontError),
+    Palette(PaletteError),
+    InvalidKey(u8)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "could not read config: {}", err),
+            ConfigError::Parse(err) => write!(f, "could not parse config: {}", err),
+            ConfigError::UnknownPreset(name) => write!(f, "unknown keymap preset '{}'", name),
+            ConfigError::UnknownPlatform(name) => write!(f, "unknown platform '{}'", name),
+            ConfigError::UnknownHostKey(name) => write!(f, "unknown key name '{}'", name),
+            ConfigError::UnknownButton(name) => write!(f, "unknown controller button '{}'", name),
+            ConfigError::UnknownAxis(name) => write!(f, "unknown controller axis '{}'", name),
+            ConfigError::UnknownFont(name) => write!(f, "unknown font set '{}'", name),
+            ConfigError::Font(path, err) => write!(f, "{}: {}", path.display(), err),
+            ConfigError::Palette(err) => write!(f, "{}", err),
+            ConfigError::InvalidKey(key) => write!(f, "key {:#X} is not on the hex keypad", key)
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> ConfigError {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> ConfigError {
+        ConfigError::Parse(err)
+    }
+}
+
+// Host keys are given by their SDL name. Scancodes name a physical key position ("Q" is the key
+// right of Tab on any layout), keycodes name the label printed on the key.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct KeymapConfig {
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub scancodes: HashMap<String, u8>,
+    #[serde(default)]
+    pub keycodes: HashMap<String, u8>
+}
+
+impl KeymapConfig {
+    fn apply(&self, keymap: &mut Keymap) -> Result<(), ConfigError> {
+        for (name, key) in &self.scancodes {
+            let scancode = Scancode::from_name(name).ok_or_else(|| ConfigError::UnknownHostKey(name.clone()))?;
+            keymap.bind_scancode(scancode, check_key(*key)?);
+        }
+        for (name, key) in &self.keycodes {
+            let keycode = Keycode::from_name(name).ok_or_else(|| ConfigError::UnknownHostKey(name.clone()))?;
+            keymap.bind_keycode(keycode, check_key(*key)?);
+        }
+        Ok(())
+    }
+
+    fn preset(&self) -> Result<Option<Preset>, ConfigError> {
+        match &self.preset {
+            Some(name) => Preset::from_name(name).map(Some).ok_or_else(|| ConfigError::UnknownPreset(name.clone())),
+            None => Ok(None)
+        }
+    }
+}
+
+// Buttons and axes are given by their SDL game controller name ("a", "dpup", "leftx"). Axis names are
+// followed by the direction that presses the key, "leftx-" for left and "leftx+" for right.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ControllerConfig {
+    #[serde(default)]
+    pub buttons: HashMap<String, u8>,
+    #[serde(default)]
+    pub axes: HashMap<String, u8>,
+    pub deadzone: Option<i16>
+}
+
+impl ControllerConfig {
+    fn apply(&self, profile: &mut ControllerProfile) -> Result<(), ConfigError> {
+        for (name, key) in &self.buttons {
+            let button = Button::from_string(name).ok_or_else(|| ConfigError::UnknownButton(name.clone()))?;
+            profile.bind_button(button, check_key(*key)?);
+        }
+        for (name, key) in &self.axes {
+            let (axis, direction) = parse_axis(name).ok_or_else(|| ConfigError::UnknownAxis(name.clone()))?;
+            profile.bind_axis(axis, direction, check_key(*key)?);
+        }
+        if let Some(deadzone) = self.deadzone {
+            profile.deadzone = deadzone.max(0);
+        }
+        Ok(())
+    }
+}
+
+// A built-in set by name ("vip", "chip48", "schip", "octo", "dream6800") or raw glyph data from a file, and
+// the address it is loaded at. Without either the platform's own set is used.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct FontConfig {
+    pub set: Option<String>,
+    pub file: Option<PathBuf>,
+    pub address: Option<u16>
+}
+
+impl FontConfig {
+    fn has_glyphs(&self) -> bool {
+        self.set.is_some() || self.file.is_some()
+    }
+
+    fn font(&self, platform: Platform) -> Result<Font, ConfigError> {
+        if let Some(path) = &self.file {
+            return Font::load(path).map_err(|err| ConfigError::Font(path.clone(), err));
+        }
+        let set = match &self.set {
+            Some(name) => FontSet::from_name(name).ok_or_else(|| ConfigError::UnknownFont(name.clone()))?,
+            None => FontSet::for_platform(platform)
+        };
+        Ok(Font::new(set))
+    }
+}
+
+// Overrides for a single ROM, keyed by the ROM's file name without extension or by its SHA-1. The
+// platform is a name ("chip8") or a database platform id ("superchip1"), ids also select that platform's
+// quirks.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct RomConfig {
+    pub keymap: Option<KeymapConfig>,
+    pub controller: Option<ControllerConfig>,
+    pub platform: Option<String>,
+    pub quirks: Option<QuirkOverrides>,
+    pub tickrate: Option<usize>,
+    pub font: Option<FontConfig>,
+    pub filter: Option<FilterConfig>,
+    pub palette: Option<PaletteConfig>
+}
+
+impl RomConfig {
+    fn apply(&self, settings: &mut RomSettings) -> Result<(), ConfigError> {
+        if let Some(name) = &self.platform {
+            match (Platform::from_platform_id(name), Quirks::for_platform_id(name)) {
+                (Some(platform), Some(quirks)) => {
+                    settings.platform = platform;
+                    settings.quirks = quirks;
+                },
+                _ => settings.platform = Platform::from_name(name).ok_or_else(|| ConfigError::UnknownPlatform(name.clone()))?
+            }
+        }
+        if let Some(quirks) = &self.quirks {
+            settings.quirks.apply(quirks);
+        }
+        if self.tickrate.is_some() {
+            settings.instructions_per_frame = self.tickrate;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub keymap: KeymapConfig,
+    #[serde(default)]
+    pub controller: ControllerConfig,
+    #[serde(default)]
+    pub roms: HashMap<String, RomConfig>,
+    pub database: Option<PathBuf>,      // Used instead of the bundled ROM database
+    #[serde(default)]
+    pub memory: AccessPolicy,
+    #[serde(default)]
+    pub font: FontConfig,
+    #[serde(default)]
+    pub filter: FilterConfig,
+    pub palette: Option<PaletteConfig>
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        Config::parse(&fs::read_to_string(path)?)
+    }
+
+    // Loads the config from the default location, falling back to the defaults if there is none
+    pub fn load_default() -> Result<Config, ConfigError> {
+        match Config::default_path() {
+            Some(path) if path.exists() => Config::load(&path),
+            _ => Ok(Config::default())
+        }
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("chip8").join("config.toml"))
+    }
+
+    pub fn rom(&self, rom_name: &str) -> Option<&RomConfig> {
+        self.roms.get(rom_name)
+    }
+
+    // The key of the ROM's section, a section for the hash wins over one for the name
+    pub fn rom_key<'a>(&self, rom_name: &'a str, sha1: &'a str) -> &'a str {
+        if self.roms.contains_key(sha1) {sha1} else {rom_name}
+    }
+
+    // Settings found in the ROM database, or the defaults for an unknown ROM, with the overrides of the
+    // ROM's section applied
+    pub fn rom_settings_for(&self, rom_name: &str, found: Option<RomSettings>) -> Result<RomSettings, ConfigError> {
+        let mut settings = found.unwrap_or_else(|| RomSettings {
+            title: rom_name.to_string(),
+            authors: Vec::new(),
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            instructions_per_frame: None,
+            keys: HashMap::new(),
+            colors: None
+        });
+        if let Some(rom) = self.rom(rom_name) {
+            rom.apply(&mut settings)?;
+        }
+        Ok(settings)
+    }
+
+    // A preset in the ROM section replaces the global preset. The game controls from the ROM database, the
+    // global bindings and then the ROM's bindings are added on top.
+    pub fn keymap_for(&self, rom_name: &str, game_keys: &HashMap<String, u8>) -> Result<Keymap, ConfigError> {
+        let rom_keymap = self.rom(rom_name).and_then(|rom| rom.keymap.as_ref());
+        let rom_preset = match rom_keymap {
+            Some(keymap) => keymap.preset()?,
+            None => None
+        };
+        let preset = match rom_preset {
+            Some(preset) => preset,
+            None => self.keymap.preset()?.unwrap_or(Preset::Qwerty)
+        };
+        let mut keymap = Keymap::from_preset(preset);
+        keymap.bind_game_keys(game_keys);
+        self.keymap.apply(&mut keymap)?;
+        if let Some(rom_keymap) = rom_keymap {
+            rom_keymap.apply(&mut keymap)?;
+        }
+        Ok(keymap)
+    }
+
+    // Glyphs and address in the ROM section each replace the global ones
+    pub fn font_for(&self, rom_name: &str, platform: Platform) -> Result<Font, ConfigError> {
+        let rom_font = self.rom(rom_name).and_then(|rom| rom.font.as_ref());
+        let mut font = match rom_font {
+            Some(rom_font) if rom_font.has_glyphs() => rom_font.font(platform)?,
+            _ => self.font.font(platform)?
+        };
+        if let Some(address) = rom_font.and_then(|rom_font| rom_font.address).or(self.font.address) {
+            font.address = address;
+        }
+        Ok(font)
+    }
+
+    // The ROM's section wins over the colors from the ROM database, which win over the global palette
+    pub fn palette_for(&self, rom_name: &str, database_colors: Option<&Vec<String>>) -> Result<Palette, ConfigError> {
+        let config = self.rom(rom_name).and_then(|rom| rom.palette.clone())
+            .or_else(|| database_colors.map(|colors| PaletteConfig::Colors(colors.clone())))
+            .or_else(|| self.palette.clone());
+        match config {
+            Some(config) => config.palette().map_err(ConfigError::Palette),
+            None => Ok(Palette::default())
+        }
+    }
+
+    pub fn filter_for(&self, rom_name: &str) -> FilterConfig {
+        self.rom(rom_name).and_then(|rom| rom.filter).unwrap_or(self.filter)
+    }
+
+    // The game controls from the ROM database and then the controller bindings in the config are added on
+    // top of the default profile
+    pub fn controller_profile_for(&self, rom_name: &str, game_keys: &HashMap<String, u8>)
+                                  -> Result<ControllerProfile, ConfigError> {
+        let mut profile = ControllerProfile::default();
+        profile.bind_game_keys(game_keys);
+        self.controller.apply(&mut profile)?;
+        if let Some(controller) = self.rom(rom_name).and_then(|rom| rom.controller.as_ref()) {
+            controller.apply(&mut profile)?;
+        }
+        Ok(profile)
+    }
+}
+
+fn parse_axis(name: &str) -> Option<(Axis, Direction)> {
+    let direction = match name.chars().last()? {
+        '-' => Direction::Negative,
+        '+' => Direction::Positive,
+        _ => return None
+    };
+    Axis::from_string(&name[..name.len() - 1]).map(|axis| (axis, direction))
+}
+
+fn check_key(key: u8) -> Result<u8, ConfigError> {
+    if key < 16 {
+        Ok(key)
+    } else {
+        Err(ConfigError::InvalidKey(key))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::Access;
+
+    static CONFIG: &str = r#"
+        [keymap]
+        preset = "cosmac"
+
+        [roms.pong.keymap]
+        preset = "numpad"
+
+        [roms.invaders.keymap]
+    "#;
+
+    #[test]
+    fn test_empty_config() {
+        let config = Config::parse("").unwrap();
+        let keymap = config.keymap_for("anything", &HashMap::new()).unwrap();
+        assert_eq!(keymap.lookup(Some(Scancode::Q), None), Some(0x4));
+    }
+
+    #[test]
+    fn test_global_preset() {
+        let config = Config::parse(CONFIG).unwrap();
+        let keymap = config.keymap_for("tetris", &HashMap::new()).unwrap();
+        assert_eq!(keymap.lookup(Some(Scancode::A), None), Some(0xA));
+    }
+
+    #[test]
+    fn test_rom_preset_override() {
+        let config = Config::parse(CONFIG).unwrap();
+        let keymap = config.keymap_for("pong", &HashMap::new()).unwrap();
+        assert_eq!(keymap.lookup(Some(Scancode::Kp8), None), Some(0x2));
+        assert_eq!(keymap.lookup(Some(Scancode::A), None), None);
+    }
+
+    #[test]
+    fn test_rom_preset_keeps_global_bindings() {
+        let config = Config::parse(r#"
+            [keymap]
+            preset = "cosmac"
+            scancodes = { "Space" = 0x5, "X" = 0x0 }
+            [roms.pong.keymap]
+            preset = "numpad"
+            scancodes = { "X" = 0x1 }
+        "#).unwrap();
+        let keymap = config.keymap_for("pong", &HashMap::new()).unwrap();
+        assert_eq!(keymap.lookup(Some(Scancode::Kp8), None), Some(0x2));
+        assert_eq!(keymap.lookup(Scancode::from_name("Space"), None), Some(0x5));
+        assert_eq!(keymap.lookup(Scancode::from_name("X"), None), Some(0x1));
+        assert_eq!(keymap.lookup(Some(Scancode::A), None), None);
+    }
+
+    #[test]
+    fn test_rom_without_preset_keeps_global() {
+        let config = Config::parse(CONFIG).unwrap();
+        let keymap = config.keymap_for("invaders", &HashMap::new()).unwrap();
+        assert_eq!(keymap.lookup(Some(Scancode::A), None), Some(0xA));
+    }
+
+    #[test]
+    fn test_game_keys() {
+        let config = Config::parse("[roms.pong.keymap]\npreset = \"numpad\"").unwrap();
+        let keys: HashMap<String, u8> = vec![("up".to_string(), 0x1), ("down".to_string(), 0x4)].into_iter().collect();
+        let keymap = config.keymap_for("pong", &keys).unwrap();
+        assert_eq!(keymap.lookup(Some(Scancode::Up), None), Some(0x1));
+        assert_eq!(keymap.lookup(Some(Scancode::Down), None), Some(0x4));
+        assert_eq!(keymap.lookup(Some(Scancode::Kp8), None), Some(0x2));
+    }
+
+    #[test]
+    fn test_unknown_preset() {
+        let config = Config::parse("[keymap]\npreset = \"dvorak\"").unwrap();
+        match config.keymap_for("pong", &HashMap::new()) {
+            Err(ConfigError::UnknownPreset(name)) => assert_eq!(name, "dvorak"),
+            _ => panic!("expected an unknown preset error")
+        }
+    }
+
+    #[test]
+    fn test_controller_deadzone_override() {
+        let config = Config::parse("[controller]\ndeadzone = 100\n[roms.pong.controller]\ndeadzone = 200").unwrap();
+        assert_eq!(config.controller_profile_for("tetris", &HashMap::new()).unwrap().deadzone, 100);
+        assert_eq!(config.controller_profile_for("pong", &HashMap::new()).unwrap().deadzone, 200);
+        let config = Config::parse("[controller]\ndeadzone = -32768").unwrap();
+        let profile = config.controller_profile_for("pong", &HashMap::new()).unwrap();
+        assert_eq!(profile.deadzone, 0);
+        assert_eq!(profile.key_for_axis(&Axis::LeftX, i16::MIN), Some(0x7));
+    }
+
+    #[test]
+    fn test_rom_settings_override() {
+        let config = Config::parse(r#"
+            [roms.pong]
+            platform = "superchip1"
+            tickrate = 20
+            [roms.pong.quirks]
+            memory_increment_by_x = false
+        "#).unwrap();
+        let settings = config.rom_settings_for("pong", None).unwrap();
+        assert_eq!(settings.platform, Platform::SuperChip);
+        assert!(settings.quirks.shift);
+        assert!(!settings.quirks.memory_increment_by_x);
+        assert_eq!(settings.instructions_per_frame, Some(20));
+        assert_eq!(config.rom_settings_for("tetris", None).unwrap().quirks, Quirks::default());
+    }
+
+    #[test]
+    fn test_rom_key() {
+        let config = Config::parse("[roms.a9993e364706816aba3e25717850c26c9cd0d89d]\ntickrate = 5").unwrap();
+        assert_eq!(config.rom_key("abc", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+                   "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(config.rom_key("abc", "0000"), "abc");
+    }
+
+    #[test]
+    fn test_memory_policy() {
+        let config = Config::parse("[memory]\nreserved_writes = \"warn\"\nout_of_range = \"trap\"").unwrap();
+        assert_eq!(config.memory, AccessPolicy{reserved_writes: Access::Warn, out_of_range: Access::Trap});
+        assert_eq!(Config::parse("").unwrap().memory, AccessPolicy::default());
+        assert!(Config::parse("[memory]\nreserved_writes = \"ignore\"").is_err());
+    }
+
+    #[test]
+    fn test_font() {
+        let config = Config::parse(r#"
+            [font]
+            set = "vip"
+            address = 0x50
+            [roms.pong.font]
+            set = "octo"
+            [roms.tetris.font]
+            address = 0x0
+        "#).unwrap();
+        assert_eq!(config.font_for("invaders", Platform::Chip8).unwrap().small, Font::new(FontSet::CosmacVip).small);
+        let font = config.font_for("pong", Platform::Chip8).unwrap();
+        assert_eq!((font.large.len(), font.address), (160, 0x50));
+        let font = config.font_for("tetris", Platform::Chip8).unwrap();
+        assert_eq!((font.small[5], font.address), (0x60, 0x0));
+        assert_eq!(Config::parse("").unwrap().font_for("pong", Platform::SuperChip).unwrap(), Font::new(FontSet::SuperChip));
+        let config = Config::parse("[font]\nset = \"megachip\"").unwrap();
+        assert!(matches!(config.font_for("pong", Platform::Chip8), Err(ConfigError::UnknownFont(_))));
+        let config = Config::parse("[font]\nfile = \"/nonexistent/font.bin\"").unwrap();
+        assert!(matches!(config.font_for("pong", Platform::Chip8), Err(ConfigError::Font(..))));
+    }
+
+    #[test]
+    fn test_palette() {
+        let config = Config::parse(r##"
+            palette = "amber"
+            [roms.pong]
+            palette = ["#000000", "#FF0000"]
+            [roms.tetris]
+            palette = "vga"
+        "##).unwrap();
+        let database = vec!["#000000".to_string(), "#00FF00".to_string()];
+        assert_eq!(config.palette_for("invaders", None).unwrap(), Palette::from_name("amber").unwrap());
+        assert_eq!(config.palette_for("invaders", Some(&database)).unwrap().color(1), [0x00, 0xFF, 0x00]);
+        assert_eq!(config.palette_for("pong", Some(&database)).unwrap().color(1), [0xFF, 0x00, 0x00]);
+        assert!(matches!(config.palette_for("tetris", None), Err(ConfigError::Palette(PaletteError::Unknown(_)))));
+        assert_eq!(Config::parse("").unwrap().palette_for("pong", None).unwrap(), Palette::default());
+    }
+
+    #[test]
+    fn test_filter() {
+        let config = Config::parse(r#"
+            [filter]
+            mode = "phosphor"
+            decay = 0.25
+            [roms.pong.filter]
+            mode = "blend"
+            [roms.tetris.filter]
+            mode = "none"
+        "#).unwrap();
+        assert_eq!(config.filter_for("invaders"), FilterConfig::Phosphor{decay: 0.25});
+        assert_eq!(config.filter_for("pong"), FilterConfig::Blend{frames: 3});
+        assert_eq!(config.filter_for("tetris"), FilterConfig::None);
+        assert_eq!(Config::parse("").unwrap().filter_for("pong"), FilterConfig::None);
+        assert!(Config::parse("[filter]\nmode = \"bloom\"").is_err());
+    }
+
+    #[test]
+    fn test_unknown_platform() {
+        let config = Config::parse("[roms.pong]\nplatform = \"megachip\"").unwrap();
+        match config.rom_settings_for("pong", None) {
+            Err(ConfigError::UnknownPlatform(name)) => assert_eq!(name, "megachip"),
+            _ => panic!("expected an unknown platform error")
+        }
+    }
+
+    #[test]
+    fn test_parse_axis_without_direction() {
+        assert_eq!(parse_axis("leftx"), None);
+        assert_eq!(parse_axis(""), None);
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        assert!(check_key(0xF).is_ok());
+        match check_key(0x10) {
+            Err(ConfigError::InvalidKey(key)) => assert_eq!(key, 0x10),
+            _ => panic!("expected an invalid key error")
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::bus::Bus;
+use crate::cpu::CPU;
+use crate::framebuffer::Framebuffer;
+use crate::graphics::Graphics;
+use crate::input::{Input, InputDevice};
+use crate::instructions::Instruction;
+use crate::memory::{AccessPolicy, Memory, MemoryDevice, MemoryFault};
+use crate::platform::{Platform, QuirkOverrides, Quirks};
+use crate::rom::RomError;
+use crate::sound::Sound;
+
+
+const INSTRUCTIONS_PER_FRAME: usize = 10;
+
+#[derive(Debug)]
+pub enum ConformanceError {
+    Io(PathBuf, io::Error),
+    Parse(toml::de::Error),
+    Rom(RomError),
+    UnknownPlatform(String),
+    InvalidKey(u8),
+    InvalidGolden(PathBuf, String),
+    Mismatch(String)
+}
+
+impl fmt::Display for ConformanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConformanceError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            ConformanceError::Parse(err) => write!(f, "could not parse test case: {}", err),
+            ConformanceError::Rom(err) => write!(f, "{}", err),
+            ConformanceError::UnknownPlatform(name) => write!(f, "unknown platform '{}'", name),
+            ConformanceError::InvalidKey(key) => write!(f, "key {:#X} is not on the hex keypad", key),
+            ConformanceError::InvalidGolden(path, err) => write!(f, "{}: {}", path.display(), err),
+            ConformanceError::Mismatch(diff) => write!(f, "framebuffer does not match the golden image\n{}", diff)
+        }
+    }
+}
+
+impl From<toml::de::Error> for ConformanceError {
+    fn from(err: toml::de::Error) -> ConformanceError {
+        ConformanceError::Parse(err)
+    }
+}
+
+impl From<RomError> for ConformanceError {
+    fn from(err: RomError) -> ConformanceError {
+        ConformanceError::Rom(err)
+    }
+}
+
+// When to stop before the frame limit
+#[derive(Debug, PartialEq, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Halt {
+    #[default]
+    Never,
+    Loop,       // The next instruction jumps to itself, the way most test ROMs end
+    Key         // Waiting for a key after the last scripted key event
+}
+
+// Pressed or released at the start of a frame
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+pub struct KeyEvent {
+    pub frame: usize,
+    pub key: u8,
+    #[serde(default = "pressed")]
+    pub pressed: bool
+}
+
+fn pressed() -> bool {
+    true
+}
+
+// A test case, read from a TOML file next to the ROM and golden image. Paths are relative to that file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Case {
+    pub rom: PathBuf,
+    pub golden: PathBuf,            // Text or PBM
+    pub frames: usize,              // Upper limit
+    pub instructions_per_frame: Option<usize>,
+    pub platform: Option<String>,   // Platform name or CHIP-8 database platform id
+    pub quirks: Option<QuirkOverrides>,
+    #[serde(default)]
+    pub keys: Vec<KeyEvent>,
+    #[serde(default)]
+    pub halt: Halt,
+    #[serde(default)]
+    pub memory: AccessPolicy
+}
+
+pub struct Outcome {
+    pub frames: usize,              // Frames run before halting
+    pub halted: bool,
+    pub fault: Option<MemoryFault>, // Trapped by the memory access policy, which also halts
+    pub framebuffer: Framebuffer
+}
+
+impl Case {
+    pub fn parse(text: &str) -> Result<Case, ConformanceError> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Case, ConformanceError> {
+        let text = fs::read_to_string(path).map_err(|err| ConformanceError::Io(path.to_path_buf(), err))?;
+        let mut case = Case::parse(&text)?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        case.rom = directory.join(&case.rom);
+        case.golden = directory.join(&case.golden);
+        Ok(case)
+    }
+
+    fn platform(&self) -> Result<(Platform, Quirks), ConformanceError> {
+        let (platform, mut quirks) = match &self.platform {
+            None => (Platform::default(), Quirks::default()),
+            Some(name) => match (Platform::from_platform_id(name), Quirks::for_platform_id(name)) {
+                (Some(platform), Some(quirks)) => (platform, quirks),
+                _ => (Platform::from_name(name).ok_or_else(|| ConformanceError::UnknownPlatform(name.clone()))?,
+                      Quirks::default())
+            }
+        };
+        if let Some(overrides) = &self.quirks {
+            quirks.apply(overrides);
+        }
+        Ok((platform, quirks))
+    }
+
+    fn is_halted(&self, cpu: &CPU, frame: usize) -> bool {
+        if cpu.fault().is_some() {
+            return true;
+        }
+        match (self.halt, cpu.bus.memory.peek_instruction(&cpu.PC)) {
+            (Halt::Loop, Instruction::Jump{address}) => address == cpu.PC,
+            (Halt::Key, Instruction::WaitForKeyPressAndStoreValue{..}) =>
+                cpu.bus.input.get_pressed_key().is_none() && self.keys.iter().all(|event| event.frame < frame),
+            _ => false
+        }
+    }
+
+    pub fn run(&self, rom: &[u8]) -> Result<Outcome, ConformanceError> {
+        if let Some(event) = self.keys.iter().find(|event| event.key > 0xF) {
+            return Err(ConformanceError::InvalidKey(event.key));
+        }
+        let (platform, quirks) = self.platform()?;
+        let mut memory = Memory::with_platform(platform);
+        memory.load_rom(rom)?;
+        memory.policy = self.memory;
+        let mut cpu = CPU::new(Bus::from_parts(memory, Input::new(), Graphics::new(), Sound::new()));
+        cpu.quirks = quirks;
+        cpu.set_pc(&platform.program_start());
+        let instructions = self.instructions_per_frame.unwrap_or(INSTRUCTIONS_PER_FRAME);
+        let mut frame = 0;
+        while frame < self.frames && !self.is_halted(&cpu, frame) {
+            for event in self.keys.iter().filter(|event| event.frame == frame) {
+                if event.pressed {
+                    cpu.bus.input.press(&event.key);
+                } else {
+                    cpu.bus.input.release(&event.key);
+                }
+            }
+            cpu.run_frame(instructions);
+            frame += 1;
+        }
+        Ok(Outcome {
+            frames: frame,
+            halted: frame < self.frames,
+            fault: cpu.fault(),
+            framebuffer: Framebuffer::from_graphics(&cpu.bus.graphics)
+        })
+    }
+
+    // Runs the ROM and compares the result with the golden image. With update set the golden image is
+    // (re)written instead, as PBM if its name ends in .pbm.
+    pub fn check(&self, update: bool) -> Result<Outcome, ConformanceError> {
+        let rom = fs::read(&self.rom).map_err(|err| ConformanceError::Io(self.rom.clone(), err))?;
+        let outcome = self.run(&rom)?;
+        if update {
+            let golden = if self.golden.extension().is_some_and(|extension| extension == "pbm") {
+                outcome.framebuffer.to_pbm()
+            } else {
+                outcome.framebuffer.to_text()
+            };
+            fs::write(&self.golden, golden).map_err(|err| ConformanceError::Io(self.golden.clone(), err))?;
+            return Ok(outcome);
+        }
+        let data = fs::read(&self.golden).map_err(|err| ConformanceError::Io(self.golden.clone(), err))?;
+        let expected = Framebuffer::parse(&data).map_err(|err| ConformanceError::InvalidGolden(self.golden.clone(), err))?;
+        match expected.diff(&outcome.framebuffer) {
+            Some(diff) => Err(ConformanceError::Mismatch(diff)),
+            None => Ok(outcome)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn get_case(text: &str) -> Case {
+        Case::parse(&format!("rom = \"test.ch8\"\ngolden = \"test.txt\"\n{}", text)).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let case = get_case("frames = 30\nplatform = \"superchip\"\nhalt = \"key\"\nkeys = [{frame = 2, key = 10}, \
+                             {frame = 3, key = 10, pressed = false}]\n[quirks]\nwrap = true\n");
+        assert_eq!(case.frames, 30);
+        assert_eq!(case.halt, Halt::Key);
+        assert_eq!(case.keys, vec![KeyEvent{frame: 2, key: 10, pressed: true}, KeyEvent{frame: 3, key: 10, pressed: false}]);
+        let (platform, quirks) = case.platform().unwrap();
+        assert_eq!(platform, Platform::SuperChip);
+        assert!(quirks.shift && quirks.wrap);
+    }
+
+    #[test]
+    fn test_platform_name() {
+        assert_eq!(get_case("frames = 1\nplatform = \"xo-chip\"").platform().unwrap(), (Platform::XoChip, Quirks::default()));
+        assert!(matches!(get_case("frames = 1\nplatform = \"megachip\"").platform(), Err(ConformanceError::UnknownPlatform(_))));
+    }
+
+    #[test]
+    fn test_run() {
+        // LD V0, 2; LD F, V0; DRW V1, V1, 5; JP 0x206
+        let rom = [0x60, 0x02, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06];
+        let outcome = get_case("frames = 10").run(&rom).unwrap();
+        assert_eq!((outcome.frames, outcome.halted), (10, false));
+        let rows: Vec<String> = outcome.framebuffer.to_text().lines().take(2).map(|row| row[..6].to_string()).collect();
+        assert_eq!(rows, vec!["####..", "...#.."]);
+        let outcome = get_case("frames = 10\nhalt = \"loop\"").run(&rom).unwrap();
+        assert_eq!((outcome.frames, outcome.halted), (1, true));
+    }
+
+    #[test]
+    fn test_run_memory_fault() {
+        // LD I, 0x100; LD [I], V0; JP 0x204
+        let rom = [0xA1, 0x00, 0xF0, 0x55, 0x12, 0x04];
+        let outcome = get_case("frames = 10\n[memory]\nreserved_writes = \"trap\"").run(&rom).unwrap();
+        assert_eq!((outcome.frames, outcome.halted), (1, true));
+        assert_eq!(outcome.fault, Some(MemoryFault::ReservedWrite{address: 0x100}));
+    }
+
+    #[test]
+    fn test_run_keys() {
+        // LD V0, K; CLS; LD F, V0; DRW V1, V1, 5; JP 0x200
+        let rom = [0xF0, 0x0A, 0x00, 0xE0, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x00];
+        let outcome = get_case("frames = 10\nhalt = \"key\"\nkeys = [{frame = 3, key = 1}, {frame = 4, key = 1, pressed = false}]")
+            .run(&rom).unwrap();
+        assert_eq!((outcome.frames, outcome.halted), (5, true));
+        assert!(outcome.framebuffer.get_pixel(2, 0));
+        let case = get_case("frames = 10\nkeys = [{frame = 3, key = 16}]");
+        assert!(matches!(case.run(&rom), Err(ConformanceError::InvalidKey(16))));
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use crate::input::{Input, KEY_COUNT};
+
+
+const DEFAULT_DEADZONE: i16 = 8000;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Direction {
+    Negative,   // Left or up
+    Positive    // Right or down
+}
+
+#[derive(Debug, Clone)]
+pub struct ControllerProfile {
+    pub buttons: HashMap<Button, u8>,
+    pub axes: HashMap<(Axis, Direction), u8>,
+    pub deadzone: i16
+}
+
+impl ControllerProfile {
+    pub fn new() -> ControllerProfile {
+        ControllerProfile {
+            buttons: HashMap::new(),
+            axes: HashMap::new(),
+            deadzone: DEFAULT_DEADZONE
+        }
+    }
+
+    pub fn bind_button(&mut self, button: Button, key: u8) {
+        if (key as usize) < KEY_COUNT {
+            self.buttons.insert(button, key);
+        }
+    }
+
+    pub fn bind_axis(&mut self, axis: Axis, direction: Direction, key: u8) {
+        if (key as usize) < KEY_COUNT {
+            self.axes.insert((axis, direction), key);
+        }
+    }
+
+    // Binds the game controls named by the ROM database ("up", "a") to the D-pad, left stick and face buttons
+    pub fn bind_game_keys(&mut self, keys: &HashMap<String, u8>) {
+        for (name, key) in keys {
+            match name.as_str() {
+                "up" => {
+                    self.bind_button(Button::DPadUp, *key);
+                    self.bind_axis(Axis::LeftY, Direction::Negative, *key);
+                },
+                "down" => {
+                    self.bind_button(Button::DPadDown, *key);
+                    self.bind_axis(Axis::LeftY, Direction::Positive, *key);
+                },
+                "left" => {
+                    self.bind_button(Button::DPadLeft, *key);
+                    self.bind_axis(Axis::LeftX, Direction::Negative, *key);
+                },
+                "right" => {
+                    self.bind_button(Button::DPadRight, *key);
+                    self.bind_axis(Axis::LeftX, Direction::Positive, *key);
+                },
+                "a" => self.bind_button(Button::A, *key),
+                "b" => self.bind_button(Button::B, *key),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn key_for_button(&self, button: &Button) -> Option<u8> {
+        self.buttons.get(button).copied()
+    }
+
+    pub fn key_for_axis(&self, axis: &Axis, value: i16) -> Option<u8> {
+        let deadzone = self.deadzone.max(0);
+        let direction = if value < -deadzone {
+            Direction::Negative
+        } else if value > deadzone {
+            Direction::Positive
+        } else {
+            return None;
+        };
+        self.axes.get(&(*axis, direction)).copied()
+    }
+}
+
+// D-pad and left stick on W/A/S/D (5/7/8/9), face buttons on the keys around them. This is what most
+// modern CHIP-8 games use, older games are better served by a per-ROM profile.
+impl Default for ControllerProfile {
+    fn default() -> ControllerProfile {
+        let mut profile = ControllerProfile::new();
+        profile.bind_button(Button::DPadUp, 0x5);
+        profile.bind_button(Button::DPadLeft, 0x7);
+        profile.bind_button(Button::DPadDown, 0x8);
+        profile.bind_button(Button::DPadRight, 0x9);
+        profile.bind_button(Button::A, 0x6);
+        profile.bind_button(Button::B, 0x4);
+        profile.bind_button(Button::X, 0xA);
+        profile.bind_button(Button::Y, 0xB);
+        profile.bind_button(Button::Start, 0xF);
+        profile.bind_axis(Axis::LeftY, Direction::Negative, 0x5);
+        profile.bind_axis(Axis::LeftX, Direction::Negative, 0x7);
+        profile.bind_axis(Axis::LeftY, Direction::Positive, 0x8);
+        profile.bind_axis(Axis::LeftX, Direction::Positive, 0x9);
+        profile
+    }
+}
+
+// Keys currently held by a single controller, so moving a stick from one direction to the other releases
+// the key of the first direction and unplugging the controller releases everything it held
+struct ControllerState {
+    button_keys: HashMap<Button, u8>,
+    axis_keys: HashMap<Axis, u8>
+}
+
+impl ControllerState {
+    fn new() -> ControllerState {
+        ControllerState {
+            button_keys: HashMap::new(),
+            axis_keys: HashMap::new()
+        }
+    }
+
+    fn button(&mut self, profile: &ControllerProfile, button: &Button, pressed: bool, input: &mut Input) -> bool {
+        if !pressed {
+            if let Some(key) = self.button_keys.remove(button) {
+                input.release(&key);
+            }
+            return profile.key_for_button(button).is_some();
+        }
+        match profile.key_for_button(button) {
+            Some(key) => {
+                if self.button_keys.insert(*button, key).is_none() {
+                    input.press(&key);
+                }
+                true
+            },
+            None => false
+        }
+    }
+
+    fn axis(&mut self, profile: &ControllerProfile, axis: &Axis, value: i16, input: &mut Input) -> bool {
+        let key = profile.key_for_axis(axis, value);
+        let held = self.axis_keys.get(axis).copied();
+        if held == key {
+            return key.is_some();
+        }
+        if let Some(held) = held {
+            input.release(&held);
+            self.axis_keys.remove(axis);
+        }
+        if let Some(key) = key {
+            input.press(&key);
+            self.axis_keys.insert(*axis, key);
+        }
+        true
+    }
+
+    fn release_all(&mut self, input: &mut Input) {
+        let buttons = self.button_keys.drain().map(|(_, key)| key);
+        for key in buttons.chain(self.axis_keys.drain().map(|(_, key)| key)) {
+            input.release(&key);
+        }
+    }
+}
+
+pub struct Controllers {
+    subsystem: GameControllerSubsystem,
+    profile: ControllerProfile,
+    open: HashMap<u32, (GameController, ControllerState)>     // Keyed by joystick instance id
+}
+
+impl Controllers {
+    // Opens every controller that is already attached, later ones are picked up from the device events
+    pub fn new(subsystem: GameControllerSubsystem) -> Controllers {
+        let mut controllers = Controllers {
+            subsystem,
+            profile: ControllerProfile::default(),
+            open: HashMap::new()
+        };
+        for index in 0..controllers.subsystem.num_joysticks().unwrap_or(0) {
+            controllers.open(index);
+        }
+        controllers
+    }
+
+    pub fn set_profile(&mut self, profile: ControllerProfile) {
+        self.profile = profile;
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.open.values().map(|(controller, _)| controller.name()).collect()
+    }
+
+    fn open(&mut self, joystick_index: u32) {
+        if !self.subsystem.is_game_controller(joystick_index) {
+            return;
+        }
+        if let Ok(controller) = self.subsystem.open(joystick_index) {
+            self.open.insert(controller.instance_id(), (controller, ControllerState::new()));
+        }
+    }
+
+    // Updates the keypad state from a controller event. Returns true if the event was consumed.
+    pub fn handle_event(&mut self, event: &Event, input: &mut Input) -> bool {
+        match event {
+            Event::ControllerDeviceAdded{which, ..} => {
+                self.open(*which);
+                true
+            },
+            Event::ControllerDeviceRemoved{which, ..} => {
+                if let Some((_, mut state)) = self.open.remove(which) {
+                    state.release_all(input);
+                }
+                true
+            },
+            Event::ControllerButtonDown{which, button, ..} => match self.open.get_mut(which) {
+                Some((_, state)) => state.button(&self.profile, button, true, input),
+                None => false
+            },
+            Event::ControllerButtonUp{which, button, ..} => match self.open.get_mut(which) {
+                Some((_, state)) => state.button(&self.profile, button, false, input),
+                None => false
+            },
+            Event::ControllerAxisMotion{which, axis, value, ..} => match self.open.get_mut(which) {
+                Some((_, state)) => state.axis(&self.profile, axis, *value, input),
+                None => false
+            },
+            _ => false
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::InputDevice;
+
+    #[test]
+    fn test_default_profile() {
+        let profile = ControllerProfile::default();
+        assert_eq!(profile.key_for_button(&Button::DPadUp), Some(0x5));
+        assert_eq!(profile.key_for_button(&Button::A), Some(0x6));
+        assert_eq!(profile.key_for_button(&Button::Guide), None);
+    }
+
+    #[test]
+    fn test_axis_deadzone() {
+        let profile = ControllerProfile::default();
+        assert_eq!(profile.key_for_axis(&Axis::LeftX, 0), None);
+        assert_eq!(profile.key_for_axis(&Axis::LeftX, DEFAULT_DEADZONE), None);
+        assert_eq!(profile.key_for_axis(&Axis::LeftX, -20000), Some(0x7));
+        assert_eq!(profile.key_for_axis(&Axis::LeftX, 20000), Some(0x9));
+        assert_eq!(profile.key_for_axis(&Axis::RightX, 20000), None);
+    }
+
+    #[test]
+    fn test_bind_game_keys() {
+        let mut profile = ControllerProfile::default();
+        let keys: HashMap<String, u8> = vec![("up".to_string(), 0x2), ("a".to_string(), 0xC)].into_iter().collect();
+        profile.bind_game_keys(&keys);
+        assert_eq!(profile.key_for_button(&Button::DPadUp), Some(0x2));
+        assert_eq!(profile.key_for_axis(&Axis::LeftY, -20000), Some(0x2));
+        assert_eq!(profile.key_for_button(&Button::A), Some(0xC));
+        assert_eq!(profile.key_for_button(&Button::B), Some(0x4));
+    }
+
+    #[test]
+    fn test_bind_out_of_range() {
+        let mut profile = ControllerProfile::new();
+        profile.bind_button(Button::A, 0x10);
+        assert_eq!(profile.key_for_button(&Button::A), None);
+    }
+
+    #[test]
+    fn test_button_press_release() {
+        let profile = ControllerProfile::default();
+        let mut state = ControllerState::new();
+        let mut input = Input::new();
+        assert!(state.button(&profile, &Button::DPadDown, true, &mut input));
+        assert!(input.is_pressed(&0x8));
+        assert!(state.button(&profile, &Button::DPadDown, false, &mut input));
+        assert!(!input.is_pressed(&0x8));
+        assert!(!state.button(&profile, &Button::Guide, true, &mut input));
+    }
+
+    #[test]
+    fn test_axis_changes_direction() {
+        let profile = ControllerProfile::default();
+        let mut state = ControllerState::new();
+        let mut input = Input::new();
+        state.axis(&profile, &Axis::LeftX, -30000, &mut input);
+        assert!(input.is_pressed(&0x7));
+        state.axis(&profile, &Axis::LeftX, 30000, &mut input);
+        assert!(!input.is_pressed(&0x7));
+        assert!(input.is_pressed(&0x9));
+        state.axis(&profile, &Axis::LeftX, 100, &mut input);
+        assert_eq!(input.get_pressed_key(), None);
+    }
+
+    #[test]
+    fn test_release_all() {
+        let profile = ControllerProfile::default();
+        let mut state = ControllerState::new();
+        let mut input = Input::new();
+        state.axis(&profile, &Axis::LeftY, 30000, &mut input);
+        state.button(&profile, &Button::A, true, &mut input);
+        state.release_all(&mut input);
+        assert!(!input.is_pressed(&0x8));
+        assert!(!input.is_pressed(&0x6));
+    }
+
+    #[test]
+    fn test_shared_key() {
+        // The keyboard holds 5 while the D-pad and stick press it too
+        let profile = ControllerProfile::default();
+        let mut state = ControllerState::new();
+        let mut input = Input::new();
+        input.press(&0x5);
+        state.button(&profile, &Button::DPadUp, true, &mut input);
+        state.axis(&profile, &Axis::LeftY, -30000, &mut input);
+        state.button(&profile, &Button::DPadUp, false, &mut input);
+        state.axis(&profile, &Axis::LeftY, 0, &mut input);
+        assert!(input.is_pressed(&0x5));
+        input.release(&0x5);
+        assert!(!input.is_pressed(&0x5));
+        // A release without a press does not take a key from another holder
+        input.press(&0x6);
+        state.button(&profile, &Button::A, false, &mut input);
+        assert!(input.is_pressed(&0x6));
+    }
+}
@@ -1,42 +1,69 @@
 use crate::bus::Bus;
+use crate::memory::{Memory, MemoryDevice, MemoryFault};
+use crate::input::{Input, InputDevice};
+use crate::graphics::{Graphics, GraphicsDevice};
+use crate::sound::{Sound, SoundDevice};
 use crate::instructions::Instruction;
-use std::io::Error;
+use crate::profiler::Profiler;
+use crate::platform::Quirks;
+use crate::threaded::Threaded;
 use rand;
 
-const REGISTER_SIZE: usize = 8;
-const STACK_SIZE: usize = 16;
+const REGISTER_SIZE: usize = 16;
+pub const STACK_SIZE: usize = 16;
+const VF: u8 = 0xF;
 
-struct CPU {
-    pub registers: [u8; REGISTER_SIZE], // 16 8 bit general purpose registers
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Backend {
+    Interpreter,    // Decodes and dispatches one instruction at a time, the reference behaviour
+    Threaded        // Runs compiled basic blocks
+}
+
+impl Backend {
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "interpreter" => Some(Backend::Interpreter),
+            "threaded" => Some(Backend::Threaded),
+            _ => None
+        }
+    }
+}
+
+pub struct CPU<M = Memory, I = Input, G = Graphics, S = Sound> {
+    pub registers: [u8; REGISTER_SIZE], // 16 8 bit general purpose registers, VF doubles as the flag register
     pub stack: [u16; STACK_SIZE],       // 16 levels of stack for function calls
     pub I : u16,                        // Special register used to store addresses
-    pub VF: u8,                        // Flag Register
     pub PC: u16,                        // Program Counter
     pub SP: usize,                         // Stack Pointer
     pub DT: u8,                         // Delay Timer (Automatically decremented at a rate of 60Hz if set)
     pub ST: u8,                         // Sound Timer (Automatically decremented at a rate of 60Hz if set) Buzzer will soudn if ST > 0
-    bus: Bus
+    pub bus: Bus<M, I, G, S>,
+    pub profiler: Option<Profiler>,     // Records every executed instruction when set
+    pub quirks: Quirks,
+    waiting_for_vblank: bool,           // Set after drawing when the vblank quirk is on
+    fault: Option<MemoryFault>,         // Set when the memory access policy traps, stops the CPU
+    threaded: Option<Threaded<M, I, G, S>>  // Set when running on the threaded backend
 }
 
-impl CPU {
-    pub fn new(bus: Bus) -> CPU {
+impl<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice> CPU<M, I, G, S> {
+    pub fn new(bus: Bus<M, I, G, S>) -> CPU<M, I, G, S> {
         CPU {
             registers: [0; REGISTER_SIZE],
             stack: [0; STACK_SIZE],
             I: 0,
-            VF: 0,
             PC: 0,
             SP: 0,
             DT: 0,
             ST: 0,
-            bus: bus
+            bus: bus,
+            profiler: None,
+            quirks: Quirks::default(),
+            waiting_for_vblank: false,
+            fault: None,
+            threaded: None
         }
     }
 
-    pub fn mock() -> CPU {
-        CPU::new(Bus::mock())
-    }
-
     pub fn fetch_opcode(&mut self) -> u16 {
         let instruction = self.bus.memory.read_instruction(&self.PC);
         instruction
@@ -47,7 +74,7 @@ impl CPU {
     }
 
     pub fn inc_pc(&mut self) {
-        self.PC += 2;
+        self.PC = self.wrap_address(self.PC.wrapping_add(2));
     }
 
     pub fn set_pc(&mut self, pc: &u16) {
@@ -62,26 +89,143 @@ impl CPU {
         self.registers[register as usize] = value;
     }
 
+    // Program addresses wrap around at the end of memory
+    fn wrap_address(&self, address: u16) -> u16 {
+        (address as usize % self.bus.memory.size()) as u16
+    }
+
+    fn reset_flag_for_logic(&mut self) {
+        if self.quirks.logic {
+            self.set_register(VF, 0);
+        }
+    }
+
+    fn shift_source(&self, destination_register: u8, source_register: u8) -> u8 {
+        if self.quirks.shift {destination_register} else {source_register}
+    }
+
+    fn increment_i_after_memory(&mut self, n: u8) {
+        if self.quirks.memory_leave_i_unchanged {
+            return;
+        }
+        let increment = if self.quirks.memory_increment_by_x {n as u16} else {n as u16 + 1};
+        self.I = self.I.wrapping_add(increment);
+    }
+
+    pub fn step(&mut self) {
+        if self.waiting_for_vblank || self.fault.is_some() {
+            return;
+        }
+        let ins = self.bus.memory.decode_instruction(&self.PC);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(self.PC, &ins);
+        }
+        self.execute_opcode(ins);
+    }
+
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    // The trapped access, PC is left on the instruction that made it
+    pub fn fault(&self) -> Option<MemoryFault> {
+        self.fault
+    }
+
+    pub fn backend(&self) -> Backend {
+        if self.threaded.is_some() {Backend::Threaded} else {Backend::Interpreter}
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.threaded = match backend {
+            Backend::Interpreter => None,
+            Backend::Threaded => Some(Threaded::new())
+        };
+    }
+
+    // Back to the power-on state with a blank screen. Memory, quirks, the backend and the profiler are kept.
+    pub fn reset(&mut self, pc: &u16) {
+        self.registers = [0; REGISTER_SIZE];
+        self.stack = [0; STACK_SIZE];
+        self.I = 0;
+        self.SP = 0;
+        self.DT = 0;
+        self.ST = 0;
+        self.waiting_for_vblank = false;
+        self.fault = None;
+        self.bus.graphics.clear();
+        self.bus.sound.set_playing(false);
+        self.invalidate_code();
+        self.set_pc(pc);
+    }
+
+    // Must be called after changing memory from outside the CPU while running on the threaded backend
+    pub fn invalidate_code(&mut self) {
+        if let Some(threaded) = &mut self.threaded {
+            threaded.invalidate();
+        }
+    }
+
+    // Runs the instructions of one 60Hz frame, then updates the timers
+    pub fn run_frame(&mut self, instructions: usize) {
+        match self.threaded.take() {
+            Some(mut threaded) => {
+                threaded.run(self, instructions);
+                self.threaded = Some(threaded);
+            },
+            None => for _ in 0..instructions {
+                self.step();
+            }
+        }
+        self.tick_timers();
+    }
+
+    // Called at 60Hz, independent of the instruction rate
+    pub fn tick_timers(&mut self) {
+        self.waiting_for_vblank = false;
+        self.DT = self.DT.saturating_sub(1);
+        self.ST = self.ST.saturating_sub(1);
+        self.bus.sound.set_playing(self.ST > 0);
+    }
+
     pub fn execute_opcode(&mut self, ins: Instruction) {
+        // Accesses through I are checked as a whole first, so a trapped instruction has no effect at all
+        let access = match ins {
+            Instruction::DisplaySpriteAtLocation{n, ..} => Some((n as usize, false)),
+            Instruction::StoreBCDValueOfRegisterToI{..} => Some((3, true)),
+            Instruction::StoreNRegistersToMemory{n} => Some((n as usize + 1, true)),
+            Instruction::ReadNRegistersFromMemory{n} => Some((n as usize + 1, false)),
+            _ => None
+        };
+        if let Some((length, write)) = access {
+            if !self.bus.memory.check_range(&self.I, length, write) {
+                self.fault = self.bus.memory.take_fault();
+                return;
+            }
+        }
         let mut inc_pc = true;
         match ins {
             Instruction::NOP => {},
             Instruction::InvalidInstruction => {},
+            Instruction::ClearDisplay => {
+                self.bus.graphics.clear();
+            },
             Instruction::Jump{address} => {
-                self.PC = address;
+                self.PC = self.wrap_address(address);
                 inc_pc = false;
             },
             Instruction::Call{address} => {
                 inc_pc = false;
                 self.inc_pc();
-                self.stack[self.SP] = self.PC;
-                self.SP += 1;
-                self.PC = address;
+                // The stack wraps around rather than overflowing
+                self.stack[self.SP % STACK_SIZE] = self.PC;
+                self.SP = (self.SP + 1) % STACK_SIZE;
+                self.PC = self.wrap_address(address);
             },
             Instruction::Return => {
                 inc_pc = false;
-                self.SP -= 1;
-                self.PC = self.stack[self.SP];
+                self.SP = (self.SP + STACK_SIZE - 1) % STACK_SIZE;
+                self.PC = self.wrap_address(self.stack[self.SP]);
             },
             Instruction::SkipIfEqual{register, value} => {
                 if self.get_register(register) == value {
@@ -108,21 +252,24 @@ impl CPU {
             Instruction::OrRegisterToRegister{destination_register, source_register} => {
                 self.set_register(destination_register, self.get_register(destination_register) |
                             self.get_register(source_register));
+                self.reset_flag_for_logic();
             },
             Instruction::AndRegisterToRegister{destination_register, source_register} => {
                 self.set_register(destination_register, self.get_register(destination_register) &
                         self.get_register(source_register));
+                self.reset_flag_for_logic();
             },
             Instruction::AddRegisterToRegister{destination_register, source_register} => {
                 let r1 = self.get_register(source_register) as u16;
                 let r2 = self.get_register(destination_register) as u16;
                 let sum = r1.wrapping_add(r2);
-                self.VF = if sum > 255 {1} else {0};
                 self.set_register(destination_register, sum as u8);
+                self.set_register(VF, if sum > 255 {1} else {0});
             },
             Instruction::XorRegisterToRegister{destination_register, source_register} => {
                 self.set_register(destination_register, self.get_register(destination_register) ^
                         self.get_register(source_register));
+                self.reset_flag_for_logic();
             },
             Instruction::SetRegisterToRegister{destination_register, source_register} => {
                 self.set_register(destination_register, self.get_register(source_register));
@@ -130,24 +277,24 @@ impl CPU {
             Instruction::SubtractRegisterFromRegister{destination_register, source_register} => {
                 let r1 = self.get_register(source_register) as u16;
                 let r2 = self.get_register(destination_register) as u16;
-                self.VF = if r2 > r1 {1} else {0};
                 self.set_register(destination_register, r2.wrapping_sub(r1) as u8);
+                self.set_register(VF, if r2 > r1 {1} else {0});
             },
             Instruction::SubtractIntoDifferentRegister{destination_register, source_register} => {
                 let r1 = self.get_register(source_register) as u16;
                 let r2 = self.get_register(destination_register) as u16;
-                self.VF = if r1 > r2 {1} else {0};
                 self.set_register(destination_register, r1.wrapping_sub(r2) as u8);
+                self.set_register(VF, if r1 > r2 {1} else {0});
             },
             Instruction::ShiftRight{destination_register, source_register} => {
-                let val = self.get_register(source_register);
-                self.VF = val & 0x1;
+                let val = self.get_register(self.shift_source(destination_register, source_register));
                 self.set_register(destination_register, val >> 1);
+                self.set_register(VF, val & 0x1);
             },
             Instruction::ShiftLeft{destination_register, source_register} => {
-                let val = self.get_register(source_register);
-                self.VF = val >> 7 & 0x1;
+                let val = self.get_register(self.shift_source(destination_register, source_register));
                 self.set_register(destination_register, val << 1);
+                self.set_register(VF, val >> 7 & 0x1);
             },
             Instruction::SkipIfRegistersNotEqual{register_1, register_2} => {
                 if self.get_register(register_1) != self.get_register(register_2) {
@@ -158,16 +305,88 @@ impl CPU {
                 self.I = value;
             },
             Instruction::JumpToLocationAndOffset0{address} => {
-                self.PC = address + self.get_register(0) as u16;
+                // With the jump quirk the high nibble of the address also selects the register
+                let register = if self.quirks.jump {(address >> 8 & 0xF) as u8} else {0};
+                self.PC = self.wrap_address(address + self.get_register(register) as u16);
                 inc_pc = false;
             },
             Instruction::GenerateRandomData{register, value} => {
                 let val = rand::random::<u8>() & value;
                 self.set_register(register, val);
+            },
+            Instruction::DisplaySpriteAtLocation{x, y, n} => {
+                let sprite: Vec<u8> = (0..n as u16).map(|i| self.bus.memory.read(&self.I.wrapping_add(i))).collect();
+                let collision = self.bus.graphics.draw_sprite(&self.get_register(x), &self.get_register(y), &sprite,
+                                                              self.quirks.wrap);
+                self.set_register(VF, if collision {1} else {0});
+                self.waiting_for_vblank = self.quirks.vblank;
+            },
+            Instruction::SkipIfPressedKeyEqualToRegister{register} => {
+                if self.bus.input.is_pressed(&self.get_register(register)) {
+                    self.inc_pc();
+                }
+            },
+            Instruction::DontSkipIfPressedKeyEqualToRegister{register} => {
+                if !self.bus.input.is_pressed(&self.get_register(register)) {
+                    self.inc_pc();
+                }
+            },
+            Instruction::SetRegisterToDelayTimer{register} => {
+                self.set_register(register, self.DT);
+            },
+            Instruction::WaitForKeyPressAndStoreValue{register} => {
+                // Execution stays on this instruction until a key is pressed
+                match self.bus.input.get_pressed_key() {
+                    Some(key) => self.set_register(register, key),
+                    None => inc_pc = false
+                }
+            },
+            Instruction::SetDelayTimerToRegister{register} => {
+                self.DT = self.get_register(register);
+            },
+            Instruction::SetSoundTimerToRegister{register} => {
+                self.ST = self.get_register(register);
+                self.bus.sound.set_playing(self.ST > 0);
+            },
+            Instruction::AddRegisterToRegisterI{register} => {
+                self.I = self.I.wrapping_add(self.get_register(register) as u16);
+            },
+            Instruction::SetIToFontAddress{digit} => {
+                // digit is the register holding the digit
+                let font = self.get_register(digit) & 0xF;
+                self.I = self.bus.memory.get_font_address(&font);
+            },
+            Instruction::SetIToLargeFontAddress{digit} => {
+                // Left unchanged where the font has no large glyph for the digit
+                let font = self.get_register(digit) & 0xF;
+                if let Some(address) = self.bus.memory.get_large_font_address(&font) {
+                    self.I = address;
+                }
+            },
+            Instruction::StoreBCDValueOfRegisterToI{register} => {
+                let val = self.get_register(register);
+                self.bus.memory.write(&self.I, &(val / 100));
+                self.bus.memory.write(&self.I.wrapping_add(1), &(val / 10 % 10));
+                self.bus.memory.write(&self.I.wrapping_add(2), &(val % 10));
+            },
+            Instruction::StoreNRegistersToMemory{n} => {
+                for i in 0..=n {
+                    self.bus.memory.write(&self.I.wrapping_add(i as u16), &self.get_register(i));
+                }
+                self.increment_i_after_memory(n);
+            },
+            Instruction::ReadNRegistersFromMemory{n} => {
+                for i in 0..=n {
+                    let val = self.bus.memory.read(&self.I.wrapping_add(i as u16));
+                    self.set_register(i, val);
+                }
+                self.increment_i_after_memory(n);
             }
-
-            _ => {}
         };
+        if let Some(fault) = self.bus.memory.take_fault() {
+            self.fault = Some(fault);
+            return;
+        }
         if inc_pc {
             self.inc_pc();
         }
@@ -177,9 +396,11 @@ impl CPU {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::font::{Font, FontSet};
+    use crate::mock::{RecordingMemory, FaultyMemory, RecordingGraphics, RecordingSound};
 
     fn get_cpu() -> CPU {
-        let mut cpu = CPU::mock();
+        let mut cpu = CPU::new(Bus::new());
         let data: Vec<u8> = vec![0xDE, 0xAD, 0xBE, 0xEF];
         cpu.bus.memory.load_data(&0x200, &data);
         cpu
@@ -251,6 +472,35 @@ mod test {
         assert_eq!(cpu.stack[0], 0x0E2);
     }
 
+    #[test]
+    fn test_stack_wraps() {
+        let mut cpu = get_cpu();
+        cpu.execute_opcode(Instruction::Return);
+        assert_eq!(cpu.SP, 15);
+        for _ in 0..16 {
+            cpu.execute_opcode(Instruction::Call{address: 0x300});
+        }
+        assert_eq!(cpu.SP, 15);
+        assert_eq!((cpu.stack[15], cpu.stack[14]), (0x002, 0x302));
+    }
+
+    #[test]
+    fn test_flag_written_after_result() {
+        let mut cpu = get_cpu();
+        cpu.execute_opcode(Instruction::LoadRegister{register: 0xF, value: 0xFF});
+        cpu.execute_opcode(Instruction::LoadRegister{register: 1, value: 0x02});
+        cpu.execute_opcode(Instruction::AddRegisterToRegister{destination_register: 0xF, source_register: 1});
+        assert_eq!(cpu.get_register(VF), 1);
+    }
+
+    #[test]
+    fn test_high_registers() {
+        let mut cpu = get_cpu();
+        cpu.execute_opcode(Instruction::LoadRegister{register: 0xE, value: 0x12});
+        cpu.execute_opcode(Instruction::SetRegisterToRegister{destination_register: 0x8, source_register: 0xE});
+        assert_eq!((cpu.registers[0x8], cpu.registers[0xE]), (0x12, 0x12));
+    }
+
     #[test]
     fn test_skip_if_equal() {
         let mut cpu = get_cpu();
@@ -341,9 +591,9 @@ mod test {
         cpu.execute_opcode(Instruction::LoadRegister{register: 4, value: 0x0B});
         cpu.execute_opcode(Instruction::AddRegisterToRegister{destination_register: 4, source_register: 5});
         assert_eq!(cpu.registers[4], 0xBB);
-        assert_eq!(cpu.VF, 0);
+        assert_eq!(cpu.get_register(VF), 0);
         cpu.execute_opcode(Instruction::AddRegisterToRegister{destination_register: 4, source_register: 5});
-        assert_eq!(cpu.VF, 1);
+        assert_eq!(cpu.get_register(VF), 1);
     }
 
     #[test]
@@ -353,9 +603,9 @@ mod test {
         cpu.execute_opcode(Instruction::LoadRegister{register: 4, value: 0xBB});
         cpu.execute_opcode(Instruction::SubtractRegisterFromRegister{destination_register: 4, source_register: 5});
         assert_eq!(cpu.registers[4], 0x0B);
-        assert_eq!(cpu.VF, 1);
+        assert_eq!(cpu.get_register(VF), 1);
         cpu.execute_opcode(Instruction::SubtractRegisterFromRegister{destination_register: 4, source_register: 5});
-        assert_eq!(cpu.VF, 0)
+        assert_eq!(cpu.get_register(VF), 0)
     }
 
     #[test]
@@ -365,9 +615,9 @@ mod test {
         cpu.execute_opcode(Instruction::LoadRegister{register: 4, value: 0xB0});
         cpu.execute_opcode(Instruction::SubtractIntoDifferentRegister{destination_register: 4, source_register: 5});
         assert_eq!(cpu.registers[4], 0x0B);
-        assert_eq!(cpu.VF, 1);
+        assert_eq!(cpu.get_register(VF), 1);
         cpu.execute_opcode(Instruction::SubtractIntoDifferentRegister{destination_register: 5, source_register: 4});
-        assert_eq!(cpu.VF, 0)
+        assert_eq!(cpu.get_register(VF), 0)
     }
 
     #[test]
@@ -376,9 +626,9 @@ mod test {
         cpu.execute_opcode(Instruction::LoadRegister{register: 5, value: 0x22});
         cpu.execute_opcode(Instruction::ShiftRight{destination_register: 4, source_register: 5});
         assert_eq!(cpu.registers[4], 0x11);
-        assert_eq!(cpu.VF, 0);
+        assert_eq!(cpu.get_register(VF), 0);
         cpu.execute_opcode(Instruction::ShiftRight{destination_register: 4, source_register: 4});
-        assert_eq!(cpu.VF, 1);
+        assert_eq!(cpu.get_register(VF), 1);
         assert_eq!(cpu.registers[4], 0x08);
     }
 
@@ -388,9 +638,9 @@ mod test {
         cpu.execute_opcode(Instruction::LoadRegister{register: 5, value: 0x88});
         cpu.execute_opcode(Instruction::ShiftLeft{destination_register: 4, source_register: 5});
         assert_eq!(cpu.registers[4], 0x10);
-        assert_eq!(cpu.VF, 1);
+        assert_eq!(cpu.get_register(VF), 1);
         cpu.execute_opcode(Instruction::ShiftLeft{destination_register: 4, source_register: 4});
-        assert_eq!(cpu.VF, 0);
+        assert_eq!(cpu.get_register(VF), 0);
         assert_eq!(cpu.registers[4], 0x20);
     }
 
@@ -417,9 +667,317 @@ mod test {
     fn test_jump_to_location_and_offset() {
         let mut cpu = get_cpu();
         cpu.execute_opcode(Instruction::LoadRegister{register: 0, value: 0x55});
-        cpu.execute_opcode(Instruction::JumpToLocationAndOffset0{address: 0xCBAB});
-        assert_eq!(cpu.PC, 0xCC00);
+        cpu.execute_opcode(Instruction::JumpToLocationAndOffset0{address: 0xBAB});
+        assert_eq!(cpu.PC, 0xC00);
+        // Past the end of memory the address wraps around
+        cpu.execute_opcode(Instruction::JumpToLocationAndOffset0{address: 0xFFF});
+        assert_eq!(cpu.PC, 0x54);
+
+    }
+
+    #[test]
+    fn test_step() {
+        let mut cpu = get_cpu();
+        cpu.bus.memory.load_data(&0x200, &[0x65, 0xBB, 0x12, 0x00]);
+        cpu.set_pc(&0x200);
+        cpu.step();
+        assert_eq!(cpu.registers[5], 0xBB);
+        assert_eq!(cpu.PC, 0x202);
+        cpu.step();
+        assert_eq!(cpu.PC, 0x200);
+    }
+
+    #[test]
+    fn test_step_profiles() {
+        let mut cpu = get_cpu();
+        cpu.profiler = Some(Profiler::new());
+        cpu.bus.memory.load_data(&0x200, &[0x22, 0x04, 0x00, 0x00, 0x00, 0xEE]);
+        cpu.set_pc(&0x200);
+        cpu.step();
+        cpu.step();
+        let profiler = cpu.profiler.unwrap();
+        assert_eq!(profiler.cycles(), 2);
+        assert_eq!(profiler.folded(), "main 1\nmain;sub_204 1\n");
+    }
 
+    #[test]
+    fn test_tick_timers() {
+        let bus = Bus::from_parts(Memory::new(), Input::new(), Graphics::new(), RecordingSound::new());
+        let mut cpu = CPU::new(bus);
+        cpu.execute_opcode(Instruction::LoadRegister{register: 1, value: 2});
+        cpu.execute_opcode(Instruction::SetDelayTimerToRegister{register: 1});
+        cpu.execute_opcode(Instruction::SetSoundTimerToRegister{register: 1});
+        cpu.tick_timers();
+        cpu.tick_timers();
+        cpu.tick_timers();
+        assert_eq!(cpu.DT, 0);
+        assert_eq!(cpu.ST, 0);
+        assert_eq!(cpu.bus.sound.changes, vec![true, true, false, false]);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_set_register_to_delay_timer() {
+        let mut cpu = get_cpu();
+        cpu.DT = 0x3C;
+        cpu.execute_opcode(Instruction::SetRegisterToDelayTimer{register: 2});
+        assert_eq!(cpu.registers[2], 0x3C);
+    }
+
+    #[test]
+    fn test_clear_display() {
+        let bus = Bus::from_parts(Memory::new(), Input::new(), RecordingGraphics::new(), Sound::new());
+        let mut cpu = CPU::new(bus);
+        cpu.execute_opcode(Instruction::ClearDisplay);
+        assert_eq!(cpu.bus.graphics.clears, 1);
+    }
+
+    #[test]
+    fn test_display_sprite() {
+        let bus = Bus::from_parts(Memory::new(), Input::new(), RecordingGraphics::new(), Sound::new());
+        let mut cpu = CPU::new(bus);
+        cpu.execute_opcode(Instruction::LoadRegister{register: 1, value: 10});
+        cpu.execute_opcode(Instruction::LoadRegister{register: 2, value: 20});
+        cpu.execute_opcode(Instruction::SetAddressRegister{value: 5});
+        cpu.execute_opcode(Instruction::DisplaySpriteAtLocation{x: 1, y: 2, n: 5});
+        assert_eq!(cpu.bus.graphics.sprites, vec![(10, 20, vec![0x20, 0x60, 0x20, 0x20, 0x70])]);
+        assert_eq!(cpu.get_register(VF), 0);
+        cpu.bus.graphics.collide = true;
+        cpu.execute_opcode(Instruction::DisplaySpriteAtLocation{x: 1, y: 2, n: 1});
+        assert_eq!(cpu.get_register(VF), 1);
+    }
+
+    #[test]
+    fn test_skip_if_key_pressed() {
+        let mut cpu = get_cpu();
+        cpu.registers[3] = 0xA;
+        cpu.execute_opcode(Instruction::SkipIfPressedKeyEqualToRegister{register: 3});
+        assert_eq!(cpu.PC, 0x02);
+        cpu.bus.input.press(&0xA);
+        cpu.execute_opcode(Instruction::SkipIfPressedKeyEqualToRegister{register: 3});
+        assert_eq!(cpu.PC, 0x06);
+    }
+
+    #[test]
+    fn test_skip_if_key_not_pressed() {
+        let mut cpu = get_cpu();
+        cpu.registers[3] = 0xA;
+        cpu.execute_opcode(Instruction::DontSkipIfPressedKeyEqualToRegister{register: 3});
+        assert_eq!(cpu.PC, 0x04);
+        cpu.bus.input.press(&0xA);
+        cpu.execute_opcode(Instruction::DontSkipIfPressedKeyEqualToRegister{register: 3});
+        assert_eq!(cpu.PC, 0x06);
+    }
+
+    #[test]
+    fn test_wait_for_key_press() {
+        let mut cpu = get_cpu();
+        cpu.execute_opcode(Instruction::WaitForKeyPressAndStoreValue{register: 4});
+        assert_eq!(cpu.PC, 0x00);
+        cpu.bus.input.press(&0x7);
+        cpu.execute_opcode(Instruction::WaitForKeyPressAndStoreValue{register: 4});
+        assert_eq!(cpu.PC, 0x02);
+        assert_eq!(cpu.registers[4], 0x7);
+    }
+
+    #[test]
+    fn test_add_register_to_i() {
+        let mut cpu = get_cpu();
+        cpu.execute_opcode(Instruction::SetAddressRegister{value: 0x200});
+        cpu.execute_opcode(Instruction::LoadRegister{register: 1, value: 0x22});
+        cpu.execute_opcode(Instruction::AddRegisterToRegisterI{register: 1});
+        assert_eq!(cpu.I, 0x222);
+    }
+
+    #[test]
+    fn test_set_i_to_font_address() {
+        let mut cpu = get_cpu();
+        cpu.execute_opcode(Instruction::LoadRegister{register: 1, value: 0xA});
+        cpu.execute_opcode(Instruction::SetIToFontAddress{digit: 1});
+        assert_eq!(cpu.I, 50);
+    }
+
+    #[test]
+    fn test_set_i_to_large_font_address() {
+        let mut cpu = get_cpu();
+        cpu.execute_opcode(Instruction::LoadRegister{register: 1, value: 0x2});
+        cpu.execute_opcode(Instruction::SetIToLargeFontAddress{digit: 1});
+        assert_eq!(cpu.I, 0);
+        cpu.bus.memory.set_font(Font::new(FontSet::SuperChip)).unwrap();
+        cpu.execute_opcode(Instruction::SetIToLargeFontAddress{digit: 1});
+        assert_eq!(cpu.I, 100);
+    }
+
+    #[test]
+    fn test_store_bcd() {
+        let bus = Bus::from_parts(RecordingMemory::new(), Input::new(), Graphics::new(), Sound::new());
+        let mut cpu = CPU::new(bus);
+        cpu.execute_opcode(Instruction::LoadRegister{register: 1, value: 254});
+        cpu.execute_opcode(Instruction::SetAddressRegister{value: 0x300});
+        cpu.execute_opcode(Instruction::StoreBCDValueOfRegisterToI{register: 1});
+        assert_eq!(cpu.bus.memory.writes, vec![(0x300, 2), (0x301, 5), (0x302, 4)]);
+    }
+
+    #[test]
+    fn test_store_registers() {
+        let bus = Bus::from_parts(RecordingMemory::new(), Input::new(), Graphics::new(), Sound::new());
+        let mut cpu = CPU::new(bus);
+        cpu.registers = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        cpu.execute_opcode(Instruction::SetAddressRegister{value: 0x300});
+        cpu.execute_opcode(Instruction::StoreNRegistersToMemory{n: 2});
+        assert_eq!(cpu.bus.memory.writes, vec![(0x300, 1), (0x301, 2), (0x302, 3)]);
+        assert_eq!(cpu.I, 0x300);
+    }
+
+    #[test]
+    fn test_read_registers() {
+        let mut cpu = get_cpu();
+        cpu.execute_opcode(Instruction::SetAddressRegister{value: 0x200});
+        cpu.execute_opcode(Instruction::ReadNRegistersFromMemory{n: 3});
+        assert_eq!(cpu.registers[..4], [0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(cpu.registers[4], 0);
+    }
+
+    #[test]
+    fn test_read_registers_from_faulty_memory() {
+        let mut memory = FaultyMemory::new();
+        memory.memory.load_data(&0x200, &[0x11, 0x22]);
+        memory.stuck.insert(0x201, 0x00);
+        let bus = Bus::from_parts(memory, Input::new(), Graphics::new(), Sound::new());
+        let mut cpu = CPU::new(bus);
+        cpu.execute_opcode(Instruction::SetAddressRegister{value: 0x200});
+        cpu.execute_opcode(Instruction::ReadNRegistersFromMemory{n: 1});
+        assert_eq!(cpu.registers[..2], [0x11, 0x00]);
+    }
+
+    #[test]
+    fn test_step_self_modifying_code() {
+        let mut cpu = get_cpu();
+        // Stores V0 and V1 over the NOP at 0x208, turning it into 7001
+        cpu.bus.memory.load_data(&0x200, &[0x60, 0x70, 0x61, 0x01, 0xA2, 0x08, 0xF1, 0x55, 0x00, 0x00]);
+        cpu.set_pc(&0x208);
+        cpu.step();
+        cpu.set_pc(&0x200);
+        for _ in 0..5 {
+            cpu.step();
+        }
+        assert_eq!(cpu.registers[0], 0x71);
+    }
+
+    #[test]
+    fn test_memory_trap_stops_cpu() {
+        let mut cpu = get_cpu();
+        cpu.bus.memory.policy.reserved_writes = crate::memory::Access::Trap;
+        // LD V0, 0x42; LD I, 0x1FF; LD [I], V1; LD V0, 1
+        cpu.bus.memory.load_data(&0x200, &[0x60, 0x42, 0xA1, 0xFF, 0xF1, 0x55, 0x60, 0x01]);
+        cpu.set_pc(&0x200);
+        cpu.run_frame(10);
+        assert_eq!(cpu.fault(), Some(MemoryFault::ReservedWrite{address: 0x1FF}));
+        assert_eq!(cpu.PC, 0x204);
+        // Neither register was written, V1 would have gone to 0x200
+        assert_eq!(cpu.bus.memory.memory[0x1FF..0x201], [0x00, 0x60]);
+        assert_eq!(cpu.registers[0], 0x42);
+    }
+
+    #[test]
+    fn test_memory_trap_after_wrapping() {
+        let mut cpu = get_cpu();
+        cpu.bus.memory.policy.reserved_writes = crate::memory::Access::Trap;
+        cpu.registers[..3].copy_from_slice(&[0x11, 0x22, 0x33]);
+        // LD I, 0xFFE; LD [I], V2 writes 0xFFE, 0xFFF and then wraps into 0x000
+        cpu.bus.memory.load_data(&0x200, &[0xAF, 0xFE, 0xF2, 0x55]);
+        let font = cpu.bus.memory.memory[0];
+        cpu.set_pc(&0x200);
+        cpu.run_frame(10);
+        assert_eq!(cpu.fault(), Some(MemoryFault::ReservedWrite{address: 0x000}));
+        assert_eq!(cpu.PC, 0x202);
+        assert_eq!(cpu.bus.memory.memory[0xFFE..], [0x00, 0x00]);
+        assert_eq!(cpu.bus.memory.memory[0], font);
+    }
+
+    #[test]
+    fn test_memory_trap_loads_nothing() {
+        let mut cpu = get_cpu();
+        cpu.bus.memory.policy.out_of_range = crate::memory::Access::Trap;
+        cpu.bus.memory.load_data(&0xFFE, &[0x11, 0x22]);
+        // LD I, 0xFFE; LD V3, [I]
+        cpu.bus.memory.load_data(&0x200, &[0xAF, 0xFE, 0xF3, 0x65]);
+        cpu.set_pc(&0x200);
+        cpu.run_frame(10);
+        assert_eq!(cpu.fault(), Some(MemoryFault::OutOfRange{address: 0x1000, write: false}));
+        assert_eq!((cpu.PC, cpu.I), (0x202, 0xFFE));
+        assert_eq!(cpu.registers[..4], [0; 4]);
+    }
+
+    #[test]
+    fn test_shift_quirk() {
+        let mut cpu = get_cpu();
+        cpu.quirks.shift = true;
+        cpu.registers[4] = 0x03;
+        cpu.registers[5] = 0x22;
+        cpu.execute_opcode(Instruction::ShiftRight{destination_register: 4, source_register: 5});
+        assert_eq!(cpu.registers[4], 0x01);
+        assert_eq!(cpu.get_register(VF), 1);
+    }
+
+    #[test]
+    fn test_logic_quirk() {
+        let mut cpu = get_cpu();
+        cpu.quirks.logic = true;
+        cpu.set_register(VF, 1);
+        cpu.execute_opcode(Instruction::OrRegisterToRegister{destination_register: 1, source_register: 2});
+        assert_eq!(cpu.get_register(VF), 0);
+    }
+
+    #[test]
+    fn test_jump_quirk() {
+        let mut cpu = get_cpu();
+        cpu.quirks.jump = true;
+        cpu.registers[0] = 0x10;
+        cpu.registers[2] = 0x01;
+        cpu.execute_opcode(Instruction::JumpToLocationAndOffset0{address: 0x234});
+        assert_eq!(cpu.PC, 0x235);
+    }
+
+    #[test]
+    fn test_memory_quirks() {
+        let mut cpu = get_cpu();
+        cpu.quirks.memory_leave_i_unchanged = false;
+        cpu.execute_opcode(Instruction::SetAddressRegister{value: 0x300});
+        cpu.execute_opcode(Instruction::StoreNRegistersToMemory{n: 2});
+        assert_eq!(cpu.I, 0x303);
+        cpu.quirks.memory_increment_by_x = true;
+        cpu.execute_opcode(Instruction::ReadNRegistersFromMemory{n: 2});
+        assert_eq!(cpu.I, 0x305);
+    }
+
+    #[test]
+    fn test_vblank_quirk() {
+        let mut cpu = get_cpu();
+        cpu.quirks.vblank = true;
+        cpu.bus.memory.load_data(&0x200, &[0xD0, 0x01, 0x70, 0x01]);
+        cpu.set_pc(&0x200);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.PC, 0x202);
+        cpu.tick_timers();
+        cpu.step();
+        assert_eq!(cpu.registers[0], 1);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut cpu = get_cpu();
+        cpu.bus.memory.load_data(&0x200, &[0x60, 0x07, 0xA0, 0x00, 0x22, 0x08, 0x00, 0x00, 0xD0, 0x01]);
+        cpu.set_pc(&0x200);
+        for _ in 0..4 {
+            cpu.step();
+        }
+        cpu.DT = 10;
+        assert_eq!(cpu.bus.graphics.get_pixel(7, 7), 1);
+        cpu.reset(&0x200);
+        assert_eq!((cpu.PC, cpu.I, cpu.SP, cpu.DT, cpu.registers[0]), (0x200, 0, 0, 0, 0));
+        assert_eq!(cpu.stack, [0; STACK_SIZE]);
+        assert_eq!(cpu.bus.memory.read(&0x200), 0x60);
+        assert_eq!(cpu.bus.graphics.get_pixel(7, 7), 0);
+    }
+}
@@ -0,0 +1,625 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use serde_json::{json, Value};
+use crate::bus::Bus;
+use crate::cpu::CPU;
+use crate::instructions::Instruction;
+use crate::memory::MemoryDevice;
+use crate::runner::{Runner, Stop};
+use crate::symbols::SymbolMap;
+
+
+const THREAD_ID: u64 = 1;
+const INSTRUCTIONS_PER_FRAME: usize = 10;
+// Variable references of the scopes
+const REGISTERS: u64 = 1;
+const TIMERS: u64 = 2;
+const STACK: u64 = 3;
+
+// Loads the ROM to launch and returns the instructions per frame. The command line passes one that applies
+// the config and the ROM database.
+pub type Loader = Box<dyn Fn(&Path) -> Result<(CPU, usize), String>>;
+
+// Reads a message framed with a Content-Length header, None at the end of the input
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+// A plain ROM at the program start, without the config
+pub fn load(path: &Path) -> Result<(CPU, usize), String> {
+    let mut cpu = CPU::new(Bus::new());
+    cpu.bus.memory.load_rom_file(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let start = cpu.bus.memory.platform.program_start();
+    cpu.set_pc(&start);
+    Ok((cpu, INSTRUCTIONS_PER_FRAME))
+}
+
+// A Debug Adapter Protocol server for a single CHIP-8 thread. Requests are answered while the CPU is stopped,
+// and between frames while it runs, so pause and new breakpoints take effect within a frame.
+pub struct Adapter<W: Write> {
+    output: W,
+    seq: u64,
+    loader: Loader,
+    cpu: Option<CPU>,
+    runner: Runner,
+    symbols: Option<SymbolMap>,
+    source_breakpoints: BTreeMap<PathBuf, BTreeSet<u16>>,
+    instruction_breakpoints: BTreeSet<u16>,
+    until: Option<(u16, usize)>,    // Where a step over or out stops, with the stack depth it must have
+    stop_on_entry: bool,
+    running: bool,
+    done: bool,
+    events: Vec<Value>,             // Sent after the response
+    transcript: Option<Box<dyn Write>>
+}
+
+impl<W: Write> Adapter<W> {
+    pub fn new(output: W, loader: Loader) -> Adapter<W> {
+        Adapter {
+            output,
+            seq: 0,
+            loader,
+            cpu: None,
+            runner: Runner::new(INSTRUCTIONS_PER_FRAME),
+            symbols: None,
+            source_breakpoints: BTreeMap::new(),
+            instruction_breakpoints: BTreeSet::new(),
+            until: None,
+            stop_on_entry: false,
+            running: false,
+            done: false,
+            events: Vec::new(),
+            transcript: None
+        }
+    }
+
+    // Logs every message both ways as a line of {"from": "client" or "adapter", "message": ...}, the format
+    // tests/dap.rs replays
+    pub fn record(&mut self, transcript: Box<dyn Write>) {
+        self.transcript = Some(transcript);
+    }
+
+    fn log(&mut self, from: &str, message: &Value) -> io::Result<()> {
+        match self.transcript.as_mut() {
+            Some(transcript) => writeln!(transcript, "{}", json!({"from": from, "message": message})),
+            None => Ok(())
+        }
+    }
+
+    // A ROM that is already loaded, for attach requests
+    pub fn attach_to(&mut self, cpu: CPU, instructions: usize) {
+        self.cpu = Some(cpu);
+        self.runner.instructions = instructions.max(1);
+    }
+
+    // Handles requests until disconnect or the end of the input
+    pub fn run(&mut self, messages: Receiver<Value>) -> io::Result<()> {
+        while !self.done {
+            if !self.running {
+                match messages.recv() {
+                    Ok(message) => self.handle(&message)?,
+                    Err(_) => break
+                }
+                continue;
+            }
+            let mut received = None;
+            let stop = match self.cpu.as_mut() {
+                Some(cpu) => self.runner.run(cpu, || match messages.try_recv() {
+                    Ok(message) => {
+                        received = Some(Some(message));
+                        true
+                    },
+                    Err(TryRecvError::Empty) => false,
+                    Err(TryRecvError::Disconnected) => {
+                        received = Some(None);
+                        true
+                    }
+                }),
+                None => Stop::Interrupted
+            };
+            match received {
+                Some(Some(message)) => self.handle(&message)?,
+                Some(None) => break,
+                None => self.stopped(stop)?
+            }
+        }
+        Ok(())
+    }
+
+    pub fn handle(&mut self, request: &Value) -> io::Result<()> {
+        self.log("client", request)?;
+        let command = request["command"].as_str().unwrap_or("");
+        let arguments = &request["arguments"];
+        let result = match command {
+            "initialize" => {
+                self.event("initialized", json!({}));
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsInstructionBreakpoints": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsWriteMemoryRequest": true,
+                    "supportsDisassembleRequest": true,
+                    "supportsSteppingGranularity": true,
+                    "supportsTerminateRequest": true
+                }))
+            },
+            "launch" => self.launch(arguments),
+            "attach" => self.attach(arguments),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.stop_event("entry", None);
+                } else {
+                    self.running = self.cpu.is_some();
+                }
+                Ok(json!({}))
+            },
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "CHIP-8"}]})),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({"scopes": [
+                {"name": "Registers", "variablesReference": REGISTERS, "expensive": false},
+                {"name": "Timers", "variablesReference": TIMERS, "expensive": false},
+                {"name": "Stack", "variablesReference": STACK, "expensive": false}
+            ]})),
+            "variables" => self.variables(arguments),
+            "continue" => self.resume(None),
+            "next" => self.next(),
+            "stepIn" => self.step(),
+            "stepOut" => self.step_out(),
+            "pause" => {
+                if self.running {
+                    self.until = None;
+                    self.update_breakpoints();
+                    self.stop_event("pause", None);
+                }
+                Ok(json!({}))
+            },
+            "readMemory" => self.read_memory(arguments),
+            "writeMemory" => self.write_memory(arguments),
+            "disassemble" => self.disassemble(arguments),
+            "terminate" => {
+                self.running = false;
+                self.event("terminated", json!({}));
+                Ok(json!({}))
+            },
+            "disconnect" => {
+                self.running = false;
+                self.done = true;
+                Ok(json!({}))
+            },
+            _ => Err(format!("unsupported request '{}'", command))
+        };
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok()
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message)
+        }
+        self.send(response)?;
+        for event in std::mem::take(&mut self.events) {
+            self.send(event)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        self.log("adapter", &message)?;
+        write_message(&mut self.output, &message)
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.events.push(json!({"type": "event", "event": event, "body": body}));
+    }
+
+    fn stop_event(&mut self, reason: &str, description: Option<String>) {
+        self.running = false;
+        let mut body = json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true});
+        if let Some(description) = description {
+            body["description"] = json!(description);
+        }
+        self.event("stopped", body);
+    }
+
+    // Reports why the CPU stopped, or keeps running past the target of a step in a deeper call
+    fn stopped(&mut self, stop: Stop) -> io::Result<()> {
+        match stop {
+            Stop::Breakpoint(address) => {
+                let depth = self.cpu.as_ref().map_or(0, |cpu| cpu.SP);
+                let target = self.until.take();
+                if target == Some((address, depth)) {
+                    self.stop_event("step", None);
+                } else if self.instruction_breakpoints.contains(&address) ||
+                    self.source_breakpoints.values().any(|addresses| addresses.contains(&address)) {
+                    self.stop_event("breakpoint", None);
+                } else {
+                    self.until = target;
+                    return Ok(());
+                }
+                self.update_breakpoints();
+            },
+            Stop::Fault(fault) => self.stop_event("exception", Some(fault.to_string())),
+            Stop::Step => self.stop_event("step", None),
+            Stop::Interrupted => self.stop_event("pause", None)
+        }
+        for event in std::mem::take(&mut self.events) {
+            self.send(event)?;
+        }
+        Ok(())
+    }
+
+    fn cpu(&self) -> Result<&CPU, String> {
+        self.cpu.as_ref().ok_or_else(|| "no ROM is loaded".to_string())
+    }
+
+    fn load_symbols(&mut self, arguments: &Value) -> Result<(), String> {
+        if let Some(path) = arguments["symbols"].as_str() {
+            self.symbols = Some(SymbolMap::load(Path::new(path)).map_err(|err| err.to_string())?);
+        }
+        Ok(())
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = arguments["program"].as_str().ok_or("launch needs the program to run")?;
+        let (cpu, instructions) = (self.loader)(Path::new(program))?;
+        self.cpu = Some(cpu);
+        self.runner.instructions = arguments["instructionsPerFrame"].as_u64().map_or(instructions, |n| n as usize).max(1);
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.load_symbols(arguments)?;
+        Ok(json!({}))
+    }
+
+    fn attach(&mut self, arguments: &Value) -> Result<Value, String> {
+        if self.cpu.is_none() {
+            return Err("nothing to attach to, start the adapter with a ROM".to_string());
+        }
+        self.load_symbols(arguments)?;
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = PathBuf::from(arguments["source"]["path"].as_str().ok_or("breakpoints need a source path")?);
+        let lines: Vec<u64> = arguments["breakpoints"].as_array().map_or(Vec::new(), |breakpoints| {
+            breakpoints.iter().filter_map(|breakpoint| breakpoint["line"].as_u64()).collect()
+        });
+        let mut addresses = BTreeSet::new();
+        let breakpoints: Vec<Value> = lines.iter().map(|line| {
+            match self.symbols.as_ref().and_then(|symbols| symbols.address_for(&path, *line as usize)) {
+                Some(entry) => {
+                    addresses.insert(entry.address);
+                    json!({"verified": true, "line": entry.line, "instructionReference": reference(entry.address)})
+                },
+                None => json!({"verified": false, "line": line, "message": match self.symbols {
+                    Some(_) => "no code on or after this line",
+                    None => "no symbol map, set breakpoints in the disassembly instead"
+                }})
+            }
+        }).collect();
+        self.source_breakpoints.insert(path, addresses);
+        self.update_breakpoints();
+        Ok(json!({"breakpoints": breakpoints}))
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let size = self.cpu().map_or(0x1000, |cpu| cpu.bus.memory.memory.len()) as i64;
+        self.instruction_breakpoints.clear();
+        let breakpoints: Vec<Value> = arguments["breakpoints"].as_array().map_or(&[][..], Vec::as_slice).iter()
+            .map(|breakpoint| {
+                let address = breakpoint["instructionReference"].as_str().and_then(parse_address)
+                    .map(|address| address + breakpoint["offset"].as_i64().unwrap_or(0))
+                    .filter(|address| (0..size).contains(address));
+                match address {
+                    Some(address) => {
+                        self.instruction_breakpoints.insert(address as u16);
+                        json!({"verified": true, "instructionReference": reference(address as u16)})
+                    },
+                    None => json!({"verified": false, "message": "not an address in memory"})
+                }
+            })
+            .collect();
+        self.update_breakpoints();
+        Ok(json!({"breakpoints": breakpoints}))
+    }
+
+    fn update_breakpoints(&mut self) {
+        let mut breakpoints: BTreeSet<u16> = self.source_breakpoints.values().flatten().copied().collect();
+        breakpoints.extend(&self.instruction_breakpoints);
+        breakpoints.extend(self.until.map(|(address, _)| address));
+        self.runner.breakpoints = breakpoints;
+    }
+
+    // The current instruction, then the call of each subroutine on the stack
+    fn stack_trace(&self) -> Result<Value, String> {
+        let cpu = self.cpu()?;
+        let calls = (0..cpu.SP).rev().map(|level| cpu.stack[level].wrapping_sub(2));
+        let frames: Vec<Value> = std::iter::once(cpu.PC).chain(calls).enumerate()
+            .map(|(id, address)| self.frame(id, address))
+            .collect();
+        Ok(json!({"stackFrames": frames, "totalFrames": frames.len()}))
+    }
+
+    fn frame(&self, id: usize, address: u16) -> Value {
+        let name = match self.symbols.as_ref().and_then(|symbols| symbols.label_for(address)) {
+            Some((label, 0)) => label.to_string(),
+            Some((label, offset)) => format!("{}+{}", label, offset),
+            None => reference(address)
+        };
+        let mut frame = json!({"id": id, "name": name, "line": 0, "column": 0,
+                               "instructionPointerReference": reference(address)});
+        if let Some(line) = self.symbols.as_ref().and_then(|symbols| symbols.line_for(address)) {
+            frame["line"] = json!(line.line);
+            frame["column"] = json!(1);
+            frame["source"] = source(&line.source);
+        }
+        frame
+    }
+
+    fn variables(&self, arguments: &Value) -> Result<Value, String> {
+        let cpu = self.cpu()?;
+        let byte = |name: String, value: u8| json!({"name": name, "value": format!("{:#04X}", value),
+                                                    "type": "u8", "variablesReference": 0});
+        let address = |name: &str, value: u16| json!({"name": name, "value": reference(value), "type": "u16",
+                                                       "variablesReference": 0, "memoryReference": reference(value)});
+        let variables: Vec<Value> = match arguments["variablesReference"].as_u64() {
+            Some(REGISTERS) => (0..16).map(|register| byte(format!("V{:X}", register), cpu.registers[register]))
+                .chain(vec![address("I", cpu.I), address("PC", cpu.PC)])
+                .collect(),
+            Some(TIMERS) => vec![byte("DT".to_string(), cpu.DT), byte("ST".to_string(), cpu.ST)],
+            Some(STACK) => std::iter::once(json!({"name": "SP", "value": cpu.SP.to_string(), "variablesReference": 0}))
+                .chain((0..cpu.SP).map(|level| address(&format!("[{}]", level), cpu.stack[level])))
+                .collect(),
+            _ => return Err("unknown variables reference".to_string())
+        };
+        Ok(json!({"variables": variables}))
+    }
+
+    fn resume(&mut self, until: Option<(u16, usize)>) -> Result<Value, String> {
+        self.cpu()?;
+        self.until = until;
+        self.update_breakpoints();
+        self.running = true;
+        Ok(json!({"allThreadsContinued": true}))
+    }
+
+    fn step(&mut self) -> Result<Value, String> {
+        let cpu = self.cpu.as_mut().ok_or("no ROM is loaded")?;
+        match self.runner.step(cpu) {
+            Stop::Fault(fault) => self.stop_event("exception", Some(fault.to_string())),
+            _ => self.stop_event("step", None)
+        }
+        Ok(json!({}))
+    }
+
+    // Runs a call until it returns to the next instruction
+    fn next(&mut self) -> Result<Value, String> {
+        let cpu = self.cpu()?;
+        match cpu.bus.memory.peek_instruction(&cpu.PC) {
+            Instruction::Call{..} => {
+                let target = (cpu.PC.wrapping_add(2), cpu.SP);
+                self.resume(Some(target))
+            },
+            _ => self.step()
+        }
+    }
+
+    fn step_out(&mut self) -> Result<Value, String> {
+        let cpu = self.cpu()?;
+        if cpu.SP == 0 {
+            return Err("not in a subroutine".to_string());
+        }
+        let target = (cpu.stack[cpu.SP - 1], cpu.SP - 1);
+        self.resume(Some(target))
+    }
+
+    fn read_memory(&self, arguments: &Value) -> Result<Value, String> {
+        let memory = &self.cpu()?.bus.memory.memory;
+        let start = memory_address(arguments)?;
+        let count = arguments["count"].as_u64().unwrap_or(0).min(memory.len() as u64) as i64;
+        let end = start.saturating_add(count).clamp(0, memory.len() as i64);
+        let data = if start >= 0 && start < end {&memory[start as usize..end as usize]} else {&[][..]};
+        Ok(json!({
+            "address": reference(start.clamp(0, u16::MAX as i64) as u16),
+            "data": to_base64(data),
+            "unreadableBytes": count - data.len() as i64
+        }))
+    }
+
+    fn write_memory(&mut self, arguments: &Value) -> Result<Value, String> {
+        let start = memory_address(arguments)?;
+        let data = arguments["data"].as_str().and_then(from_base64).ok_or("data is not base64")?;
+        let cpu = self.cpu.as_mut().ok_or("no ROM is loaded")?;
+        if start < 0 || (start as u64).saturating_add(data.len() as u64) > cpu.bus.memory.memory.len() as u64 {
+            return Err("write past the end of memory".to_string());
+        }
+        cpu.bus.memory.load_data(&(start as u16), &data);
+        cpu.invalidate_code();
+        Ok(json!({"bytesWritten": data.len()}))
+    }
+
+    // Instructions are two bytes, so instruction offsets count in steps of two from the reference
+    fn disassemble(&self, arguments: &Value) -> Result<Value, String> {
+        let memory = &self.cpu()?.bus.memory.memory;
+        let start = arguments["instructionOffset"].as_i64().unwrap_or(0).checked_mul(2)
+            .and_then(|offset| offset.checked_add(memory_address(arguments).ok()?))
+            .ok_or("instruction offset out of range")?;
+        // One entry per byte of memory at most, whatever the client asks for
+        let count = arguments["instructionCount"].as_u64().unwrap_or(0).min(memory.len() as u64) as i64;
+        let instructions: Vec<Value> = (0..count).map(|i| start.saturating_add(2 * i)).map(|address| {
+            if address < 0 || address as usize + 1 >= memory.len() {
+                return json!({"address": format!("{:#05X}", address.max(0)), "instruction": "",
+                              "presentationHint": "invalid"});
+            }
+            let word = (memory[address as usize] as u16) << 8 | memory[address as usize + 1] as u16;
+            let address = address as u16;
+            let mut instruction = json!({
+                "address": reference(address),
+                "instructionBytes": format!("{:04X}", word),
+                "instruction": Instruction::fetch_opcode(&word).disassemble()
+            });
+            if let Some(symbols) = &self.symbols {
+                if let Some((label, 0)) = symbols.label_for(address) {
+                    instruction["symbol"] = json!(label);
+                }
+                if let Some(line) = symbols.lines.iter().find(|line| line.address == address) {
+                    instruction["line"] = json!(line.line);
+                    instruction["location"] = source(&line.source);
+                }
+            }
+            instruction
+        }).collect();
+        Ok(json!({"instructions": instructions}))
+    }
+}
+
+// Serves requests from the input, read on a thread of its own so they arrive while the CPU runs
+pub fn serve<R: BufRead + Send + 'static, W: Write>(mut input: R, adapter: &mut Adapter<W>) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    adapter.run(receiver)
+}
+
+fn reference(address: u16) -> String {
+    format!("{:#05X}", address)
+}
+
+fn source(path: &Path) -> Value {
+    json!({"name": path.file_name().map(|name| name.to_string_lossy()), "path": path})
+}
+
+// "0x200" or "512"
+fn parse_address(text: &str) -> Option<i64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok()
+    }
+}
+
+fn memory_address(arguments: &Value) -> Result<i64, String> {
+    let address = arguments["memoryReference"].as_str().and_then(parse_address).ok_or("invalid memory reference")?;
+    address.checked_add(arguments["offset"].as_i64().unwrap_or(0)).ok_or_else(|| "memory offset out of range".to_string())
+}
+
+static BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn to_base64(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| bits | ((*byte as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64[((bits >> (18 - 6 * i)) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn from_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut data = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.bytes() {
+        bits = bits << 6 | BASE64.iter().position(|known| *known == c)? as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+    Some(data)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_framing() {
+        let mut output = Vec::new();
+        write_message(&mut output, &json!({"seq": 1})).unwrap();
+        assert_eq!(output, b"Content-Length: 9\r\n\r\n{\"seq\":1}");
+        let mut input = &output[..];
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({"seq": 1})));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(to_base64(&[0x60, 0x05, 0xA3, 0x00]), "YAWjAA==");
+        assert_eq!(to_base64(b"abc"), "YWJj");
+        assert_eq!(from_base64("YAWjAA=="), Some(vec![0x60, 0x05, 0xA3, 0x00]));
+        assert_eq!(from_base64("YWJj"), Some(b"abc".to_vec()));
+        assert_eq!(from_base64("Y!"), None);
+    }
+
+    #[test]
+    fn test_requests_without_rom() {
+        let mut adapter = Adapter::new(Vec::new(), Box::new(load));
+        adapter.handle(&json!({"seq": 1, "type": "request", "command": "stackTrace"})).unwrap();
+        adapter.handle(&json!({"seq": 2, "type": "request", "command": "attach"})).unwrap();
+        let mut output = &adapter.output[..];
+        let response = read_message(&mut output).unwrap().unwrap();
+        assert_eq!((response["success"].clone(), response["message"].clone()), (json!(false), json!("no ROM is loaded")));
+        assert_eq!(read_message(&mut output).unwrap().unwrap()["request_seq"], json!(2));
+    }
+
+    #[test]
+    fn test_memory_ranges() {
+        let mut adapter = Adapter::new(Vec::new(), Box::new(load));
+        adapter.attach_to(CPU::new(Bus::new()), 1);
+        let read = adapter.read_memory(&json!({"memoryReference": "0xFFE", "count": u64::MAX})).unwrap();
+        assert_eq!((read["data"].clone(), read["unreadableBytes"].clone()), (json!("AAA="), json!(4094)));
+        let read = adapter.read_memory(&json!({"memoryReference": "0x7FFFFFFFFFFFFFFF", "count": 16})).unwrap();
+        assert_eq!((read["data"].clone(), read["unreadableBytes"].clone()), (json!(""), json!(16)));
+        assert!(adapter.read_memory(&json!({"memoryReference": "0x7FFFFFFFFFFFFFFF", "offset": 1})).is_err());
+        assert!(adapter.write_memory(&json!({"memoryReference": "0x7FFFFFFFFFFFFFFF", "data": "AQI="})).is_err());
+        let listing = adapter.disassemble(&json!({"memoryReference": "0x200", "instructionOffset": i64::MIN,
+                                                  "instructionCount": 1})).unwrap_err();
+        assert_eq!(listing, "instruction offset out of range");
+        let listing = adapter.disassemble(&json!({"memoryReference": "0x7FFFFFFFFFFFFFFF", "instructionCount": u64::MAX}))
+            .unwrap();
+        assert_eq!(listing["instructions"].as_array().unwrap().len(), 4096);
+    }
+}
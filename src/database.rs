@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::Deserialize;
+use crate::platform::{Platform, QuirkOverrides, Quirks};
+
+
+// Same layout as programs.json of the community CHIP-8 database, so entries can be copied over as is
+static BUNDLED: &str = include_str!("../data/programs.json");
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Io(io::Error),
+    Parse(serde_json::Error)
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::Io(err) => write!(f, "could not read ROM database: {}", err),
+            DatabaseError::Parse(err) => write!(f, "could not parse ROM database: {}", err)
+        }
+    }
+}
+
+impl From<io::Error> for DatabaseError {
+    fn from(err: io::Error) -> DatabaseError {
+        DatabaseError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for DatabaseError {
+    fn from(err: serde_json::Error) -> DatabaseError {
+        DatabaseError::Parse(err)
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Colors {
+    #[serde(default)]
+    pub pixels: Vec<String>,        // "#rrggbb", background first
+    pub buzzer: Option<String>,
+    pub silence: Option<String>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RomEntry {
+    #[serde(default)]
+    platforms: Vec<String>,         // Most suitable first
+    tickrate: Option<usize>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, QuirkOverrides>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, RomEntry>     // Keyed by SHA-1
+}
+
+// Everything the database knows about a single ROM, resolved for a platform this interpreter supports
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomSettings {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub instructions_per_frame: Option<usize>,
+    pub keys: HashMap<String, u8>,      // Game controls ("up", "a") to keypad keys
+    pub colors: Option<Vec<String>>
+}
+
+pub struct Database {
+    programs: Vec<Program>,
+    index: HashMap<String, (usize, String)>     // SHA-1 to program index and the hash as written
+}
+
+impl Database {
+    pub fn parse(text: &str) -> Result<Database, DatabaseError> {
+        let programs: Vec<Program> = serde_json::from_str(text)?;
+        let mut index = HashMap::new();
+        for (i, program) in programs.iter().enumerate() {
+            for sha1 in program.roms.keys() {
+                index.insert(sha1.to_lowercase(), (i, sha1.clone()));
+            }
+        }
+        Ok(Database {programs, index})
+    }
+
+    pub fn load(path: &Path) -> Result<Database, DatabaseError> {
+        Database::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn bundled() -> Database {
+        Database::parse(BUNDLED).expect("bundled ROM database is invalid")
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    // Picks the first listed platform that is supported. ROMs only listed for unsupported platforms are
+    // not found.
+    pub fn lookup(&self, sha1: &str) -> Option<RomSettings> {
+        let (i, key) = self.index.get(&sha1.to_lowercase())?;
+        let program = &self.programs[*i];
+        let rom = &program.roms[key];
+        let (id, platform) = rom.platforms.iter()
+            .find_map(|id| Platform::from_platform_id(id).map(|platform| (id, platform)))?;
+        let mut quirks = Quirks::for_platform_id(id).unwrap_or_default();
+        if let Some(overrides) = rom.quirky_platforms.get(id) {
+            quirks.apply(overrides);
+        }
+        Some(RomSettings {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            platform,
+            quirks,
+            instructions_per_frame: rom.tickrate,
+            keys: rom.keys.clone(),
+            colors: rom.colors.as_ref().map(|colors| colors.pixels.clone())
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::RomInfo;
+
+    static DATABASE: &str = r##"[
+        {
+            "title": "Test Game",
+            "authors": ["Someone"],
+            "roms": {
+                "A9993E364706816ABA3E25717850C26C9CD0D89D": {
+                    "file": "test.ch8",
+                    "platforms": ["megachip8", "superchip"],
+                    "tickrate": 30,
+                    "keys": {"up": 5, "a": 6},
+                    "colors": {"pixels": ["#000000", "#ffffff"]},
+                    "quirkyPlatforms": {"superchip": {"shift": false, "wrap": true}}
+                },
+                "0000000000000000000000000000000000000000": {
+                    "platforms": ["megachip8"]
+                }
+            }
+        }
+    ]"##;
+
+    #[test]
+    fn test_bundled() {
+        let database = Database::bundled();
+        assert!(!database.is_empty());
+        let info = RomInfo::new(include_bytes!("../tests/fixtures/conformance/keypad.ch8"));
+        let settings = database.lookup(&info.sha1).unwrap();
+        assert_eq!(settings.title, "Keypad Test");
+        assert_eq!(settings.platform, Platform::Chip8);
+        assert!(!settings.quirks.vblank);
+        assert_eq!(settings.instructions_per_frame, Some(15));
+        assert_eq!(settings.keys["a"], 0xA);
+        assert_eq!(settings.colors, Some(vec!["#0a1a0a".to_string(), "#33ff66".to_string()]));
+        let info = RomInfo::new(include_bytes!("../tests/fixtures/conformance/bcd.ch8"));
+        let quirks = Quirks {vblank: false, ..Quirks::for_platform_id("originalChip8").unwrap()};
+        assert_eq!(database.lookup(&info.sha1).unwrap().quirks, quirks);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let database = Database::parse(DATABASE).unwrap();
+        assert_eq!(database.len(), 2);
+        let settings = database.lookup("a9993e364706816aba3e25717850c26c9cd0d89d").unwrap();
+        assert_eq!(settings.title, "Test Game");
+        assert_eq!(settings.platform, Platform::SuperChip);
+        assert!(!settings.quirks.shift);
+        assert!(settings.quirks.wrap);
+        assert!(settings.quirks.jump);
+        assert_eq!(settings.instructions_per_frame, Some(30));
+        assert_eq!(settings.keys["up"], 5);
+        assert_eq!(settings.colors, Some(vec!["#000000".to_string(), "#ffffff".to_string()]));
+    }
+
+    #[test]
+    fn test_lookup_unsupported_platform() {
+        let database = Database::parse(DATABASE).unwrap();
+        assert_eq!(database.lookup("0000000000000000000000000000000000000000"), None);
+        assert_eq!(database.lookup("1111111111111111111111111111111111111111"), None);
+    }
+}
@@ -1,4 +1,5 @@
 use std::io;
+use std::io::Write;
 use std::result::Result;
 use std::io::Error;
 use crossterm::{
@@ -8,8 +9,11 @@ use crossterm::{
 };
 use tui::Terminal;
 use tui::backend::CrosstermBackend;
+use tui::buffer::Buffer;
 use tui::widgets::{Widget, Block, Borders};
-use tui::layout::{Layout, Constraint, Direction};
+use tui::layout::{Layout, Constraint, Direction, Rect};
+use tui::style::{Color, Style};
+use crate::heatmap::Heatmap;
 
 pub fn crossterm_terminal() -> Result<(), Error> {
     let stdout = io::stdout();
@@ -23,3 +27,104 @@ pub fn crossterm_terminal() -> Result<(), Error> {
         f.render_widget(block, size);
     })
 }
+
+// Memory laid out as a grid, one cell per group of addresses. The colour shows whether the group was
+// mostly executed (green), written (red) or read (blue), the symbol how often it was accessed.
+pub struct HeatmapWidget<'a> {
+    heatmap: &'a Heatmap,
+    block: Option<Block<'a>>
+}
+
+impl<'a> HeatmapWidget<'a> {
+    pub fn new(heatmap: &'a Heatmap) -> HeatmapWidget<'a> {
+        HeatmapWidget {
+            heatmap,
+            block: None
+        }
+    }
+
+    pub fn block(mut self, block: Block<'a>) -> HeatmapWidget<'a> {
+        self.block = Some(block);
+        self
+    }
+
+    fn color(&self, start: usize, size: usize) -> Color {
+        let sum = |counts: &[u32]| -> u64 {counts[start..(start + size).min(counts.len())].iter().map(|c| *c as u64).sum()};
+        let (executes, writes, reads) = (sum(&self.heatmap.executes), sum(&self.heatmap.writes), sum(&self.heatmap.reads));
+        if executes >= writes && executes >= reads {
+            Color::Green
+        } else if writes >= reads {
+            Color::Red
+        } else {
+            Color::Blue
+        }
+    }
+}
+
+impl<'a> Widget for HeatmapWidget<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = match self.block {
+            Some(block) => {
+                let inner = block.inner(area);
+                block.render(area, buf);
+                inner
+            },
+            None => area
+        };
+        let label_width = 6;
+        if area.width <= label_width || area.height < 2 {
+            return;
+        }
+        // Powers of two keep the row labels on round addresses
+        let rows = (area.height - 1) as usize;
+        let mut cells_per_line = 1;
+        while cells_per_line * 2 <= (area.width - label_width) as usize {
+            cells_per_line *= 2;
+        }
+        let bytes_per_cell = self.heatmap.len().div_ceil(cells_per_line * rows).next_power_of_two();
+        let max_total = self.heatmap.max_total();
+        let line_size = cells_per_line * bytes_per_cell;
+
+        for (row, line) in (0..self.heatmap.len()).step_by(line_size).enumerate() {
+            let y = area.top() + row as u16;
+            buf.set_string(area.left(), y, format!("{:#05X}", line), Style::default());
+            for (column, cell) in (line..(line + line_size).min(self.heatmap.len())).step_by(bytes_per_cell).enumerate() {
+                let modified = (cell..cell + bytes_per_cell).any(|address| self.heatmap.is_self_modified(address));
+                let (symbol, color) = if modified {
+                    ('!', Color::Magenta)
+                } else {
+                    (Heatmap::symbol(self.heatmap.intensity(cell, bytes_per_cell, max_total)), self.color(cell, bytes_per_cell))
+                };
+                buf.get_mut(area.left() + label_width + column as u16, y)
+                    .set_symbol(&symbol.to_string())
+                    .set_fg(color);
+            }
+        }
+        let legend = format!("{} bytes per cell  executed written read  ! self-modified", bytes_per_cell);
+        buf.set_stringn(area.left(), area.bottom() - 1, legend, area.width as usize, Style::default());
+    }
+}
+
+// Shows the heatmap until a key is pressed
+pub fn show_heatmap(heatmap: &Heatmap) -> Result<(), Error> {
+    enable_raw_mode().map_err(to_io_error)?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen).map_err(to_io_error)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+    terminal.draw(|mut f| {
+        let widget = HeatmapWidget::new(heatmap)
+            .block(Block::default().title("Debugger - Memory heatmap").borders(Borders::ALL));
+        f.render_widget(widget, f.size());
+    })?;
+    loop {
+        if let CEvent::Key(_) = event::read().map_err(to_io_error)? {
+            break;
+        }
+    }
+    execute!(terminal.backend_mut(), LeaveAlternateScreen).map_err(to_io_error)?;
+    disable_raw_mode().map_err(to_io_error)
+}
+
+fn to_io_error(err: crossterm::ErrorKind) -> Error {
+    Error::other(err.to_string())
+}
@@ -0,0 +1,172 @@
+use std::collections::VecDeque;
+use serde::Deserialize;
+use crate::graphics::{Graphics, HEIGHT, WIDTH};
+
+
+pub const MAX_INTENSITY: u8 = 255;
+
+fn blend_frames() -> usize {
+    3
+}
+
+fn decay() -> f32 {
+    0.5
+}
+
+fn hold_frames() -> usize {
+    2
+}
+
+// Render-side filters against the flicker of sprites that are erased and redrawn with XOR. None of them
+// change the framebuffer the ROM sees.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum FilterConfig {
+    #[default]
+    None,
+    // Average of the last frames
+    Blend {
+        #[serde(default = "blend_frames")]
+        frames: usize
+    },
+    // Unlit pixels fade by the decay factor each frame, as on a CRT
+    Phosphor {
+        #[serde(default = "decay")]
+        decay: f32
+    },
+    // Frames that only erase are held back until a frame draws, for at most the given number of frames
+    Hold {
+        #[serde(default = "hold_frames")]
+        frames: usize
+    }
+}
+
+// Turns each frame of the framebuffer into the intensity of every pixel, row after row
+pub struct Filter {
+    config: FilterConfig,
+    history: VecDeque<Vec<bool>>,   // Blend: the last frames
+    intensities: Vec<u8>,
+    held: usize                     // Hold: frames held so far
+}
+
+impl Filter {
+    pub fn new(config: FilterConfig) -> Filter {
+        let config = match config {
+            FilterConfig::Blend{frames} => FilterConfig::Blend{frames: frames.max(1)},
+            FilterConfig::Phosphor{decay} => FilterConfig::Phosphor{decay: decay.clamp(0.0, 1.0)},
+            config => config
+        };
+        Filter {
+            config,
+            history: VecDeque::new(),
+            intensities: vec![0; WIDTH * HEIGHT],
+            held: 0
+        }
+    }
+
+    pub fn config(&self) -> FilterConfig {
+        self.config
+    }
+
+    // Called once per frame, after the frame ran
+    pub fn frame(&mut self, graphics: &Graphics) -> &[u8] {
+        let pixels: Vec<bool> = graphics.buffer.iter().flatten().map(|pixel| *pixel == 1).collect();
+        let intensity = |lit: bool| if lit {MAX_INTENSITY} else {0};
+        match self.config {
+            FilterConfig::None => {
+                self.intensities = pixels.into_iter().map(intensity).collect();
+            },
+            FilterConfig::Blend{frames} => {
+                self.history.push_back(pixels);
+                while self.history.len() > frames {
+                    self.history.pop_front();
+                }
+                let count = self.history.len();
+                for (i, value) in self.intensities.iter_mut().enumerate() {
+                    let lit = self.history.iter().filter(|frame| frame[i]).count();
+                    *value = (lit * MAX_INTENSITY as usize / count) as u8;
+                }
+            },
+            FilterConfig::Phosphor{decay} => {
+                for (value, lit) in self.intensities.iter_mut().zip(pixels) {
+                    *value = if lit {MAX_INTENSITY} else {(*value as f32 * decay) as u8};
+                }
+            },
+            FilterConfig::Hold{frames} => {
+                let shown: Vec<bool> = self.intensities.iter().map(|value| *value == MAX_INTENSITY).collect();
+                let draws = pixels.iter().zip(&shown).any(|(lit, shown)| *lit && !shown);
+                let erases = pixels.iter().zip(&shown).any(|(lit, shown)| !lit && *shown);
+                if erases && !draws && self.held < frames {
+                    self.held += 1;
+                } else {
+                    self.held = 0;
+                    self.intensities = pixels.into_iter().map(intensity).collect();
+                }
+            }
+        }
+        &self.intensities
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graphics::GraphicsDevice;
+
+    fn get_graphics(lit: bool) -> Graphics {
+        let mut graphics = Graphics::new();
+        if lit {
+            graphics.draw_sprite(&0, &0, &[0x80], false);
+        }
+        graphics
+    }
+
+    #[test]
+    fn test_none() {
+        let mut filter = Filter::new(FilterConfig::None);
+        assert_eq!(filter.frame(&get_graphics(true))[..2], [MAX_INTENSITY, 0]);
+        assert_eq!(filter.frame(&get_graphics(false))[0], 0);
+    }
+
+    #[test]
+    fn test_blend() {
+        let mut filter = Filter::new(FilterConfig::Blend{frames: 2});
+        assert_eq!(filter.frame(&get_graphics(true))[0], MAX_INTENSITY);
+        assert_eq!(filter.frame(&get_graphics(false))[0], 127);
+        assert_eq!(filter.frame(&get_graphics(false))[0], 0);
+        assert_eq!(Filter::new(FilterConfig::Blend{frames: 0}).config(), FilterConfig::Blend{frames: 1});
+    }
+
+    #[test]
+    fn test_phosphor() {
+        let mut filter = Filter::new(FilterConfig::Phosphor{decay: 0.5});
+        filter.frame(&get_graphics(true));
+        assert_eq!(filter.frame(&get_graphics(false))[0], 127);
+        assert_eq!(filter.frame(&get_graphics(false))[0], 63);
+        assert_eq!(filter.frame(&get_graphics(true))[0], MAX_INTENSITY);
+    }
+
+    #[test]
+    fn test_hold() {
+        let mut filter = Filter::new(FilterConfig::Hold{frames: 2});
+        filter.frame(&get_graphics(true));
+        // The erase is held until the redraw
+        assert_eq!(filter.frame(&get_graphics(false))[0], MAX_INTENSITY);
+        assert_eq!(filter.frame(&get_graphics(true))[0], MAX_INTENSITY);
+        // and shown once held for long enough
+        assert_eq!(filter.frame(&get_graphics(false))[0], MAX_INTENSITY);
+        assert_eq!(filter.frame(&get_graphics(false))[0], MAX_INTENSITY);
+        assert_eq!(filter.frame(&get_graphics(false))[0], 0);
+    }
+
+    #[test]
+    fn test_hold_shows_moves() {
+        // A sprite erased and drawn elsewhere in the same frame is shown right away
+        let mut filter = Filter::new(FilterConfig::Hold{frames: 2});
+        filter.frame(&get_graphics(true));
+        let mut graphics = Graphics::new();
+        graphics.draw_sprite(&1, &0, &[0x80], false);
+        assert_eq!(filter.frame(&graphics)[..2], [0, MAX_INTENSITY]);
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::platform::Platform;
+
+
+pub const SMALL_GLYPH_SIZE: u16 = 5;
+pub const LARGE_GLYPH_SIZE: u16 = 10;
+const SMALL_FONT_SIZE: usize = 16 * SMALL_GLYPH_SIZE as usize;
+
+#[derive(Debug)]
+pub enum FontError {
+    Io(io::Error),
+    InvalidSize(usize),
+    DoesNotFit{address: u16, size: usize}
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FontError::Io(err) => write!(f, "could not read font: {}", err),
+            FontError::InvalidSize(size) =>
+                write!(f, "font has {} bytes, expected 80, 180 (with 0-9 large) or 240 (with 0-F large)", size),
+            FontError::DoesNotFit{address, size} =>
+                write!(f, "font of {} bytes at {:#05X} overlaps the program", size, address)
+        }
+    }
+}
+
+impl From<io::Error> for FontError {
+    fn from(err: io::Error) -> FontError {
+        FontError::Io(err)
+    }
+}
+
+// Glyph shapes of the interpreters ROMs were written for
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FontSet {
+    CosmacVip,
+    Chip48,
+    SuperChip,  // CHIP-48 digits and large 0-9
+    Octo,       // CHIP-48 digits and large 0-F
+    Dream6800
+}
+
+impl FontSet {
+    pub fn from_name(name: &str) -> Option<FontSet> {
+        match name.to_lowercase().as_str() {
+            "vip" | "cosmac" | "cosmacvip" => Some(FontSet::CosmacVip),
+            "chip48" | "chip-48" => Some(FontSet::Chip48),
+            "schip" | "superchip" => Some(FontSet::SuperChip),
+            "octo" | "xochip" | "xo-chip" => Some(FontSet::Octo),
+            "dream6800" | "dream" => Some(FontSet::Dream6800),
+            _ => None
+        }
+    }
+
+    // The set the platform's interpreter shipped with
+    pub fn for_platform(platform: Platform) -> FontSet {
+        match platform {
+            Platform::Chip8 => FontSet::Chip48,
+            Platform::SuperChip => FontSet::SuperChip,
+            Platform::XoChip => FontSet::Octo
+        }
+    }
+
+    fn small(&self) -> &'static [u8] {
+        match self {
+            FontSet::CosmacVip => &VIP_FONT,
+            FontSet::Chip48 | FontSet::SuperChip | FontSet::Octo => &CHIP48_FONT,
+            FontSet::Dream6800 => &DREAM6800_FONT
+        }
+    }
+
+    fn large(&self) -> Option<&'static [u8]> {
+        match self {
+            FontSet::SuperChip => Some(&SCHIP_LARGE_FONT),
+            FontSet::Octo => Some(&OCTO_LARGE_FONT),
+            _ => None
+        }
+    }
+}
+
+// Glyphs and where they are loaded. The large glyphs directly follow the small ones.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Font {
+    pub small: Vec<u8>,         // 0-F, 5 rows each
+    pub large: Vec<u8>,         // 0-9 or 0-F, 10 rows each, empty if there are none
+    pub address: u16
+}
+
+impl Font {
+    pub fn new(set: FontSet) -> Font {
+        Font {
+            small: set.small().to_vec(),
+            large: set.large().map(<[u8]>::to_vec).unwrap_or_default(),
+            address: 0
+        }
+    }
+
+    // Raw glyph data, the small glyphs first
+    pub fn parse(data: &[u8]) -> Result<Font, FontError> {
+        match data.len() {
+            80 | 180 | 240 => Ok(Font {
+                small: data[..SMALL_FONT_SIZE].to_vec(),
+                large: data[SMALL_FONT_SIZE..].to_vec(),
+                address: 0
+            }),
+            size => Err(FontError::InvalidSize(size))
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Font, FontError> {
+        Font::parse(&fs::read(path)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.small.len() + self.large.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The font has to stay clear of the program
+    pub fn check_fits(&self, platform: Platform) -> Result<(), FontError> {
+        if self.address as usize + self.len() > platform.program_start() as usize {
+            return Err(FontError::DoesNotFit{address: self.address, size: self.len()});
+        }
+        Ok(())
+    }
+
+    pub fn small_glyph_address(&self, digit: u8) -> u16 {
+        self.address + (digit & 0xF) as u16 * SMALL_GLYPH_SIZE
+    }
+
+    // None for digits without a large glyph
+    pub fn large_glyph_address(&self, digit: u8) -> Option<u16> {
+        let offset = (digit & 0xF) as usize * LARGE_GLYPH_SIZE as usize;
+        if offset >= self.large.len() {
+            return None;
+        }
+        Some(self.address + (self.small.len() + offset) as u16)
+    }
+}
+
+impl Default for Font {
+    fn default() -> Font {
+        Font::new(FontSet::Chip48)
+    }
+}
+
+static VIP_FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0,  // 0
+    0x60, 0x20, 0x20, 0x20, 0x70,  // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0,  // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0,  // 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20,  // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0,  // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0,  // 6
+    0xF0, 0x10, 0x10, 0x10, 0x10,  // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0,  // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0,  // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90,  // A
+    0xF0, 0x50, 0x70, 0x50, 0xF0,  // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0,  // C
+    0xF0, 0x50, 0x50, 0x50, 0xF0,  // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0,  // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80   // F
+];
+
+static CHIP48_FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0,  // 0
+    0x20, 0x60, 0x20, 0x20, 0x70,  // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0,  // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0,  // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10,  // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0,  // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0,  // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40,  // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0,  // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0,  // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90,  // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0,  // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0,  // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0,  // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0,  // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80   // F
+];
+
+static DREAM6800_FONT: [u8; 80] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0,  // 0
+    0x40, 0x40, 0x40, 0x40, 0x40,  // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0,  // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0,  // 3
+    0x80, 0xA0, 0xA0, 0xE0, 0x20,  // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0,  // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0,  // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20,  // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0,  // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0,  // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0,  // A
+    0xC0, 0xA0, 0xE0, 0xA0, 0xC0,  // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0,  // C
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0,  // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0,  // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80   // F
+];
+
+static SCHIP_LARGE_FONT: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C,  // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C,  // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF,  // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C,  // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06,  // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C,  // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C,  // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60,  // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C,  // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C   // 9
+];
+
+static OCTO_LARGE_FONT: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF,  // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF,  // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,  // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,  // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03,  // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,  // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,  // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18,  // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,  // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,  // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,  // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC,  // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C,  // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,  // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,  // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0   // F
+];
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_font_sets() {
+        for name in ["vip", "chip48", "schip", "octo", "dream6800"].iter() {
+            let font = Font::new(FontSet::from_name(name).unwrap());
+            assert_eq!(font.small.len(), 80);
+            assert!(font.large.is_empty() || font.large.len() == 100 || font.large.len() == 160);
+        }
+        assert_eq!(FontSet::from_name("megachip"), None);
+        assert_eq!(Font::new(FontSet::CosmacVip).small[20..25], [0xA0, 0xA0, 0xF0, 0x20, 0x20]);
+    }
+
+    #[test]
+    fn test_glyph_addresses() {
+        let mut font = Font::new(FontSet::SuperChip);
+        font.address = 0x50;
+        assert_eq!(font.small_glyph_address(0xA), 0x82);
+        assert_eq!(font.large_glyph_address(0), Some(0xA0));
+        assert_eq!(font.large_glyph_address(9), Some(0xFA));
+        assert_eq!(font.large_glyph_address(0xA), None);
+        assert_eq!(Font::new(FontSet::Octo).large_glyph_address(0xF), Some(0xE6));
+        assert_eq!(Font::new(FontSet::CosmacVip).large_glyph_address(0), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Font::parse(&[0xF0; 180]).unwrap().large.len(), 100);
+        assert!(matches!(Font::parse(&[0xF0; 81]), Err(FontError::InvalidSize(81))));
+    }
+
+    #[test]
+    fn test_check_fits() {
+        let mut font = Font::new(FontSet::Octo);
+        font.address = 0x110;
+        assert!(font.check_fits(Platform::XoChip).is_ok());
+        font.address = 0x111;
+        assert!(matches!(font.check_fits(Platform::XoChip), Err(FontError::DoesNotFit{address: 0x111, size: 240})));
+    }
+}
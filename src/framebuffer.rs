@@ -0,0 +1,202 @@
+use std::fmt::Write;
+use crate::graphics::{Graphics, HEIGHT, WIDTH};
+
+
+// A monochrome snapshot of the screen, stored as text ('#' lit, '.' unlit, one line per row) or as a plain PBM
+#[derive(Debug, PartialEq, Clone)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<bool>       // Row after row
+}
+
+impl Framebuffer {
+    pub fn from_graphics(graphics: &Graphics) -> Framebuffer {
+        Framebuffer {
+            width: WIDTH,
+            height: HEIGHT,
+            pixels: graphics.buffer.iter().flatten().map(|pixel| *pixel == 1).collect()
+        }
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for row in self.pixels.chunks(self.width) {
+            text.extend(row.iter().map(|pixel| if *pixel {'#'} else {'.'}));
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn parse_text(text: &str) -> Result<Framebuffer, String> {
+        let rows: Vec<&str> = text.lines().map(str::trim_end).filter(|line| !line.is_empty()).collect();
+        let width = rows.first().map_or(0, |row| row.chars().count());
+        let mut pixels = Vec::new();
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() != width {
+                return Err(format!("row {} has {} pixels, expected {}", y, row.chars().count(), width));
+            }
+            for c in row.chars() {
+                match c {
+                    '#' => pixels.push(true),
+                    '.' => pixels.push(false),
+                    _ => return Err(format!("unexpected '{}' in row {}", c, y))
+                }
+            }
+        }
+        Ok(Framebuffer {width, height: rows.len(), pixels})
+    }
+
+    // Plain (P1) PBM, 1 is black so lit pixels are written as 1
+    pub fn to_pbm(&self) -> String {
+        let mut pbm = format!("P1\n{} {}\n", self.width, self.height);
+        for row in self.pixels.chunks(self.width) {
+            let row: Vec<&str> = row.iter().map(|pixel| if *pixel {"1"} else {"0"}).collect();
+            writeln!(pbm, "{}", row.join(" ")).unwrap();
+        }
+        pbm
+    }
+
+    // Reads plain (P1) and raw (P4) PBM
+    pub fn parse_pbm(data: &[u8]) -> Result<Framebuffer, String> {
+        let mut header = Vec::new();
+        let mut position = 0;
+        while header.len() < 3 {
+            while position < data.len() && (data[position].is_ascii_whitespace() || data[position] == b'#') {
+                if data[position] == b'#' {
+                    while position < data.len() && data[position] != b'\n' {
+                        position += 1;
+                    }
+                } else {
+                    position += 1;
+                }
+            }
+            let start = position;
+            while position < data.len() && !data[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            if start == position {
+                return Err("truncated PBM header".to_string());
+            }
+            header.push(String::from_utf8_lossy(&data[start..position]).to_string());
+        }
+        let size = |value: &str| value.parse::<usize>().map_err(|_| format!("invalid PBM size '{}'", value));
+        let (width, height) = (size(&header[1])?, size(&header[2])?);
+        let pixels: Vec<bool> = match header[0].as_str() {
+            "P1" => data[position..].iter().filter(|byte| **byte == b'0' || **byte == b'1').map(|byte| *byte == b'1').collect(),
+            "P4" => {
+                let row_bytes = width.div_ceil(8);
+                data.get(position + 1..).unwrap_or(&[]).chunks(row_bytes).take(height)
+                    .flat_map(|row| (0..width).map(move |x| row.get(x / 8).is_some_and(|byte| byte >> (7 - x % 8) & 0x1 == 1)))
+                    .collect()
+            },
+            magic => return Err(format!("unsupported PBM type '{}'", magic))
+        };
+        if pixels.len() != width * height {
+            return Err(format!("PBM has {} pixels, expected {}", pixels.len(), width * height));
+        }
+        Ok(Framebuffer {width, height, pixels})
+    }
+
+    // Golden files are PBM when they start with a PBM magic number, text otherwise
+    pub fn parse(data: &[u8]) -> Result<Framebuffer, String> {
+        if data.starts_with(b"P1") || data.starts_with(b"P4") {
+            Framebuffer::parse_pbm(data)
+        } else {
+            Framebuffer::parse_text(&String::from_utf8_lossy(data))
+        }
+    }
+
+    // None if both are the same. Otherwise a picture of this (the expected) framebuffer with '+' for pixels
+    // that are only lit in actual and '-' for pixels only lit here.
+    pub fn diff(&self, actual: &Framebuffer) -> Option<String> {
+        if (self.width, self.height) != (actual.width, actual.height) {
+            return Some(format!("expected a {}x{} framebuffer, got {}x{}\n", self.width, self.height, actual.width, actual.height));
+        }
+        let differences = self.pixels.iter().zip(&actual.pixels).filter(|(expected, actual)| expected != actual).count();
+        if differences == 0 {
+            return None;
+        }
+        let mut report = format!("{} pixels differ ('+' only lit in actual, '-' only lit in expected)\n", differences);
+        for y in 0..self.height {
+            let row: String = (0..self.width).map(|x| match (self.get_pixel(x, y), actual.get_pixel(x, y)) {
+                (true, true) => '#',
+                (false, false) => '.',
+                (false, true) => '+',
+                (true, false) => '-'
+            }).collect();
+            let marker = if row.contains(['+', '-']) {'<'} else {' '};
+            writeln!(report, "{:2} {} {}", y, row, marker).unwrap();
+        }
+        Some(report)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graphics::GraphicsDevice;
+
+    fn get_framebuffer() -> Framebuffer {
+        let mut graphics = Graphics::new();
+        graphics.draw_sprite(&2, &1, &[0xF0, 0x90], false);
+        Framebuffer::from_graphics(&graphics)
+    }
+
+    #[test]
+    fn test_text() {
+        let framebuffer = get_framebuffer();
+        let text = framebuffer.to_text();
+        assert_eq!(text.lines().count(), HEIGHT);
+        assert_eq!(text.lines().nth(1).unwrap(), format!("..####{}", ".".repeat(WIDTH - 6)));
+        assert_eq!(text.lines().nth(2).unwrap(), format!("..#..#{}", ".".repeat(WIDTH - 6)));
+        assert_eq!(Framebuffer::parse(text.as_bytes()), Ok(framebuffer));
+    }
+
+    #[test]
+    fn test_parse_text_errors() {
+        assert!(Framebuffer::parse_text("##.\n#.\n").is_err());
+        assert!(Framebuffer::parse_text("#x\n").is_err());
+    }
+
+    #[test]
+    fn test_pbm() {
+        let framebuffer = get_framebuffer();
+        let pbm = framebuffer.to_pbm();
+        assert!(pbm.starts_with("P1\n64 32\n"));
+        assert_eq!(Framebuffer::parse(pbm.as_bytes()), Ok(framebuffer));
+    }
+
+    #[test]
+    fn test_parse_raw_pbm() {
+        let framebuffer = Framebuffer::parse(b"P4\n# comment\n10 2\n\xC0\x40\x00\x80").unwrap();
+        assert_eq!((framebuffer.width, framebuffer.height), (10, 2));
+        assert_eq!(framebuffer.to_text(), "##.......#\n........#.\n");
+    }
+
+    #[test]
+    fn test_diff() {
+        let expected = get_framebuffer();
+        assert_eq!(expected.diff(&expected), None);
+        let mut actual = expected.clone();
+        actual.pixels[WIDTH + 2] = false;
+        actual.pixels[3 * WIDTH] = true;
+        let diff = expected.diff(&actual).unwrap();
+        assert!(diff.starts_with("2 pixels differ"));
+        assert!(diff.contains(&format!(" 1 ..-###{} <\n", ".".repeat(WIDTH - 6))));
+        assert!(diff.contains(&format!(" 3 +{} <\n", ".".repeat(WIDTH - 1))));
+        assert!(diff.contains(&format!(" 0 {}  \n", ".".repeat(WIDTH))));
+    }
+
+    #[test]
+    fn test_diff_size() {
+        let expected = get_framebuffer();
+        let actual = Framebuffer {width: 1, height: 1, pixels: vec![true]};
+        assert_eq!(expected.diff(&actual), Some("expected a 64x32 framebuffer, got 1x1\n".to_string()));
+    }
+}
@@ -0,0 +1,152 @@
+use crate::analysis::ControlFlowGraph;
+use crate::bus::Bus;
+use crate::cpu::{Backend, CPU};
+use crate::graphics::Graphics;
+use crate::input::Input;
+use crate::memory::Memory;
+use crate::platform::{Platform, Quirks};
+use crate::recompiler::recompile;
+use crate::sound::Sound;
+
+
+// No ROM and no key sequence may panic the core: memory addresses, the program counter and the stack wrap
+// around, register and font indices are masked to 4 bits and keys off the keypad are ignored. These are the
+// entry points of the fuzz targets in fuzz/ and of the randomized tests below, so a crash found by one can
+// be replayed by the other.
+
+const MIN_FRAMES: usize = 60;
+
+// Input layout:
+//   byte 0      quirks in bits 0-6, threaded backend in bit 7
+//   byte 1      platform in bits 0-1, instructions per frame - 1 in bits 2-7
+//   bytes 2-3   ROM length, big endian
+//   ROM
+//   the rest    one key event per frame: key in bits 0-3, pressed in bit 7
+pub fn execute(data: &[u8]) {
+    if data.len() < 4 {
+        return;
+    }
+    let bit = |n: u8| data[0] >> n & 0x1 == 1;
+    let quirks = Quirks {
+        shift: bit(0),
+        memory_increment_by_x: bit(1),
+        memory_leave_i_unchanged: bit(2),
+        wrap: bit(3),
+        jump: bit(4),
+        vblank: bit(5),
+        logic: bit(6)
+    };
+    let backend = if bit(7) {Backend::Threaded} else {Backend::Interpreter};
+    let platform = [Platform::Chip8, Platform::SuperChip, Platform::XoChip, Platform::Chip8][(data[1] & 0x3) as usize];
+    let instructions = (data[1] >> 2) as usize + 1;
+    let length = (data[2] as usize) << 8 | data[3] as usize;
+    let (rom, events) = data[4..].split_at(length.min(data.len() - 4));
+
+    let mut memory = Memory::with_platform(platform);
+    if memory.load_rom(rom).is_err() {
+        return;
+    }
+    let mut cpu = CPU::new(Bus::from_parts(memory, Input::new(), Graphics::new(), Sound::new()));
+    cpu.quirks = quirks;
+    cpu.set_backend(backend);
+    cpu.set_pc(&platform.program_start());
+    for frame in 0..events.len().max(MIN_FRAMES) {
+        if let Some(event) = events.get(frame) {
+            if event & 0x80 != 0 {
+                cpu.bus.input.press(&(event & 0xF));
+            } else {
+                cpu.bus.input.release(&(event & 0xF));
+            }
+        }
+        cpu.run_frame(instructions);
+    }
+}
+
+// Static analysis and recompilation of arbitrary ROM bytes
+pub fn analyse(rom: &[u8]) {
+    let graph = ControlFlowGraph::build(rom, Platform::Chip8.program_start());
+    graph.report();
+    graph.to_dot();
+    recompile(rom, Platform::Chip8.program_start(), "fuzz.ch8");
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    // Random bytes decode to mostly jumps and calls into zeroed memory, so half of the instructions are
+    // drawn from the opcodes most likely to go out of bounds
+    fn random_rom(rng: &mut StdRng) -> Vec<u8> {
+        let risky = [0x2000, 0x00EE, 0xB000, 0xF055, 0xF065, 0xF033, 0xF01E, 0xD000, 0xF029, 0xE09E, 0x8006, 0x800E];
+        let mut rom = Vec::new();
+        for _ in 0..rng.gen_range(1, 128) {
+            let opcode: u16 = if rng.gen() {
+                risky[rng.gen_range(0, risky.len())] | rng.gen::<u16>() & 0x0FFF
+            } else {
+                rng.gen()
+            };
+            rom.extend_from_slice(&opcode.to_be_bytes());
+        }
+        rom
+    }
+
+    fn random_input(rng: &mut StdRng) -> Vec<u8> {
+        let rom = random_rom(rng);
+        let mut data = vec![rng.gen(), rng.gen(), (rom.len() >> 8) as u8, rom.len() as u8];
+        data.extend(rom);
+        data.extend((0..rng.gen_range(0, 120)).map(|_| rng.gen::<u8>()));
+        data
+    }
+
+    #[test]
+    fn test_execute_random_roms() {
+        let mut rng = StdRng::seed_from_u64(39);
+        for _ in 0..300 {
+            execute(&random_input(&mut rng));
+        }
+    }
+
+    #[test]
+    fn test_execute_random_bytes() {
+        let mut rng = StdRng::seed_from_u64(40);
+        for _ in 0..300 {
+            let data: Vec<u8> = (0..rng.gen_range(0, 600)).map(|_| rng.gen()).collect();
+            execute(&data);
+        }
+    }
+
+    #[test]
+    fn test_execute_edge_cases() {
+        // ROM filling all of memory, ending in a jump to the last instruction
+        let mut rom = vec![0x1F, 0xFE];
+        rom.resize(0xE00, 0xFF);
+        let mut data = vec![0x80, 0x00, 0x0E, 0x00];
+        data.extend(rom);
+        execute(&data);
+        // Returns with an empty stack and calls until the stack overflows
+        execute(&[0x00, 0x00, 0x00, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+        execute(&[0x80, 0x00, 0x00, 0x02, 0x22, 0x00]);
+        // Stores and draws with I at the end of memory, on each platform
+        for platform in 0..3 {
+            execute(&[0x00, platform, 0x00, 0x0A, 0xAF, 0xFF, 0xFF, 0x55, 0xFF, 0x65, 0xD0, 0x0F, 0x12, 0x00]);
+        }
+        // Jumps to the last address, where the instruction wraps around, and past the end of memory
+        for backend in [0x00, 0x80].iter() {
+            execute(&[*backend, 0x00, 0x00, 0x04, 0x60, 0x00, 0xBF, 0xFF]);
+            execute(&[*backend | 0x10, 0x00, 0x00, 0x04, 0x6F, 0xFF, 0xBF, 0xFF]);
+        }
+    }
+
+    #[test]
+    fn test_analyse_random_roms() {
+        let mut rng = StdRng::seed_from_u64(41);
+        for _ in 0..100 {
+            analyse(&random_rom(&mut rng));
+        }
+        analyse(&[]);
+        analyse(&[0x12]);
+    }
+}
@@ -0,0 +1,549 @@
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use crate::cpu::CPU;
+use crate::graphics::GraphicsDevice;
+use crate::input::InputDevice;
+use crate::memory::Memory;
+use crate::runner::{Runner, Stop};
+use crate::sound::SoundDevice;
+
+
+const INTERRUPT: u8 = 0x03;
+const PACKET_SIZE: usize = 0x4000;
+const REGISTERS: usize = 21;     // V0-VF, I, PC, SP, DT, ST
+
+// Registers in the order of the g packet, I and PC are 16 bit little endian
+static TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+// What the server does after a packet
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Packet(String),
+    Step,
+    Continue,
+    Close(Option<String>)   // With a last packet to send
+}
+
+// The state of one debugger connection. Software and hardware breakpoints both stop before the instruction
+// runs without patching memory, they only differ in the reason reported.
+pub struct Session<'a, I, G, S> {
+    pub cpu: &'a mut CPU<Memory, I, G, S>,
+    pub runner: Runner,
+    software: BTreeSet<u16>,
+    hardware: BTreeSet<u16>,
+    last_stop: Stop
+}
+
+impl<'a, I: InputDevice, G: GraphicsDevice, S: SoundDevice> Session<'a, I, G, S> {
+    pub fn new(cpu: &'a mut CPU<Memory, I, G, S>, runner: Runner) -> Session<'a, I, G, S> {
+        Session {cpu, runner, software: BTreeSet::new(), hardware: BTreeSet::new(), last_stop: Stop::Step}
+    }
+
+    // Answers a packet without its framing. Unsupported packets get an empty reply as the protocol asks.
+    pub fn handle(&mut self, packet: &str) -> Reply {
+        let reply = |text: &str| Reply::Packet(text.to_string());
+        let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        match command {
+            "?" => Reply::Packet(self.stop_reply(self.last_stop)),
+            "g" => Reply::Packet(to_hex(&self.registers())),
+            "G" => match from_hex(arguments) {
+                Some(bytes) if bytes.len() == self.registers().len() => {
+                    self.set_registers(&bytes);
+                    reply("OK")
+                },
+                _ => reply("E01")
+            },
+            "p" => match parse_number(arguments).and_then(|number| self.register(number)) {
+                Some(bytes) => Reply::Packet(to_hex(&bytes)),
+                None => reply("E01")
+            },
+            "P" => match arguments.split_once('=').and_then(|(number, value)| Some((parse_number(number)?, from_hex(value)?))) {
+                Some((number, bytes)) if self.set_register(number, &bytes) => reply("OK"),
+                _ => reply("E01")
+            },
+            "m" => match parse_range(arguments) {
+                Some((address, _)) if address >= self.cpu.bus.memory.memory.len() => reply("E14"),
+                Some((address, length)) => {
+                    let memory = &self.cpu.bus.memory.memory;
+                    Reply::Packet(to_hex(&memory[address..(address + length).min(memory.len())]))
+                },
+                None => reply("E01")
+            },
+            "M" => match arguments.split_once(':').and_then(|(range, data)| Some((parse_range(range)?, from_hex(data)?))) {
+                Some(((address, length), data)) if data.len() == length => {
+                    if address + length > self.cpu.bus.memory.memory.len() {
+                        return reply("E14");
+                    }
+                    self.cpu.bus.memory.load_data(&(address as u16), &data);
+                    self.cpu.invalidate_code();
+                    reply("OK")
+                },
+                _ => reply("E01")
+            },
+            "c" | "s" => {
+                if let Some(address) = parse_number(arguments) {
+                    self.cpu.set_pc(&(address as u16));
+                }
+                if command == "c" {Reply::Continue} else {Reply::Step}
+            },
+            "Z" | "z" => self.breakpoint(command == "Z", arguments),
+            "H" | "T" => reply("OK"),
+            "D" => Reply::Close(Some("OK".to_string())),
+            "k" => Reply::Close(None),
+            _ => self.query(packet)
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> Reply {
+        let reply = |text: &str| Reply::Packet(text.to_string());
+        if packet.starts_with("qSupported") {
+            return Reply::Packet(format!("PacketSize={:x};qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+",
+                                         PACKET_SIZE));
+        }
+        if let Some(arguments) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_range(arguments) {
+                Some((offset, length)) => {
+                    let data = TARGET_XML.as_bytes();
+                    let chunk = &data[offset.min(data.len())..(offset + length).min(data.len())];
+                    let more = offset + length < data.len();
+                    Reply::Packet(format!("{}{}", if more {'m'} else {'l'}, String::from_utf8_lossy(chunk)))
+                },
+                None => reply("E01")
+            };
+        }
+        match packet {
+            "qAttached" => reply("1"),
+            "qC" => reply("QC1"),
+            "qfThreadInfo" => reply("m1"),
+            "qsThreadInfo" => reply("l"),
+            "QStartNoAckMode" => reply("OK"),
+            "vCont?" => reply("vCont;c;C;s;S"),
+            _ if packet.starts_with("vCont;c") || packet.starts_with("vCont;C") => Reply::Continue,
+            _ if packet.starts_with("vCont;s") || packet.starts_with("vCont;S") => Reply::Step,
+            _ => reply("")
+        }
+    }
+
+    // Z0 and Z1, watchpoints are not supported
+    fn breakpoint(&mut self, insert: bool, arguments: &str) -> Reply {
+        let mut fields = arguments.split(',');
+        let (kind, address) = match (fields.next(), fields.next().and_then(parse_number)) {
+            (Some(kind), Some(address)) => (kind, address as u16),
+            _ => return Reply::Packet("E01".to_string())
+        };
+        let set = match kind {
+            "0" => &mut self.software,
+            "1" => &mut self.hardware,
+            _ => return Reply::Packet(String::new())
+        };
+        if insert {
+            set.insert(address);
+        } else {
+            set.remove(&address);
+        }
+        self.runner.breakpoints = self.software.union(&self.hardware).copied().collect();
+        Reply::Packet("OK".to_string())
+    }
+
+    pub fn step(&mut self) -> String {
+        self.last_stop = self.runner.step(self.cpu);
+        self.stop_reply(self.last_stop)
+    }
+
+    pub fn resume<F: FnMut() -> bool>(&mut self, interrupted: F) -> String {
+        self.last_stop = self.runner.run(self.cpu, interrupted);
+        self.stop_reply(self.last_stop)
+    }
+
+    fn stop_reply(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Step => "S05".to_string(),
+            Stop::Breakpoint(address) if self.software.contains(&address) => "T05swbreak:;".to_string(),
+            Stop::Breakpoint(_) => "T05hwbreak:;".to_string(),
+            Stop::Fault(_) => "S0b".to_string(),
+            Stop::Interrupted => "S02".to_string()
+        }
+    }
+
+    fn registers(&self) -> Vec<u8> {
+        (0..REGISTERS).flat_map(|number| self.register(number).unwrap()).collect()
+    }
+
+    fn set_registers(&mut self, bytes: &[u8]) {
+        let mut offset = 0;
+        for number in 0..REGISTERS {
+            let size = self.register(number).unwrap().len();
+            self.set_register(number, &bytes[offset..offset + size]);
+            offset += size;
+        }
+    }
+
+    fn register(&self, number: usize) -> Option<Vec<u8>> {
+        let cpu = &self.cpu;
+        match number {
+            0..=15 => Some(vec![cpu.registers[number]]),
+            16 => Some(cpu.I.to_le_bytes().to_vec()),
+            17 => Some(cpu.PC.to_le_bytes().to_vec()),
+            18 => Some(vec![cpu.SP as u8]),
+            19 => Some(vec![cpu.DT]),
+            20 => Some(vec![cpu.ST]),
+            _ => None
+        }
+    }
+
+    fn set_register(&mut self, number: usize, bytes: &[u8]) -> bool {
+        let cpu = &mut self.cpu;
+        match (number, bytes) {
+            (0..=15, [value]) => cpu.registers[number] = *value,
+            (16, [low, high]) => cpu.I = u16::from_le_bytes([*low, *high]),
+            (17, [low, high]) => cpu.set_pc(&u16::from_le_bytes([*low, *high])),
+            (18, [value]) => cpu.SP = *value as usize % cpu.stack.len(),
+            (19, [value]) => cpu.DT = *value,
+            (20, [value]) => cpu.ST = *value,
+            _ => return false
+        }
+        true
+    }
+}
+
+// Packet framing over a stream: "$data#checksum", acknowledged with + or - until no-ack mode is on
+pub struct Connection<T> {
+    stream: T,
+    buffer: Vec<u8>,
+    ack: bool,
+    last: Vec<u8>   // Sent again on -
+}
+
+impl<T: Read + Write> Connection<T> {
+    pub fn new(stream: T) -> Connection<T> {
+        Connection {stream, buffer: Vec::new(), ack: true, last: Vec::new()}
+    }
+
+    // The next packet's data, None when the debugger disconnected
+    pub fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            while let Some(&byte) = self.buffer.first() {
+                match byte {
+                    b'$' => break,
+                    b'-' => {
+                        let last = self.last.clone();
+                        self.stream.write_all(&last)?;
+                    },
+                    _ => {}     // Acks, and interrupts that arrive after the CPU already stopped
+                }
+                self.buffer.remove(0);
+            }
+            if let Some(end) = self.buffer.iter().position(|byte| *byte == b'#') {
+                if self.buffer.len() >= end + 3 {
+                    let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+                    let data = unescape(&packet[1..end]);
+                    let valid = std::str::from_utf8(&packet[end + 1..]).ok()
+                        .and_then(|checksum| u8::from_str_radix(checksum, 16).ok()) == Some(checksum(&packet[1..end]));
+                    if self.ack {
+                        self.stream.write_all(if valid {b"+"} else {b"-"})?;
+                    }
+                    if valid {
+                        return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+                    }
+                    continue;
+                }
+            }
+            let mut chunk = [0; 1024];
+            let read = self.stream.read(&mut chunk)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    pub fn send(&mut self, data: &str) -> io::Result<()> {
+        let data = escape(data.as_bytes());
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(&data);
+        packet.extend_from_slice(format!("#{:02x}", checksum(&data)).as_bytes());
+        self.stream.write_all(&packet)?;
+        self.stream.flush()?;
+        self.last = packet;
+        Ok(())
+    }
+
+    pub fn set_ack(&mut self, ack: bool) {
+        self.ack = ack;
+    }
+
+    // Takes an interrupt from what was received so far
+    fn take_interrupt(&mut self) -> bool {
+        match self.buffer.iter().position(|byte| *byte == INTERRUPT) {
+            Some(position) => {
+                self.buffer.remove(position);
+                true
+            },
+            None => false
+        }
+    }
+}
+
+impl Connection<TcpStream> {
+    // Checks for a Ctrl-C from the debugger without waiting
+    pub fn poll_interrupt(&mut self) -> bool {
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut chunk = [0; 1024];
+        if let Ok(read) = self.stream.read(&mut chunk) {
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+        let _ = self.stream.set_nonblocking(false);
+        self.take_interrupt()
+    }
+}
+
+// Serves one debugger until it detaches, kills the session or disconnects. The CPU stays stopped while the
+// debugger has control and runs between continue and the next stop.
+pub fn serve<I, G, S>(cpu: &mut CPU<Memory, I, G, S>, runner: Runner, stream: TcpStream) -> io::Result<()>
+    where I: InputDevice, G: GraphicsDevice, S: SoundDevice {
+    stream.set_nodelay(true)?;
+    let mut connection = Connection::new(stream);
+    let mut session = Session::new(cpu, runner);
+    while let Some(packet) = connection.read_packet()? {
+        match session.handle(&packet) {
+            Reply::Packet(reply) => {
+                connection.send(&reply)?;
+                if packet == "QStartNoAckMode" {
+                    connection.set_ack(false);
+                }
+            },
+            Reply::Step => {
+                let reply = session.step();
+                connection.send(&reply)?;
+            },
+            Reply::Continue => {
+                let reply = session.resume(|| connection.poll_interrupt());
+                connection.send(&reply)?;
+            },
+            Reply::Close(reply) => {
+                if let Some(reply) = reply {
+                    connection.send(&reply)?;
+                }
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+// $, #, } and * are sent as } followed by the byte xor 0x20
+fn escape(data: &[u8]) -> Vec<u8> {
+    data.iter().flat_map(|byte| match byte {
+        b'$' | b'#' | b'}' | b'*' => vec![b'}', byte ^ 0x20],
+        _ => vec![*byte]
+    }).collect()
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'}' => result.extend(bytes.next().map(|byte| byte ^ 0x20)),
+            _ => result.push(*byte)
+        }
+    }
+    result
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+// "address,length", refused when the end does not fit
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    let (address, length) = (parse_number(address)?, parse_number(length)?);
+    address.checked_add(length)?;
+    Some((address, length))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+    use crate::bus::Bus;
+
+    fn get_cpu() -> CPU {
+        let mut cpu = CPU::new(Bus::new());
+        // LD V0, 0x12; ADD V1, 1; JP 0x202
+        cpu.bus.memory.load_data(&0x200, &[0x60, 0x12, 0x71, 0x01, 0x12, 0x02]);
+        cpu.set_pc(&0x200);
+        cpu
+    }
+
+    fn get_runner() -> Runner {
+        let mut runner = Runner::new(10);
+        runner.paced = false;
+        runner
+    }
+
+    // Sends a packet as GDB would and returns the reply
+    fn request(stream: &mut TcpStream, packet: &str) -> String {
+        write!(stream, "${}#{:02x}", packet, checksum(packet.as_bytes())).unwrap();
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        while reply.len() < 3 || reply[reply.len() - 3] != b'#' {
+            stream.read_exact(&mut byte).unwrap();
+            reply.push(byte[0]);
+        }
+        stream.write_all(b"+").unwrap();
+        assert_eq!(reply[0], b'+');
+        String::from_utf8(reply[2..reply.len() - 3].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut cpu = get_cpu();
+        cpu.I = 0x345;
+        let mut session = Session::new(&mut cpu, get_runner());
+        let registers = match session.handle("g") {
+            Reply::Packet(registers) => registers,
+            reply => panic!("{:?}", reply)
+        };
+        assert_eq!(registers.len(), 2 * 23);
+        assert_eq!(&registers[32..40], "45030002");
+        assert_eq!(session.handle("P5=ab"), Reply::Packet("OK".to_string()));
+        assert_eq!(session.handle("p5"), Reply::Packet("ab".to_string()));
+        assert_eq!(session.handle("P11=0403"), Reply::Packet("OK".to_string()));
+        assert_eq!(session.handle("p11"), Reply::Packet("0403".to_string()));
+        assert_eq!(session.handle("p15"), Reply::Packet("E01".to_string()));
+        assert_eq!(session.handle("G00"), Reply::Packet("E01".to_string()));
+        assert_eq!(session.cpu.PC, 0x304);
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut cpu = get_cpu();
+        let mut session = Session::new(&mut cpu, get_runner());
+        assert_eq!(session.handle("m200,3"), Reply::Packet("601271".to_string()));
+        assert_eq!(session.handle("M300,2:beef"), Reply::Packet("OK".to_string()));
+        assert_eq!(session.handle("m300,2"), Reply::Packet("beef".to_string()));
+        assert_eq!(session.handle("mffe,4"), Reply::Packet("0000".to_string()));
+        assert_eq!(session.handle("m1000,1"), Reply::Packet("E14".to_string()));
+        assert_eq!(session.handle("Mfff,2:0000"), Reply::Packet("E14".to_string()));
+        assert_eq!(session.handle("M300,2:be"), Reply::Packet("E01".to_string()));
+        assert_eq!(session.handle("m200,ffffffffffffffff"), Reply::Packet("E01".to_string()));
+        // Lengths past the end are cut at the end of memory
+        assert_eq!(session.handle("mffc,fffffffff"), Reply::Packet("00000000".to_string()));
+        assert_eq!(session.handle("Mffffffffffffffff,2:0000"), Reply::Packet("E01".to_string()));
+    }
+
+    #[test]
+    fn test_target_description() {
+        let mut cpu = get_cpu();
+        let mut session = Session::new(&mut cpu, get_runner());
+        let first = match session.handle("qXfer:features:read:target.xml:0,a") {
+            Reply::Packet(first) => first,
+            reply => panic!("{:?}", reply)
+        };
+        assert_eq!(first, "m<?xml vers");
+        let rest = match session.handle("qXfer:features:read:target.xml:a,4000") {
+            Reply::Packet(rest) => rest,
+            reply => panic!("{:?}", reply)
+        };
+        assert!(rest.starts_with("lion=") && rest.ends_with("</target>\n"));
+        assert_eq!(session.handle("qXfer:features:read:target.xml:10,ffffffffffffffff"), Reply::Packet("E01".to_string()));
+        assert_eq!(TARGET_XML.matches("<reg ").count(), REGISTERS);
+    }
+
+    #[test]
+    fn test_framing() {
+        assert_eq!(escape(b"a}b#"), b"a}]b}\x03");
+        assert_eq!(unescape(b"a}]b}\x03"), b"a}b#");
+        let mut input = io::Cursor::new(b"+$m200,2#5d$bad#00".to_vec());
+        let mut connection = Connection::new(&mut input);
+        assert_eq!(connection.read_packet().unwrap(), Some("m200,2".to_string()));
+        assert_eq!(connection.read_packet().unwrap(), None);
+        assert!(input.get_ref().ends_with(b"+-"));
+    }
+
+    // A scripted client over a real socket, as GDB would drive the server
+    #[test]
+    fn test_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.set_nodelay(true).unwrap();
+            let mut replies = Vec::new();
+            for packet in &["qSupported:swbreak+;hwbreak+", "?", "Z0,204,2", "c", "p11", "z0,204,2", "Z1,202,2", "c",
+                            "s", "p1", "z1,202,2"] {
+                replies.push(request(&mut stream, packet));
+            }
+            // Runs until interrupted
+            write!(stream, "$c#63").unwrap();
+            thread::sleep(Duration::from_millis(50));
+            stream.write_all(&[INTERRUPT]).unwrap();
+            let mut reply = [0; 8];
+            stream.read_exact(&mut reply).unwrap();
+            replies.push(String::from_utf8(reply[2..5].to_vec()).unwrap());
+            stream.write_all(b"+").unwrap();
+            replies.push(request(&mut stream, "D"));
+            replies
+        });
+        let mut cpu = get_cpu();
+        let (stream, _) = listener.accept().unwrap();
+        serve(&mut cpu, get_runner(), stream).unwrap();
+        let replies = client.join().unwrap();
+        assert!(replies[0].contains("qXfer:features:read+"));
+        assert_eq!(replies[1..5], ["S05", "OK", "T05swbreak:;", "0402"]);
+        assert_eq!(replies[6..10], ["OK", "T05hwbreak:;", "S05", "02"]);
+        assert_eq!(replies[11..], ["S02", "OK"]);
+        assert_eq!(cpu.get_register(0), 0x12);
+    }
+}
@@ -1,56 +1,264 @@
-use sdl2::pixels::Color;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use std::time::Duration;
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+const MAX_DIRTY_RECTS: usize = 16;
 
+pub trait GraphicsDevice {
+    fn clear(&mut self);
 
-pub struct Graphics {
+    // XORs the sprite onto the screen, returns true if any pixel was turned off. Sprites crossing an edge
+    // are clipped unless wrap is set.
+    fn draw_sprite(&mut self, x: &u8, y: &u8, sprite: &[u8], wrap: bool) -> bool;
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize
+}
+
+impl Rect {
+    fn union(&self, other: &Rect) -> Rect {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y
+        }
+    }
+}
 
+// What a renderer has to redraw: the rows with a pixel that changed, and rectangles covering every changed
+// pixel. A sprite drawn across an edge gives one rectangle per side.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Changes {
+    pub rows: Vec<usize>,
+    pub rects: Vec<Rect>
+}
+
+pub struct Graphics {
+    pub buffer: [[u8; WIDTH]; HEIGHT],    // 1 for a lit pixel, indexed by row then column. Writing to it
+                                          // directly bypasses the change tracking.
+    dirty_rows: [bool; HEIGHT],
+    dirty_rects: Vec<Rect>                // Merged into their bounding box past MAX_DIRTY_RECTS
 }
 
 impl Graphics {
     pub fn new() -> Graphics {
-        Graphics {}
-    }
-
-    pub fn mock() -> Graphics {
-        Graphics {}
-    }
-}
-
-
-pub fn sdl_demo() {
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
- 
-    let window = video_subsystem.window("rust-sdl2 demo", 800, 600)
-        .position_centered()
-        .build()
-        .unwrap();
- 
-    let mut canvas = window.into_canvas().build().unwrap();
- 
-    canvas.set_draw_color(Color::RGB(0, 255, 255));
-    canvas.clear();
-    canvas.present();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    let mut i = 0;
-    'running: loop {
-        i = (i + 1) % 255;
-        canvas.set_draw_color(Color::RGB(i, 64, 255 - i));
-        canvas.clear();
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit {..} |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    break 'running
-                },
-                _ => {}
+        Graphics {
+            buffer: [[0; WIDTH]; HEIGHT],
+            dirty_rows: [false; HEIGHT],
+            dirty_rects: Vec::new()
+        }
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        self.buffer[y % HEIGHT][x % WIDTH]
+    }
+
+    // Whether any pixel changed since the changes were last taken
+    pub fn is_changed(&self) -> bool {
+        !self.dirty_rects.is_empty()
+    }
+
+    // The changes since the last call, None if the screen is unchanged. Renderers call this once per frame.
+    pub fn take_changes(&mut self) -> Option<Changes> {
+        if !self.is_changed() {
+            return None;
+        }
+        let rows = (0..HEIGHT).filter(|row| self.dirty_rows[*row]).collect();
+        self.dirty_rows = [false; HEIGHT];
+        Some(Changes {rows, rects: std::mem::take(&mut self.dirty_rects)})
+    }
+
+    // Marks the whole screen, for a renderer that lost its contents
+    pub fn invalidate(&mut self) {
+        self.dirty_rows = [true; HEIGHT];
+        self.dirty_rects = vec![Rect {x: 0, y: 0, width: WIDTH, height: HEIGHT}];
+    }
+
+    fn mark(&mut self, rect: Rect) {
+        (rect.y..rect.y + rect.height).for_each(|row| self.dirty_rows[row] = true);
+        self.push_rect(rect);
+    }
+
+    fn push_rect(&mut self, rect: Rect) {
+        self.dirty_rects.push(rect);
+        if self.dirty_rects.len() > MAX_DIRTY_RECTS {
+            let first = self.dirty_rects[0];
+            let bounds = self.dirty_rects.iter().fold(first, |bounds, rect| bounds.union(rect));
+            self.dirty_rects = vec![bounds];
+        }
+    }
+}
+
+impl Default for Graphics {
+    fn default() -> Graphics {
+        Graphics::new()
+    }
+}
+
+impl GraphicsDevice for Graphics {
+    fn clear(&mut self) {
+        let lit: Vec<usize> = (0..HEIGHT).filter(|row| self.buffer[*row].contains(&1)).collect();
+        if let (Some(first), Some(last)) = (lit.first(), lit.last()) {
+            // One rectangle over the lit rows, while blank rows in between did not change. Rows already marked
+            // stay marked.
+            lit.iter().for_each(|row| self.dirty_rows[*row] = true);
+            self.push_rect(Rect {x: 0, y: *first, width: WIDTH, height: last - first + 1});
+        }
+        self.buffer = [[0; WIDTH]; HEIGHT];
+    }
+
+    // The starting position always wraps around the screen
+    fn draw_sprite(&mut self, x: &u8, y: &u8, sprite: &[u8], wrap: bool) -> bool {
+        let x = *x as usize % WIDTH;
+        let y = *y as usize % HEIGHT;
+        let (rows, columns) = if wrap {(sprite.len(), 8)} else {(HEIGHT - y, 8.min(WIDTH - x))};
+        let mut collision = false;
+        // Bounds of the changed pixels on each side of the right and bottom edges
+        let mut sides: [Option<Rect>; 4] = [None; 4];
+        for (row, byte) in sprite.iter().enumerate().take(rows) {
+            for column in 0..columns {
+                if byte >> (7 - column) & 0x1 == 0 {
+                    continue;
+                }
+                let (pixel_x, pixel_y) = ((x + column) % WIDTH, (y + row) % HEIGHT);
+                let pixel = &mut self.buffer[pixel_y][pixel_x];
+                collision |= *pixel == 1;
+                *pixel ^= 1;
+                let rect = Rect {x: pixel_x, y: pixel_y, width: 1, height: 1};
+                let side = &mut sides[(x + column >= WIDTH) as usize | ((y + row >= HEIGHT) as usize * 2)];
+                *side = Some(side.map_or(rect, |bounds| bounds.union(&rect)));
             }
         }
-        // The rest of the game loop goes here...
+        sides.iter().flatten().for_each(|rect| self.mark(*rect));
+        collision
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_draw_sprite() {
+        let mut graphics = Graphics::new();
+        assert!(!graphics.draw_sprite(&2, &3, &[0xA0, 0x40], false));
+        assert_eq!(graphics.get_pixel(2, 3), 1);
+        assert_eq!(graphics.get_pixel(3, 3), 0);
+        assert_eq!(graphics.get_pixel(4, 3), 1);
+        assert_eq!(graphics.get_pixel(3, 4), 1);
+    }
+
+    #[test]
+    fn test_draw_sprite_collision() {
+        let mut graphics = Graphics::new();
+        graphics.draw_sprite(&0, &0, &[0xC0], false);
+        assert!(graphics.draw_sprite(&1, &0, &[0x80], false));
+        assert_eq!(graphics.get_pixel(0, 0), 1);
+        assert_eq!(graphics.get_pixel(1, 0), 0);
+    }
+
+    #[test]
+    fn test_draw_sprite_wraps_start() {
+        let mut graphics = Graphics::new();
+        graphics.draw_sprite(&(WIDTH as u8 + 1), &(HEIGHT as u8 + 2), &[0x80], false);
+        assert_eq!(graphics.get_pixel(1, 2), 1);
+    }
+
+    #[test]
+    fn test_draw_sprite_clips() {
+        let mut graphics = Graphics::new();
+        graphics.draw_sprite(&62, &31, &[0xFF, 0xFF], false);
+        assert_eq!(graphics.get_pixel(62, 31), 1);
+        assert_eq!(graphics.get_pixel(63, 31), 1);
+        assert_eq!(graphics.get_pixel(0, 31), 0);
+        assert_eq!(graphics.get_pixel(62, 0), 0);
+    }
 
-        canvas.present();
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+    #[test]
+    fn test_draw_sprite_wraps() {
+        let mut graphics = Graphics::new();
+        graphics.draw_sprite(&62, &31, &[0xFF, 0xFF], true);
+        assert_eq!(graphics.get_pixel(63, 31), 1);
+        assert_eq!(graphics.get_pixel(0, 31), 1);
+        assert_eq!(graphics.get_pixel(62, 0), 1);
+        assert_eq!(graphics.get_pixel(6, 0), 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_clear() {
+        let mut graphics = Graphics::new();
+        graphics.draw_sprite(&0, &0, &[0xFF], false);
+        graphics.clear();
+        assert_eq!(graphics.get_pixel(0, 0), 0);
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut graphics = Graphics::new();
+        assert_eq!(graphics.take_changes(), None);
+        graphics.draw_sprite(&2, &3, &[0x00, 0x60, 0x40], false);
+        assert!(graphics.is_changed());
+        assert_eq!(graphics.take_changes(), Some(Changes {rows: vec![4, 5], rects: vec![Rect {x: 3, y: 4, width: 2, height: 2}]}));
+        assert!(!graphics.is_changed());
+        graphics.draw_sprite(&0, &0, &[0x00], false);
+        assert_eq!(graphics.take_changes(), None);
+    }
+
+    #[test]
+    fn test_changes_wrap() {
+        let mut graphics = Graphics::new();
+        graphics.draw_sprite(&62, &31, &[0xFF, 0xFF], true);
+        let changes = graphics.take_changes().unwrap();
+        assert_eq!(changes.rows, vec![0, 31]);
+        assert_eq!(changes.rects, vec![
+            Rect {x: 62, y: 31, width: 2, height: 1},
+            Rect {x: 0, y: 31, width: 6, height: 1},
+            Rect {x: 62, y: 0, width: 2, height: 1},
+            Rect {x: 0, y: 0, width: 6, height: 1}
+        ]);
+    }
+
+    #[test]
+    fn test_changes_clear() {
+        let mut graphics = Graphics::new();
+        graphics.clear();
+        assert_eq!(graphics.take_changes(), None);
+        graphics.draw_sprite(&8, &2, &[0x80], false);
+        graphics.draw_sprite(&8, &6, &[0x80], false);
+        graphics.take_changes();
+        graphics.clear();
+        assert_eq!(graphics.take_changes(), Some(Changes {rows: vec![2, 6], rects: vec![Rect {x: 0, y: 2, width: WIDTH, height: 5}]}));
+    }
+
+    #[test]
+    fn test_changes_erase_then_clear() {
+        let mut graphics = Graphics::new();
+        for y in [2, 4, 6].iter() {
+            graphics.draw_sprite(&8, y, &[0x80], false);
+        }
+        graphics.take_changes();
+        // Row 4 is erased and blank by the time the screen is cleared in the same frame
+        graphics.draw_sprite(&8, &4, &[0x80], false);
+        graphics.clear();
+        assert_eq!(graphics.take_changes().unwrap().rows, vec![2, 4, 6]);
+    }
+
+    #[test]
+    fn test_changes_merge() {
+        let mut graphics = Graphics::new();
+        for i in 0..=MAX_DIRTY_RECTS as u8 {
+            graphics.draw_sprite(&(i * 2), &i, &[0x80], false);
+        }
+        let changes = graphics.take_changes().unwrap();
+        assert_eq!(changes.rows.len(), MAX_DIRTY_RECTS + 1);
+        assert_eq!(changes.rects, vec![Rect {x: 0, y: 0, width: MAX_DIRTY_RECTS * 2 + 1, height: MAX_DIRTY_RECTS + 1}]);
+        graphics.invalidate();
+        assert_eq!(graphics.take_changes().unwrap().rows.len(), HEIGHT);
+    }
+}
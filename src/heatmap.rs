@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum CodeEvent {
+    SelfModifyingCode{pc: u16},         // Executed an instruction that was written at runtime
+    WriteToCode{address: u16, pc: u16}  // Wrote over bytes that were executed before
+}
+
+// Per address access counters kept by Memory while tracking is enabled
+pub struct AccessTracker {
+    reads: Vec<u32>,
+    writes: Vec<u32>,
+    executes: Vec<u32>,
+    pc: u16,                            // Address of the last instruction fetched
+    events: Vec<CodeEvent>,             // In the order they first happened
+    seen: HashSet<CodeEvent>
+}
+
+impl AccessTracker {
+    pub fn new(size: usize) -> AccessTracker {
+        AccessTracker {
+            reads: vec![0; size],
+            writes: vec![0; size],
+            executes: vec![0; size],
+            pc: 0,
+            events: Vec::new(),
+            seen: HashSet::new()
+        }
+    }
+
+    pub fn read(&mut self, address: &u16) {
+        if let Some(count) = self.reads.get_mut(*address as usize) {
+            *count += 1;
+        }
+    }
+
+    pub fn write(&mut self, address: &u16) {
+        let index = *address as usize;
+        if index >= self.writes.len() {
+            return;
+        }
+        self.writes[index] += 1;
+        if self.executes[index] > 0 {
+            self.push_event(CodeEvent::WriteToCode{address: *address, pc: self.pc});
+        }
+    }
+
+    pub fn execute(&mut self, address: &u16) {
+        self.pc = *address;
+        let index = *address as usize;
+        let mut modified = false;
+        for byte in index..(index + 2).min(self.executes.len()) {
+            self.executes[byte] += 1;
+            modified |= self.writes[byte] > 0;
+        }
+        if modified {
+            self.push_event(CodeEvent::SelfModifyingCode{pc: *address});
+        }
+    }
+
+    // Only the first occurrence is kept, loops would otherwise report the same event every iteration
+    fn push_event(&mut self, event: CodeEvent) {
+        if self.seen.insert(event.clone()) {
+            self.events.push(event);
+        }
+    }
+
+    pub fn heatmap(&self) -> Heatmap {
+        Heatmap {
+            reads: self.reads.clone(),
+            writes: self.writes.clone(),
+            executes: self.executes.clone(),
+            events: self.events.clone()
+        }
+    }
+}
+
+pub struct Heatmap {
+    pub reads: Vec<u32>,
+    pub writes: Vec<u32>,
+    pub executes: Vec<u32>,
+    pub events: Vec<CodeEvent>
+}
+
+static RAMP: &[u8] = b".:-=+*#%@";
+
+impl Heatmap {
+    pub fn len(&self) -> usize {
+        self.reads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reads.is_empty()
+    }
+
+    pub fn total(&self, address: usize) -> u32 {
+        self.reads[address] + self.writes[address] + self.executes[address]
+    }
+
+    pub fn is_self_modified(&self, address: usize) -> bool {
+        self.events.iter().any(|event| match event {
+            CodeEvent::SelfModifyingCode{pc} => *pc as usize == address || *pc as usize + 1 == address,
+            CodeEvent::WriteToCode{address: written, ..} => *written as usize == address
+        })
+    }
+
+    // Sorted by count, highest first. Addresses that were never accessed are left out.
+    pub fn hottest(counts: &[u32], n: usize) -> Vec<(u16, u32)> {
+        let mut hottest: Vec<(u16, u32)> = counts.iter().enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(address, count)| (address as u16, *count))
+            .collect();
+        hottest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hottest.truncate(n);
+        hottest
+    }
+
+    pub fn max_total(&self) -> u32 {
+        (0..self.len()).map(|address| self.total(address)).max().unwrap_or(0)
+    }
+
+    // Intensity of a group of addresses on a log scale from 0 (untouched) to 9, relative to a group where
+    // every address was accessed max_total times
+    pub fn intensity(&self, start: usize, size: usize, max_total: u32) -> usize {
+        let total: u64 = (start..(start + size).min(self.len())).map(|address| self.total(address) as u64).sum();
+        if total == 0 {
+            return 0;
+        }
+        let max = max_total as u64 * size as u64;
+        let scaled = (total as f64).ln_1p() / (max as f64).ln_1p();
+        1 + (scaled * (RAMP.len() - 1) as f64).round() as usize
+    }
+
+    pub fn symbol(level: usize) -> char {
+        match level {
+            0 => ' ',
+            level => RAMP[(level - 1).min(RAMP.len() - 1)] as char
+        }
+    }
+
+    // One character per cell of bytes_per_cell addresses, blank for untouched memory and '!' for cells
+    // holding self-modified code
+    pub fn render_map(&self, bytes_per_cell: usize, cells_per_line: usize) -> String {
+        let mut map = String::new();
+        let max_total = self.max_total();
+        let line_size = bytes_per_cell * cells_per_line;
+        for line in (0..self.len()).step_by(line_size) {
+            write!(map, "{:#05X} ", line).unwrap();
+            for cell in (line..(line + line_size).min(self.len())).step_by(bytes_per_cell) {
+                let modified = (cell..cell + bytes_per_cell).any(|address| self.is_self_modified(address));
+                map.push(if modified {'!'} else {Heatmap::symbol(self.intensity(cell, bytes_per_cell, max_total))});
+            }
+            map.push('\n');
+        }
+        map
+    }
+
+    pub fn report(&self) -> String {
+        let touched = |counts: &[u32]| counts.iter().filter(|count| **count > 0).count();
+        let mut report = String::new();
+        writeln!(report, "Executed {} bytes, read {} bytes, wrote {} bytes", touched(&self.executes),
+                 touched(&self.reads), touched(&self.writes)).unwrap();
+
+        writeln!(report, "\nHottest instructions").unwrap();
+        let instructions: Vec<u32> = self.executes.iter().enumerate()
+            .map(|(address, count)| if address % 2 == 0 || self.executes[address - 1] == 0 {*count} else {0})
+            .collect();
+        for (address, count) in Heatmap::hottest(&instructions, 10) {
+            writeln!(report, "  {:#05X} {:>10}", address, count).unwrap();
+        }
+        writeln!(report, "\nHottest data reads").unwrap();
+        for (address, count) in Heatmap::hottest(&self.reads, 10) {
+            writeln!(report, "  {:#05X} {:>10}", address, count).unwrap();
+        }
+        writeln!(report, "\nHottest data writes").unwrap();
+        for (address, count) in Heatmap::hottest(&self.writes, 10) {
+            writeln!(report, "  {:#05X} {:>10}", address, count).unwrap();
+        }
+
+        writeln!(report, "\nSelf-modifying code").unwrap();
+        if self.events.is_empty() {
+            writeln!(report, "  none").unwrap();
+        }
+        for event in &self.events {
+            match event {
+                CodeEvent::SelfModifyingCode{pc} =>
+                    writeln!(report, "  {:#05X} executed after being written", pc).unwrap(),
+                CodeEvent::WriteToCode{address, pc} =>
+                    writeln!(report, "  {:#05X} written by {:#05X} after being executed", address, pc).unwrap()
+            }
+        }
+
+        writeln!(report, "\nMap (4 bytes per cell, ' ' untouched to '@' hottest, '!' self-modified)").unwrap();
+        report.push_str(&self.render_map(4, 64));
+        report
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counts() {
+        let mut tracker = AccessTracker::new(16);
+        tracker.read(&3);
+        tracker.read(&3);
+        tracker.write(&4);
+        tracker.execute(&0);
+        let heatmap = tracker.heatmap();
+        assert_eq!(heatmap.reads[3], 2);
+        assert_eq!(heatmap.writes[4], 1);
+        assert_eq!(heatmap.executes[..3], [1, 1, 0]);
+        assert_eq!(heatmap.total(3), 2);
+    }
+
+    #[test]
+    fn test_out_of_range_is_ignored() {
+        let mut tracker = AccessTracker::new(16);
+        tracker.read(&16);
+        tracker.write(&16);
+        tracker.execute(&15);
+        assert_eq!(tracker.heatmap().executes[15], 1);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let mut tracker = AccessTracker::new(16);
+        tracker.execute(&0);
+        tracker.write(&9);
+        tracker.execute(&8);
+        tracker.execute(&8);
+        assert_eq!(tracker.heatmap().events, vec![CodeEvent::SelfModifyingCode{pc: 8}]);
+    }
+
+    #[test]
+    fn test_write_to_code() {
+        let mut tracker = AccessTracker::new(16);
+        tracker.execute(&2);
+        tracker.execute(&4);
+        tracker.write(&3);
+        assert_eq!(tracker.heatmap().events, vec![CodeEvent::WriteToCode{address: 3, pc: 4}]);
+    }
+
+    #[test]
+    fn test_hottest() {
+        let counts = vec![0, 5, 1, 5, 9];
+        assert_eq!(Heatmap::hottest(&counts, 3), vec![(4, 9), (1, 5), (3, 5)]);
+    }
+
+    #[test]
+    fn test_render_map() {
+        let mut tracker = AccessTracker::new(8);
+        for _ in 0..100 {
+            tracker.execute(&0);
+        }
+        tracker.read(&4);
+        let map = tracker.heatmap().render_map(2, 4);
+        assert_eq!(map, "0x000 @ : \n");
+    }
+}
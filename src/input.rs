@@ -1,13 +1,53 @@
-pub struct Input {
+pub const KEY_COUNT: usize = 16;
+
+pub trait InputDevice {
+    fn is_pressed(&self, key: &u8) -> bool;
 
+    fn get_pressed_key(&self) -> Option<u8>;
+}
+
+// Keys of the hex keypad count the host keys and buttons holding them, so a key held on the keyboard and a
+// controller at once stays pressed until both let go. Each source presses and releases a key once.
+pub struct Input {
+    pub keys: [u8; KEY_COUNT]       // Holders of each of the 16 keys of the hex keypad
 }
 
 impl Input {
     pub fn new() -> Input {
-        Input {}
+        Input {
+            keys: [0; KEY_COUNT]
+        }
+    }
+
+    pub fn press(&mut self, key: &u8) {
+        if let Some(holders) = self.keys.get_mut(*key as usize) {
+            *holders = holders.saturating_add(1);
+        }
+    }
+
+    pub fn release(&mut self, key: &u8) {
+        if let Some(holders) = self.keys.get_mut(*key as usize) {
+            *holders = holders.saturating_sub(1);
+        }
     }
 
-    pub fn mock() -> Input {
-        Input {}
+    pub fn release_all(&mut self) {
+        self.keys = [0; KEY_COUNT];
     }
-}
\ No newline at end of file
+}
+
+impl Default for Input {
+    fn default() -> Input {
+        Input::new()
+    }
+}
+
+impl InputDevice for Input {
+    fn is_pressed(&self, key: &u8) -> bool {
+        self.keys.get(*key as usize).is_some_and(|holders| *holders > 0)
+    }
+
+    fn get_pressed_key(&self) -> Option<u8> {
+        self.keys.iter().position(|holders| *holders > 0).map(|key| key as u8)
+    }
+}
@@ -2,7 +2,7 @@ type Address = u16;
 type HalfWord = u8;
 
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Instruction {
     NOP,
     InvalidInstruction,
@@ -37,6 +37,7 @@ pub enum Instruction {
     SetSoundTimerToRegister{register: u8},
     AddRegisterToRegisterI{register: u8},
     SetIToFontAddress{digit: u8},
+    SetIToLargeFontAddress{digit: u8},
     StoreBCDValueOfRegisterToI{register: u8},
     StoreNRegistersToMemory{n: u8},
     ReadNRegistersFromMemory{n: u8}
@@ -72,6 +73,102 @@ fn get_last_3_nibbles(value: &u16) -> u16 {
 
 
 impl Instruction {
+    // Name of the variant without its operands, used to group instructions
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::NOP => "NOP",
+            Instruction::InvalidInstruction => "InvalidInstruction",
+            Instruction::ClearDisplay => "ClearDisplay",
+            Instruction::Return => "Return",
+            Instruction::Jump{..} => "Jump",
+            Instruction::Call{..} => "Call",
+            Instruction::SkipIfEqual{..} => "SkipIfEqual",
+            Instruction::SkipIfNotEqual{..} => "SkipIfNotEqual",
+            Instruction::SkipIfRegistersEqual{..} => "SkipIfRegistersEqual",
+            Instruction::LoadRegister{..} => "LoadRegister",
+            Instruction::AddToRegister{..} => "AddToRegister",
+            Instruction::SetRegisterToRegister{..} => "SetRegisterToRegister",
+            Instruction::OrRegisterToRegister{..} => "OrRegisterToRegister",
+            Instruction::AndRegisterToRegister{..} => "AndRegisterToRegister",
+            Instruction::XorRegisterToRegister{..} => "XorRegisterToRegister",
+            Instruction::AddRegisterToRegister{..} => "AddRegisterToRegister",
+            Instruction::SubtractRegisterFromRegister{..} => "SubtractRegisterFromRegister",
+            Instruction::ShiftRight{..} => "ShiftRight",
+            Instruction::SubtractIntoDifferentRegister{..} => "SubtractIntoDifferentRegister",
+            Instruction::ShiftLeft{..} => "ShiftLeft",
+            Instruction::SkipIfRegistersNotEqual{..} => "SkipIfRegistersNotEqual",
+            Instruction::SetAddressRegister{..} => "SetAddressRegister",
+            Instruction::JumpToLocationAndOffset0{..} => "JumpToLocationAndOffset0",
+            Instruction::GenerateRandomData{..} => "GenerateRandomData",
+            Instruction::DisplaySpriteAtLocation{..} => "DisplaySpriteAtLocation",
+            Instruction::SkipIfPressedKeyEqualToRegister{..} => "SkipIfPressedKeyEqualToRegister",
+            Instruction::DontSkipIfPressedKeyEqualToRegister{..} => "DontSkipIfPressedKeyEqualToRegister",
+            Instruction::SetRegisterToDelayTimer{..} => "SetRegisterToDelayTimer",
+            Instruction::WaitForKeyPressAndStoreValue{..} => "WaitForKeyPressAndStoreValue",
+            Instruction::SetDelayTimerToRegister{..} => "SetDelayTimerToRegister",
+            Instruction::SetSoundTimerToRegister{..} => "SetSoundTimerToRegister",
+            Instruction::AddRegisterToRegisterI{..} => "AddRegisterToRegisterI",
+            Instruction::SetIToFontAddress{..} => "SetIToFontAddress",
+            Instruction::SetIToLargeFontAddress{..} => "SetIToLargeFontAddress",
+            Instruction::StoreBCDValueOfRegisterToI{..} => "StoreBCDValueOfRegisterToI",
+            Instruction::StoreNRegistersToMemory{..} => "StoreNRegistersToMemory",
+            Instruction::ReadNRegistersFromMemory{..} => "ReadNRegistersFromMemory"
+        }
+    }
+
+    // Assembly in the usual Cowgod syntax, as shown by debuggers
+    pub fn disassemble(&self) -> String {
+        match *self {
+            Instruction::NOP => "NOP".to_string(),
+            Instruction::InvalidInstruction => "INVALID".to_string(),
+            Instruction::ClearDisplay => "CLS".to_string(),
+            Instruction::Return => "RET".to_string(),
+            Instruction::Jump{address} => format!("JP {:#05X}", address),
+            Instruction::Call{address} => format!("CALL {:#05X}", address),
+            Instruction::SkipIfEqual{register, value} => format!("SE V{:X}, {:#04X}", register, value),
+            Instruction::SkipIfNotEqual{register, value} => format!("SNE V{:X}, {:#04X}", register, value),
+            Instruction::SkipIfRegistersEqual{register_1, register_2} => format!("SE V{:X}, V{:X}", register_1, register_2),
+            Instruction::LoadRegister{register, value} => format!("LD V{:X}, {:#04X}", register, value),
+            Instruction::AddToRegister{register, value} => format!("ADD V{:X}, {:#04X}", register, value),
+            Instruction::SetRegisterToRegister{destination_register, source_register} =>
+                format!("LD V{:X}, V{:X}", destination_register, source_register),
+            Instruction::OrRegisterToRegister{destination_register, source_register} =>
+                format!("OR V{:X}, V{:X}", destination_register, source_register),
+            Instruction::AndRegisterToRegister{destination_register, source_register} =>
+                format!("AND V{:X}, V{:X}", destination_register, source_register),
+            Instruction::XorRegisterToRegister{destination_register, source_register} =>
+                format!("XOR V{:X}, V{:X}", destination_register, source_register),
+            Instruction::AddRegisterToRegister{destination_register, source_register} =>
+                format!("ADD V{:X}, V{:X}", destination_register, source_register),
+            Instruction::SubtractRegisterFromRegister{destination_register, source_register} =>
+                format!("SUB V{:X}, V{:X}", destination_register, source_register),
+            Instruction::ShiftRight{destination_register, source_register} =>
+                format!("SHR V{:X}, V{:X}", destination_register, source_register),
+            // Decoded with X as the source
+            Instruction::SubtractIntoDifferentRegister{source_register, destination_register} =>
+                format!("SUBN V{:X}, V{:X}", source_register, destination_register),
+            Instruction::ShiftLeft{destination_register, source_register} =>
+                format!("SHL V{:X}, V{:X}", destination_register, source_register),
+            Instruction::SkipIfRegistersNotEqual{register_1, register_2} => format!("SNE V{:X}, V{:X}", register_1, register_2),
+            Instruction::SetAddressRegister{value} => format!("LD I, {:#05X}", value),
+            Instruction::JumpToLocationAndOffset0{address} => format!("JP V0, {:#05X}", address),
+            Instruction::GenerateRandomData{register, value} => format!("RND V{:X}, {:#04X}", register, value),
+            Instruction::DisplaySpriteAtLocation{x, y, n} => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipIfPressedKeyEqualToRegister{register} => format!("SKP V{:X}", register),
+            Instruction::DontSkipIfPressedKeyEqualToRegister{register} => format!("SKNP V{:X}", register),
+            Instruction::SetRegisterToDelayTimer{register} => format!("LD V{:X}, DT", register),
+            Instruction::WaitForKeyPressAndStoreValue{register} => format!("LD V{:X}, K", register),
+            Instruction::SetDelayTimerToRegister{register} => format!("LD DT, V{:X}", register),
+            Instruction::SetSoundTimerToRegister{register} => format!("LD ST, V{:X}", register),
+            Instruction::AddRegisterToRegisterI{register} => format!("ADD I, V{:X}", register),
+            Instruction::SetIToFontAddress{digit} => format!("LD F, V{:X}", digit),
+            Instruction::SetIToLargeFontAddress{digit} => format!("LD HF, V{:X}", digit),
+            Instruction::StoreBCDValueOfRegisterToI{register} => format!("LD B, V{:X}", register),
+            Instruction::StoreNRegistersToMemory{n} => format!("LD [I], V{:X}", n),
+            Instruction::ReadNRegistersFromMemory{n} => format!("LD V{:X}, [I]", n)
+        }
+    }
+
     pub fn fetch_opcode(word: &u16) -> Instruction {
         match get_first_nibble(&word) {
             0 => match get_last_byte(&word) {
@@ -125,6 +222,7 @@ impl Instruction {
                     0x18 => Instruction::SetSoundTimerToRegister{register: get_second_nibble(&word)},
                     0x1E => Instruction::AddRegisterToRegisterI{register: get_second_nibble(&word)},
                     0x29 => Instruction::SetIToFontAddress{digit: get_second_nibble(&word)},
+                    0x30 => Instruction::SetIToLargeFontAddress{digit: get_second_nibble(&word)},
                     0x33 => Instruction::StoreBCDValueOfRegisterToI{register:get_second_nibble(&word)},
                     0x55 => Instruction::StoreNRegistersToMemory{n: get_second_nibble(&word)},
                     0x65 => Instruction::ReadNRegistersFromMemory{n: get_second_nibble(&word)},
@@ -152,6 +250,25 @@ mod test {
         assert_eq!(get_last_3_nibbles(&data), 0xEAD);
     }
 
+    #[test]
+    fn test_name() {
+        assert_eq!(Instruction::NOP.name(), "NOP");
+        assert_eq!(Instruction::fetch_opcode(&0x1FEF).name(), "Jump");
+        assert_eq!(Instruction::fetch_opcode(&0xD351).name(), "DisplaySpriteAtLocation");
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let disassemble = |word: u16| Instruction::fetch_opcode(&word).disassemble();
+        assert_eq!(disassemble(0x00E0), "CLS");
+        assert_eq!(disassemble(0x1FEF), "JP 0xFEF");
+        assert_eq!(disassemble(0x6A05), "LD VA, 0x05");
+        assert_eq!(disassemble(0x8357), "SUBN V3, V5");
+        assert_eq!(disassemble(0xD351), "DRW V3, V5, 1");
+        assert_eq!(disassemble(0xF365), "LD V3, [I]");
+        assert_eq!(disassemble(0xF000), "INVALID");
+    }
+
     #[test]
     fn test_fetch_clear_display() {
         assert_eq!(Instruction::fetch_opcode(&0x00E0), Instruction::ClearDisplay);
@@ -318,6 +435,7 @@ mod test {
     #[test]
     fn test_get_font_value() {
         assert_eq!(Instruction::fetch_opcode(&0xF329), Instruction::SetIToFontAddress{digit: 0x3});
+        assert_eq!(Instruction::fetch_opcode(&0xF330), Instruction::SetIToLargeFontAddress{digit: 0x3});
     }
 
     #[test]
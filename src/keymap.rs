@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Scancode};
+use crate::input::{Input, KEY_COUNT};
+
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Preset {
+    Qwerty,     // 1234/QWER/ASDF/ZXCV block, laid out like the COSMAC VIP keypad
+    Numpad,     // Numeric keypad, laid out like the COSMAC VIP keypad
+    Cosmac      // Keys labelled 0-9 and A-F map to the hex key of the same name
+}
+
+impl Preset {
+    pub fn from_name(name: &str) -> Option<Preset> {
+        match name.to_lowercase().as_str() {
+            "qwerty" => Some(Preset::Qwerty),
+            "numpad" => Some(Preset::Numpad),
+            "cosmac" | "hex" => Some(Preset::Cosmac),
+            _ => None
+        }
+    }
+
+    fn bindings(&self) -> &'static [(Scancode, u8)] {
+        match self {
+            Preset::Qwerty => &QWERTY,
+            Preset::Numpad => &NUMPAD,
+            Preset::Cosmac => &COSMAC
+        }
+    }
+}
+
+// Presets bind scancodes, so the physical position of a key decides which hex key it sends regardless of
+// the host keyboard layout. Keycode bindings, made explicitly by the label of a key, take precedence.
+pub struct Keymap {
+    scancodes: HashMap<Scancode, u8>,
+    keycodes: HashMap<Keycode, u8>
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap {
+            scancodes: HashMap::new(),
+            keycodes: HashMap::new()
+        }
+    }
+
+    pub fn from_preset(preset: Preset) -> Keymap {
+        let mut keymap = Keymap::new();
+        for (scancode, key) in preset.bindings() {
+            keymap.bind_scancode(*scancode, *key);
+        }
+        keymap
+    }
+
+    pub fn bind_scancode(&mut self, scancode: Scancode, key: u8) {
+        if (key as usize) < KEY_COUNT {
+            self.scancodes.insert(scancode, key);
+        }
+    }
+
+    pub fn bind_keycode(&mut self, keycode: Keycode, key: u8) {
+        if (key as usize) < KEY_COUNT {
+            self.keycodes.insert(keycode, key);
+        }
+    }
+
+    // Binds the game controls named by the ROM database ("up", "a") to the arrow keys, Space and Left Shift,
+    // which no preset uses
+    pub fn bind_game_keys(&mut self, keys: &HashMap<String, u8>) {
+        for (name, key) in keys {
+            let scancode = match name.as_str() {
+                "up" => Scancode::Up,
+                "down" => Scancode::Down,
+                "left" => Scancode::Left,
+                "right" => Scancode::Right,
+                "a" => Scancode::Space,
+                "b" => Scancode::LShift,
+                _ => continue
+            };
+            self.bind_scancode(scancode, *key);
+        }
+    }
+
+    pub fn lookup(&self, scancode: Option<Scancode>, keycode: Option<Keycode>) -> Option<u8> {
+        keycode.and_then(|keycode| self.keycodes.get(&keycode))
+            .or_else(|| scancode.and_then(|scancode| self.scancodes.get(&scancode)))
+            .copied()
+    }
+
+    // Updates the keypad state from a keyboard event. Returns true if the event was consumed. Repeats of a
+    // held key are consumed without pressing it again.
+    pub fn handle_event(&self, event: &Event, input: &mut Input) -> bool {
+        match event {
+            Event::KeyDown{scancode, keycode, repeat: true, ..} => self.lookup(*scancode, *keycode).is_some(),
+            Event::KeyDown{scancode, keycode, ..} => match self.lookup(*scancode, *keycode) {
+                Some(key) => {
+                    input.press(&key);
+                    true
+                },
+                None => false
+            },
+            Event::KeyUp{scancode, keycode, ..} => match self.lookup(*scancode, *keycode) {
+                Some(key) => {
+                    input.release(&key);
+                    true
+                },
+                None => false
+            },
+            _ => false
+        }
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::from_preset(Preset::Qwerty)
+    }
+}
+
+//  COSMAC VIP keypad
+//  1 2 3 C
+//  4 5 6 D
+//  7 8 9 E
+//  A 0 B F
+static QWERTY: [(Scancode, u8); 16] = [
+    (Scancode::Num1, 0x1), (Scancode::Num2, 0x2), (Scancode::Num3, 0x3), (Scancode::Num4, 0xC),
+    (Scancode::Q, 0x4),    (Scancode::W, 0x5),    (Scancode::E, 0x6),    (Scancode::R, 0xD),
+    (Scancode::A, 0x7),    (Scancode::S, 0x8),    (Scancode::D, 0x9),    (Scancode::F, 0xE),
+    (Scancode::Z, 0xA),    (Scancode::X, 0x0),    (Scancode::C, 0xB),    (Scancode::V, 0xF)
+];
+
+// Keeps the keypad shape, so the 8/4/6/2 arrows of the numpad end up on 2/4/6/8 like most games expect
+static NUMPAD: [(Scancode, u8); 16] = [
+    (Scancode::Kp7, 0x1), (Scancode::Kp8, 0x2),      (Scancode::Kp9, 0x3),     (Scancode::KpDivide, 0xC),
+    (Scancode::Kp4, 0x4), (Scancode::Kp5, 0x5),      (Scancode::Kp6, 0x6),     (Scancode::KpMultiply, 0xD),
+    (Scancode::Kp1, 0x7), (Scancode::Kp2, 0x8),      (Scancode::Kp3, 0x9),     (Scancode::KpMinus, 0xE),
+    (Scancode::Kp0, 0xA), (Scancode::KpPeriod, 0x0), (Scancode::KpEnter, 0xB), (Scancode::KpPlus, 0xF)
+];
+
+static COSMAC: [(Scancode, u8); 16] = [
+    (Scancode::Num0, 0x0), (Scancode::Num1, 0x1), (Scancode::Num2, 0x2), (Scancode::Num3, 0x3),
+    (Scancode::Num4, 0x4), (Scancode::Num5, 0x5), (Scancode::Num6, 0x6), (Scancode::Num7, 0x7),
+    (Scancode::Num8, 0x8), (Scancode::Num9, 0x9), (Scancode::A, 0xA),    (Scancode::B, 0xB),
+    (Scancode::C, 0xC),    (Scancode::D, 0xD),    (Scancode::E, 0xE),    (Scancode::F, 0xF)
+];
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::InputDevice;
+
+    fn key_down(scancode: Scancode, keycode: Option<Keycode>) -> Event {
+        Event::KeyDown{timestamp: 0, window_id: 0, keycode, scancode: Some(scancode),
+            keymod: sdl2::keyboard::Mod::NOMOD, repeat: false}
+    }
+
+    fn key_up(scancode: Scancode, keycode: Option<Keycode>) -> Event {
+        Event::KeyUp{timestamp: 0, window_id: 0, keycode, scancode: Some(scancode),
+            keymod: sdl2::keyboard::Mod::NOMOD, repeat: false}
+    }
+
+    #[test]
+    fn test_presets_cover_all_keys() {
+        for preset in [Preset::Qwerty, Preset::Numpad, Preset::Cosmac].iter() {
+            let mut keys: Vec<u8> = preset.bindings().iter().map(|(_, key)| *key).collect();
+            keys.sort();
+            assert_eq!(keys, (0..16).collect::<Vec<u8>>());
+        }
+    }
+
+    #[test]
+    fn test_default_qwerty() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.lookup(Some(Scancode::Num1), None), Some(0x1));
+        assert_eq!(keymap.lookup(Some(Scancode::R), None), Some(0xD));
+        assert_eq!(keymap.lookup(Some(Scancode::X), None), Some(0x0));
+        assert_eq!(keymap.lookup(Some(Scancode::V), None), Some(0xF));
+        assert_eq!(keymap.lookup(Some(Scancode::P), None), None);
+    }
+
+    #[test]
+    fn test_scancode_ignores_layout() {
+        // The key in the position of Q on an AZERTY keyboard reports Keycode::A
+        let keymap = Keymap::default();
+        assert_eq!(keymap.lookup(Some(Scancode::Q), Some(Keycode::A)), Some(0x4));
+    }
+
+    #[test]
+    fn test_keycode_binding_takes_precedence() {
+        let mut keymap = Keymap::default();
+        keymap.bind_keycode(Keycode::A, 0xC);
+        assert_eq!(keymap.lookup(Some(Scancode::Q), Some(Keycode::A)), Some(0xC));
+    }
+
+    #[test]
+    fn test_bind_game_keys() {
+        let mut keymap = Keymap::default();
+        let keys: HashMap<String, u8> = vec![("up".to_string(), 0x2), ("a".to_string(), 0x10), ("player2Up".to_string(), 0x1)]
+            .into_iter().collect();
+        keymap.bind_game_keys(&keys);
+        assert_eq!(keymap.lookup(Some(Scancode::Up), None), Some(0x2));
+        assert_eq!(keymap.lookup(Some(Scancode::Space), None), None);
+        assert_eq!(keymap.lookup(Some(Scancode::W), None), Some(0x5));
+    }
+
+    #[test]
+    fn test_bind_out_of_range() {
+        let mut keymap = Keymap::new();
+        keymap.bind_scancode(Scancode::Space, 0x10);
+        assert_eq!(keymap.lookup(Some(Scancode::Space), None), None);
+    }
+
+    #[test]
+    fn test_preset_from_name() {
+        assert_eq!(Preset::from_name("QWERTY"), Some(Preset::Qwerty));
+        assert_eq!(Preset::from_name("numpad"), Some(Preset::Numpad));
+        assert_eq!(Preset::from_name("hex"), Some(Preset::Cosmac));
+        assert_eq!(Preset::from_name("dvorak"), None);
+    }
+
+    #[test]
+    fn test_handle_event() {
+        let keymap = Keymap::from_preset(Preset::Numpad);
+        let mut input = Input::new();
+        assert!(keymap.handle_event(&key_down(Scancode::Kp8, None), &mut input));
+        assert!(input.is_pressed(&0x2));
+        assert!(!keymap.handle_event(&key_down(Scancode::Num8, None), &mut input));
+        assert!(keymap.handle_event(&key_up(Scancode::Kp8, None), &mut input));
+        assert!(!input.is_pressed(&0x2));
+    }
+
+    #[test]
+    fn test_key_repeat() {
+        let keymap = Keymap::default();
+        let mut input = Input::new();
+        keymap.handle_event(&key_down(Scancode::W, None), &mut input);
+        let repeat = Event::KeyDown{timestamp: 0, window_id: 0, keycode: None, scancode: Some(Scancode::W),
+            keymod: sdl2::keyboard::Mod::NOMOD, repeat: true};
+        assert!(keymap.handle_event(&repeat, &mut input));
+        keymap.handle_event(&key_up(Scancode::W, None), &mut input);
+        assert!(!input.is_pressed(&0x5));
+    }
+}
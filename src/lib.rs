@@ -0,0 +1,38 @@
+extern crate sdl2;
+
+pub mod memory;
+pub mod font;
+pub mod analysis;
+pub mod platform;
+pub mod rom;
+pub mod heatmap;
+pub mod cpu;
+pub mod scheduler;
+pub mod runner;
+pub mod bus;
+pub mod debug;
+pub mod gdb;
+pub mod dap;
+pub mod symbols;
+pub mod graphics;
+pub mod filter;
+pub mod input;
+pub mod keymap;
+pub mod controller;
+pub mod config;
+pub mod database;
+pub mod sound;
+pub mod instructions;
+pub mod profiler;
+pub mod threaded;
+pub mod recompiler;
+pub mod framebuffer;
+pub mod palette;
+pub mod screenshot;
+pub mod recording;
+pub mod conformance;
+pub mod fuzz;
+pub mod watcher;
+pub mod window;
+#[cfg(test)]
+mod mock;
@@ -1,17 +1,364 @@
-extern crate sdl2; 
+use std::env;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::path::Path;
+use std::process;
+use std::time::Instant;
+use chip8::analysis::ControlFlowGraph;
+use chip8::bus::Bus;
+use chip8::config::Config;
+use chip8::conformance::Case;
+use chip8::cpu::{Backend, CPU};
+use chip8::dap::Adapter;
+use chip8::database::{Database, RomSettings};
+use chip8::graphics::Graphics;
+use chip8::input::Input;
+use chip8::memory::{Memory, PROGRAM_START};
+use chip8::profiler::Profiler;
+use chip8::recording;
+use chip8::runner::Runner;
+use chip8::scheduler::Rate;
+use chip8::rom::RomInfo;
+use chip8::palette::Palette;
+use chip8::screenshot::{Format, Screenshot};
+use chip8::sound::Sound;
+use chip8::window::{self, Options};
 
-mod memory;
-mod cpu;
-mod bus;
-mod debug;
-mod graphics;
-mod input;
-mod sound;
-mod instructions;
+const DEFAULT_FRAMES: usize = 600;
+const BENCH_FRAMES: usize = 100_000;
+const INSTRUCTIONS_PER_FRAME: usize = 10;
+const GDB_PORT: u16 = 1234;
+
+static USAGE: &str = "Usage:
+    chip8 run <rom> [--scale N] [--palette NAME] [--out DIR]
+                                                     Play the ROM in a window and reload it when it changes
+    chip8 heatmap <rom> [--frames N] [--tui]         Run the ROM headless and report memory accesses
+    chip8 profile <rom> [--frames N] [--folded FILE] [--backend NAME]
+                                                     Run the ROM headless and report where time is spent
+    chip8 cfg <rom> [--dot FILE]                     Report the control-flow graph of the ROM
+    chip8 bench <rom> [--frames N]                   Time a long headless run on each backend
+    chip8 recompile <rom> [--out FILE]               Translate the ROM into a Rust module
+    chip8 conformance <case.toml>... [--update]      Compare the final screen of test cases with their golden images
+    chip8 screenshot <rom> [--frames N] [--scale N] [--palette NAME] [--out DIR] [--pbm]
+                                                     Run the ROM headless and save the final screen
+    chip8 record <rom> <out.gif|out.y4m> [--frames N] [--from N] [--scale N] [--palette NAME]
+                                                     Run the ROM headless and record frames from N on
+    chip8 gdb <rom> [--port N]                       Wait for GDB on a local port and run the ROM under it
+    chip8 dap [rom] [--record FILE]                  Serve the Debug Adapter Protocol on stdin and stdout
+Only run reloads the ROM when its file changes, restart gdb and dap sessions after a rebuild.";
 
 
 fn main() {
-    println!("Hello World!");
+    let args: Vec<String> = env::args().collect();
+    let result = match args.get(1).map(String::as_str) {
+        Some("run") if args.len() > 2 => run(&args[2..]),
+        Some("heatmap") if args.len() > 2 => heatmap(&args[2..]),
+        Some("profile") if args.len() > 2 => profile(&args[2..]),
+        Some("cfg") if args.len() > 2 => cfg(&args[2..]),
+        Some("bench") if args.len() > 2 => bench(&args[2..]),
+        Some("recompile") if args.len() > 2 => recompile(&args[2..]),
+        Some("conformance") if args.len() > 2 => conformance(&args[2..]),
+        Some("screenshot") if args.len() > 2 => screenshot(&args[2..]),
+        Some("record") if args.len() > 3 => record(&args[2..]),
+        Some("gdb") if args.len() > 2 => gdb(&args[2..]),
+        Some("dap") => dap(&args[2..]),
+        _ => Err(USAGE.to_string())
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+// Value following a flag such as --frames
+fn option<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1))
 }
 
+fn frames(args: &[String], default: usize) -> Result<usize, String> {
+    match option(args, "--frames") {
+        Some(frames) => frames.parse().map_err(|_| format!("invalid frame count '{}'", frames)),
+        None => Ok(default)
+    }
+}
+
+// Platform, quirks and speed come from the ROM database, overridden by the ROM's section in the config
+fn load_settings(config: &Config, path: &str, data: &[u8]) -> Result<RomSettings, String> {
+    let database = match &config.database {
+        Some(database) => Database::load(database).map_err(|err| format!("{}: {}", database.display(), err))?,
+        None => Database::bundled()
+    };
+    let info = RomInfo::new(data);
+    config.rom_settings_for(&rom_key(config, path, data), database.lookup(&info.sha1)).map_err(|err| err.to_string())
+}
 
+// The ROM's section in the config, by hash or by file name
+fn rom_key(config: &Config, path: &str, data: &[u8]) -> String {
+    let name = Path::new(path).file_stem().and_then(|stem| stem.to_str()).unwrap_or(path);
+    config.rom_key(name, &RomInfo::new(data).sha1).to_string()
+}
+
+fn backend(args: &[String]) -> Result<Backend, String> {
+    match option(args, "--backend") {
+        Some(name) => Backend::from_name(name).ok_or_else(|| format!("unknown backend '{}'", name)),
+        None => Ok(Backend::Interpreter)
+    }
+}
+
+fn load_cpu(path: &str) -> Result<(CPU, usize), String> {
+    let (cpu, settings, _) = load_rom(path)?;
+    Ok((cpu, settings.instructions_per_frame.unwrap_or(INSTRUCTIONS_PER_FRAME)))
+}
+
+// Also returns the palette from the config and the ROM database
+fn load_rom(path: &str) -> Result<(CPU, RomSettings, Palette), String> {
+    let data = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+    let config = Config::load_default().map_err(|err| err.to_string())?;
+    let settings = load_settings(&config, path, &data)?;
+    let key = rom_key(&config, path, &data);
+    let palette = config.palette_for(&key, settings.colors.as_ref()).map_err(|err| err.to_string())?;
+    let mut memory = Memory::with_platform(settings.platform);
+    memory.policy = config.memory;
+    let font = config.font_for(&key, settings.platform).map_err(|err| err.to_string())?;
+    memory.set_font(font).map_err(|err| err.to_string())?;
+    memory.load_rom(&data).map_err(|err| format!("{}: {}", path, err))?;
+    let mut cpu = CPU::new(Bus::from_parts(memory, Input::new(), Graphics::new(), Sound::new()));
+    cpu.quirks = settings.quirks;
+    cpu.set_pc(&PROGRAM_START);
+    Ok((cpu, settings, palette))
+}
+
+// Accesses the memory access policy warned about or trapped
+fn report_memory_faults(cpu: &mut CPU) {
+    for warning in cpu.bus.memory.take_warnings() {
+        eprintln!("warning: {}", warning);
+    }
+    if let Some(fault) = cpu.fault() {
+        eprintln!("stopped at {:#05X}: {}", cpu.PC, fault);
+    }
+}
+
+// The window uses the ROM's keypad and controller bindings from the config and the database
+fn run(args: &[String]) -> Result<(), String> {
+    let (mut cpu, settings, palette) = load_rom(&args[0])?;
+    let data = fs::read(&args[0]).map_err(|err| format!("{}: {}", args[0], err))?;
+    let config = Config::load_default().map_err(|err| err.to_string())?;
+    let key = rom_key(&config, &args[0], &data);
+    let scale = match option(args, "--scale") {
+        Some(scale) => scale.parse().ok().filter(|scale| *scale > 0)
+            .ok_or_else(|| format!("invalid scale '{}'", scale))?,
+        None => window::DEFAULT_SCALE
+    };
+    let options = Options {
+        title: settings.title.clone(),
+        scale,
+        rate: Rate::PerFrame(settings.instructions_per_frame.unwrap_or(INSTRUCTIONS_PER_FRAME)),
+        palette: palette_option(args, palette)?,
+        filter: config.filter_for(&key),
+        keymap: config.keymap_for(&key, &settings.keys).map_err(|err| err.to_string())?,
+        controller: config.controller_profile_for(&key, &settings.keys).map_err(|err| err.to_string())?,
+        screenshots: option(args, "--out").map_or_else(|| ".".into(), |out| out.into()),
+        rom: args[0].clone().into()
+    };
+    window::run(&mut cpu, options)?;
+    report_memory_faults(&mut cpu);
+    Ok(())
+}
+
+fn heatmap(args: &[String]) -> Result<(), String> {
+    let tui = args.iter().any(|arg| arg == "--tui");
+    let frames = frames(args, DEFAULT_FRAMES)?;
+    let (mut cpu, instructions_per_frame) = load_cpu(&args[0])?;
+    cpu.bus.memory.enable_tracking();
+    for _ in 0..frames {
+        cpu.run_frame(instructions_per_frame);
+    }
+    report_memory_faults(&mut cpu);
+    let heatmap = cpu.bus.memory.heatmap().unwrap();
+    if tui {
+        chip8::debug::show_heatmap(&heatmap).map_err(|err| err.to_string())
+    } else {
+        println!("Memory heatmap for {} after {} frames\n", args[0], frames);
+        print!("{}", heatmap.report());
+        Ok(())
+    }
+}
+
+fn profile(args: &[String]) -> Result<(), String> {
+    let frames = frames(args, DEFAULT_FRAMES)?;
+    let (mut cpu, instructions_per_frame) = load_cpu(&args[0])?;
+    cpu.set_backend(backend(args)?);
+    cpu.profiler = Some(Profiler::new());
+    for _ in 0..frames {
+        cpu.run_frame(instructions_per_frame);
+    }
+    report_memory_faults(&mut cpu);
+    let profiler = cpu.profiler.take().unwrap();
+    if let Some(path) = option(args, "--folded") {
+        fs::write(path, profiler.folded()).map_err(|err| format!("could not write {}: {}", path, err))?;
+    }
+    println!("Profile of {} after {} frames\n", args[0], frames);
+    print!("{}", profiler.report());
+    Ok(())
+}
+
+fn cfg(args: &[String]) -> Result<(), String> {
+    let data = fs::read(&args[0]).map_err(|err| format!("{}: {}", args[0], err))?;
+    let graph = ControlFlowGraph::build(&data, PROGRAM_START);
+    if let Some(path) = option(args, "--dot") {
+        fs::write(path, graph.to_dot()).map_err(|err| format!("could not write {}: {}", path, err))?;
+    }
+    println!("Control flow of {}\n", args[0]);
+    print!("{}", graph.report());
+    Ok(())
+}
+
+// Runs the ROM from the same start without the decode cache, with it, and on the threaded backend
+fn bench(args: &[String]) -> Result<(), String> {
+    let frames = frames(args, BENCH_FRAMES)?;
+    let mut baseline = None;
+    for (name, cached, backend) in &[("uncached", false, Backend::Interpreter), ("cached", true, Backend::Interpreter),
+                                     ("threaded", true, Backend::Threaded)] {
+        let (mut cpu, instructions_per_frame) = load_cpu(&args[0])?;
+        if !cached {
+            cpu.bus.memory.disable_cache();
+        }
+        cpu.set_backend(*backend);
+        let start = Instant::now();
+        for _ in 0..frames {
+            cpu.run_frame(instructions_per_frame);
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        let rate = (frames * instructions_per_frame) as f64 / elapsed;
+        let baseline = *baseline.get_or_insert(rate);
+        println!("{:<10} {:>10.3} s {:>14.0} instructions/s {:>7.2}x", name, elapsed, rate, rate / baseline);
+    }
+    Ok(())
+}
+
+fn recompile(args: &[String]) -> Result<(), String> {
+    let data = fs::read(&args[0]).map_err(|err| format!("{}: {}", args[0], err))?;
+    let name = Path::new(&args[0]).file_name().and_then(|name| name.to_str()).unwrap_or(&args[0]);
+    let source = chip8::recompiler::recompile(&data, PROGRAM_START, name);
+    match option(args, "--out") {
+        Some(path) => fs::write(path, source).map_err(|err| format!("could not write {}: {}", path, err)),
+        None => {
+            print!("{}", source);
+            Ok(())
+        }
+    }
+}
+
+fn conformance(args: &[String]) -> Result<(), String> {
+    let update = args.iter().any(|arg| arg == "--update");
+    let mut failures = 0;
+    for path in args.iter().filter(|arg| !arg.starts_with("--")) {
+        match Case::load(Path::new(path)).and_then(|case| case.check(update)) {
+            Ok(outcome) => println!("ok    {} ({} frames{})", path, outcome.frames, if outcome.halted {", halted"} else {""}),
+            Err(err) => {
+                println!("FAIL  {}: {}", path, err);
+                failures += 1;
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(format!("{} test cases failed", failures));
+    }
+    Ok(())
+}
+
+// --palette wins over the config and the ROM database
+fn palette_option(args: &[String], palette: Palette) -> Result<Palette, String> {
+    match option(args, "--palette") {
+        Some(name) => Palette::from_name(name)
+            .ok_or_else(|| format!("unknown palette '{}', expected one of {}", name, Palette::names().join(", "))),
+        None => Ok(palette)
+    }
+}
+
+// The scale and palette of screenshots and recordings
+fn screenshot_options(args: &[String], settings: &RomSettings, palette: Palette) -> Result<Screenshot, String> {
+    let mut screenshot = Screenshot {rom: Some(settings.title.clone()), palette: palette_option(args, palette)?,
+                                     ..Screenshot::default()};
+    if let Some(scale) = option(args, "--scale") {
+        screenshot.scale = scale.parse().map_err(|_| format!("invalid scale '{}'", scale))?;
+    }
+    Ok(screenshot)
+}
+
+fn screenshot(args: &[String]) -> Result<(), String> {
+    let frames = frames(args, DEFAULT_FRAMES)?;
+    let (mut cpu, settings, palette) = load_rom(&args[0])?;
+    let screenshot = screenshot_options(args, &settings, palette)?;
+    let format = if args.iter().any(|arg| arg == "--pbm") {Format::Pbm} else {Format::Png};
+    for _ in 0..frames {
+        cpu.run_frame(settings.instructions_per_frame.unwrap_or(INSTRUCTIONS_PER_FRAME));
+    }
+    report_memory_faults(&mut cpu);
+    let directory = Path::new(option(args, "--out").map_or(".", String::as_str));
+    let path = screenshot.save(&cpu.bus.graphics, directory, format).map_err(|err| err.to_string())?;
+    println!("{}", path.display());
+    Ok(())
+}
+
+fn record(args: &[String]) -> Result<(), String> {
+    let frames = frames(args, DEFAULT_FRAMES)?;
+    let from = match option(args, "--from") {
+        Some(from) => from.parse().map_err(|_| format!("invalid frame '{}'", from))?,
+        None => 0
+    };
+    let (mut cpu, settings, palette) = load_rom(&args[0])?;
+    let options = screenshot_options(args, &settings, palette)?;
+    let path = Path::new(&args[1]);
+    let mut recorder = None;
+    for frame in 0..frames {
+        cpu.run_frame(settings.instructions_per_frame.unwrap_or(INSTRUCTIONS_PER_FRAME));
+        if frame == from {
+            recorder = Some(recording::create(path, options.palette.clone(), options.scale).map_err(|err| err.to_string())?);
+        }
+        if let Some(recorder) = &mut recorder {
+            recorder.frame(&cpu.bus.graphics).map_err(|err| err.to_string())?;
+        }
+    }
+    report_memory_faults(&mut cpu);
+    match recorder {
+        Some(recorder) => {
+            let recorded = recorder.frames();
+            recorder.finish().map_err(|err| err.to_string())?;
+            println!("Recorded {} frames to {}", recorded, path.display());
+            Ok(())
+        },
+        None => Err(format!("the ROM ran for {} frames, recording starts at frame {}", frames, from))
+    }
+}
+
+fn gdb(args: &[String]) -> Result<(), String> {
+    let port = match option(args, "--port") {
+        Some(port) => port.parse().map_err(|_| format!("invalid port '{}'", port))?,
+        None => GDB_PORT
+    };
+    let (mut cpu, instructions_per_frame) = load_cpu(&args[0])?;
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|err| format!("could not listen on port {}: {}", port, err))?;
+    println!("Waiting for GDB on 127.0.0.1:{}", port);
+    let (stream, address) = listener.accept().map_err(|err| err.to_string())?;
+    println!("Debugger connected from {}", address);
+    chip8::gdb::serve(&mut cpu, Runner::new(instructions_per_frame), stream).map_err(|err| err.to_string())?;
+    report_memory_faults(&mut cpu);
+    Ok(())
+}
+
+// Launch requests load through the config like every other command. A ROM given here is what attach
+// requests attach to.
+fn dap(args: &[String]) -> Result<(), String> {
+    let mut adapter = Adapter::new(io::stdout(), Box::new(|path: &Path| load_cpu(&path.to_string_lossy())));
+    if let Some(path) = args.first().filter(|arg| !arg.starts_with("--")) {
+        let (cpu, instructions_per_frame) = load_cpu(path)?;
+        adapter.attach_to(cpu, instructions_per_frame);
+    }
+    if let Some(path) = option(args, "--record") {
+        let transcript = fs::File::create(path).map_err(|err| format!("{}: {}", path, err))?;
+        adapter.record(Box::new(io::LineWriter::new(transcript)));
+    }
+    chip8::dap::serve(io::BufReader::new(io::stdin()), &mut adapter).map_err(|err| err.to_string())
+}
@@ -1,87 +1,371 @@
-use std::convert::TryInto;
-use byteorder::{BigEndian, ByteOrder};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use crate::font::{Font, FontError, FontSet};
+use crate::heatmap::{AccessTracker, Heatmap};
+use crate::instructions::Instruction;
+use crate::platform::Platform;
+use crate::rom::{RomError, RomInfo};
 
 
-static FONT_START: u16 = 0;
-static FONT_SIZE: u8 = 5;
+pub const PROGRAM_START: u16 = 0x200;
+const MAX_WARNINGS: usize = 256;
+
+// How a runtime access the program should not make is handled
+#[derive(Debug, PartialEq, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    #[default]
+    Allow,      // As on the original interpreter, addresses past the end of memory wrap around
+    Warn,       // Allowed, and recorded in the warnings
+    Trap        // Refused, the CPU stops on the instruction
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default, Deserialize)]
+pub struct AccessPolicy {
+    #[serde(default)]
+    pub reserved_writes: Access,    // Writes below the program start, where the font and the interpreter live
+    #[serde(default)]
+    pub out_of_range: Access        // I based reads and writes past the end of the platform's memory
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MemoryFault {
+    ReservedWrite{address: u16},
+    OutOfRange{address: u16, write: bool}
+}
+
+impl fmt::Display for MemoryFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryFault::ReservedWrite{address} => write!(f, "write to reserved address {:#05X}", address),
+            MemoryFault::OutOfRange{address, write: true} => write!(f, "write past the end of memory at {:#06X}", address),
+            MemoryFault::OutOfRange{address, write: false} => write!(f, "read past the end of memory at {:#06X}", address)
+        }
+    }
+}
+
+pub trait MemoryDevice {
+    fn read(&self, address: &u16) -> u8;
+
+    fn write(&mut self, address: &u16, data: &u8);
+
+    fn read_instruction(&self, address: &u16) -> u16;
+
+    fn decode_instruction(&self, address: &u16) -> Instruction {
+        Instruction::fetch_opcode(&self.read_instruction(address))
+    }
+
+    // Decodes without counting an execution, for looking at instructions that may not run
+    fn peek_instruction(&self, address: &u16) -> Instruction {
+        self.decode_instruction(address)
+    }
+
+    // Counts an execution of an instruction decoded with peek_instruction
+    fn record_execute(&self, _address: &u16) {}
+
+    fn get_font_address(&self, font: &u8) -> u16;
+
+    // None where the font has no large glyph for the digit
+    fn get_large_font_address(&self, _font: &u8) -> Option<u16> {
+        None
+    }
+
+    // Number of addressable bytes
+    fn size(&self) -> usize;
+
+    // The access refused by the access policy since the last call, if any
+    fn take_fault(&mut self) -> Option<MemoryFault> {
+        None
+    }
+
+    // Whether the policy lets every access to the length bytes from start go ahead, for instructions that
+    // must be refused as a whole. The first refused access becomes the fault.
+    fn check_range(&self, _start: &u16, _length: usize, _write: bool) -> bool {
+        true
+    }
+}
 
 pub struct Memory {
-    pub memory: [u8; 4096]
+    pub memory: Vec<u8>,                        // Sized for the platform
+    pub platform: Platform,
+    pub rom: Option<RomInfo>,                   // The ROM loaded with load_rom
+    font: Font,
+    tracker: Option<RefCell<AccessTracker>>,    // Access counters, only kept while tracking is enabled
+    cache: Option<Vec<Cell<Option<Instruction>>>>, // Decoded instruction per address, cleared by writes. Writing
+                                                // to the memory field directly bypasses it.
+    pub policy: AccessPolicy,
+    fault: Cell<Option<MemoryFault>>,           // First access refused since the last take_fault
+    warnings: RefCell<Vec<MemoryFault>>         // Distinct accesses allowed with a warning
 }
 
 impl Memory {
 
     pub fn new() -> Memory {
+        Memory::with_platform(Platform::Chip8)
+    }
+
+    pub fn with_platform(platform: Platform) -> Memory {
         let mut memory = Memory{
-            memory: [0; 4096]
+            memory: vec![0; platform.memory_size()],
+            platform,
+            rom: None,
+            font: Font::new(FontSet::for_platform(platform)),
+            tracker: None,
+            cache: None,
+            policy: AccessPolicy::default(),
+            fault: Cell::new(None),
+            warnings: RefCell::new(Vec::new())
         };
+        memory.enable_cache();
         memory.load_fonts();
         memory
     }
 
-    pub fn mock() -> Memory {
-        Memory {
-            memory: [0; 4096]
+    pub fn load_fonts(&mut self) {
+        let address = self.font.address;
+        let small = self.font.small.clone();
+        let large = self.font.large.clone();
+        self.load_data(&address, &small);
+        self.load_data(&(address + small.len() as u16), &large);
+        self.clear_cache();
+    }
+
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    // Replaces the loaded font, clearing the bytes of the previous one
+    pub fn set_font(&mut self, font: Font) -> Result<(), FontError> {
+        font.check_fits(self.platform)?;
+        let start = self.font.address as usize;
+        self.memory[start..start + self.font.len()].iter_mut().for_each(|byte| *byte = 0);
+        self.font = font;
+        self.load_fonts();
+        Ok(())
+    }
+
+    // Loading is not a runtime write, so it bypasses access tracking. Data past the end of memory is dropped.
+    pub fn load_data(&mut self, start_address: &u16, data: &[u8]) {
+        let start = (*start_address as usize).min(self.memory.len());
+        let length = data.len().min(self.memory.len() - start);
+        self.memory[start..start + length].copy_from_slice(&data[..length]);
+        self.clear_cache();
+    }
+
+    // Replaces the program space with the ROM
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<RomInfo, RomError> {
+        let max = self.platform.max_rom_size();
+        if data.is_empty() {
+            return Err(RomError::Empty);
         }
+        if data.len() > max {
+            return Err(RomError::TooLarge{size: data.len(), max});
+        }
+        let start = self.platform.program_start() as usize;
+        for byte in self.memory[start..].iter_mut() {
+            *byte = 0;
+        }
+        self.memory[start..start + data.len()].copy_from_slice(data);
+        self.clear_cache();
+        let info = RomInfo::new(data);
+        self.rom = Some(info.clone());
+        Ok(info)
     }
-    
-    pub fn read(&self, address: &u16) -> u8 {
-        self.memory[*address as usize]
+
+    // Clears memory back to just the font, along with the ROM info, access counters and pending faults
+    pub fn reset(&mut self) {
+        self.memory.iter_mut().for_each(|byte| *byte = 0);
+        self.load_fonts();
+        self.rom = None;
+        if self.tracker.is_some() {
+            self.enable_tracking();
+        }
+        self.fault.set(None);
+        self.warnings.borrow_mut().clear();
     }
 
-    pub fn write(&mut self, address: &u16, data: &u8) {
-        self.memory[*address as usize] = *data;
+    pub fn load_rom_file(&mut self, path: &Path) -> Result<RomInfo, RomError> {
+        self.load_rom(&fs::read(path)?)
     }
 
-    pub fn load_fonts(&mut self) {
-        let mut i: usize = FONT_START.try_into().unwrap();
-        while i < FONT_SET.len(){
-            self.memory[i] = FONT_SET[i];
-            i += 1;
+    pub fn enable_tracking(&mut self) {
+        self.tracker = Some(RefCell::new(AccessTracker::new(self.memory.len())));
+    }
+
+    pub fn disable_tracking(&mut self) {
+        self.tracker = None;
+    }
+
+    pub fn enable_cache(&mut self) {
+        self.cache = Some(vec![Cell::new(None); self.memory.len()]);
+    }
+
+    pub fn disable_cache(&mut self) {
+        self.cache = None;
+    }
+
+    fn clear_cache(&mut self) {
+        if let Some(cache) = &self.cache {
+            cache.iter().for_each(|entry| entry.set(None));
         }
     }
 
-    pub fn load_data(&mut self, start_address: &u16, data: &Vec<u8>) {
-        let mut address: u16 = *start_address;
-        for i in 0..data.len() {
-            self.write(&address, &data[i]);
-            address += 1;
+    pub fn heatmap(&self) -> Option<Heatmap> {
+        self.tracker.as_ref().map(|tracker| tracker.borrow().heatmap())
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<MemoryFault> {
+        self.warnings.replace(Vec::new())
+    }
+
+    // Addresses wrap around at the end of memory
+    fn wrap(&self, address: &u16) -> u16 {
+        (*address as usize % self.memory.len()) as u16
+    }
+
+    // Whether the access goes ahead
+    fn check(&self, access: Access, fault: MemoryFault) -> bool {
+        match access {
+            Access::Allow => true,
+            Access::Warn => {
+                let mut warnings = self.warnings.borrow_mut();
+                if warnings.len() < MAX_WARNINGS && !warnings.contains(&fault) {
+                    warnings.push(fault);
+                }
+                true
+            },
+            Access::Trap => {
+                if self.fault.get().is_none() {
+                    self.fault.set(Some(fault));
+                }
+                false
+            }
         }
     }
 
-    pub fn get_font_address(&self, font: &u8) -> u16 {
-        FONT_START + *font as u16 * FONT_SIZE as u16
+    // The fault an access would trap on, without recording it
+    fn trap_for(&self, address: &u16, write: bool) -> Option<MemoryFault> {
+        if *address as usize >= self.memory.len() && self.policy.out_of_range == Access::Trap {
+            return Some(MemoryFault::OutOfRange{address: *address, write});
+        }
+        // Other accesses wrap around before the reserved area is checked, as in write
+        let address = self.wrap(address);
+        let refused = write && address < self.platform.program_start() && self.policy.reserved_writes == Access::Trap;
+        refused.then_some(MemoryFault::ReservedWrite{address})
     }
 
-    pub fn read_instruction(&self, address: &u16) -> u16 {
-        BigEndian::read_u16(&self.memory[*address as usize..])
+    // Whether an access past the end of memory is refused
+    fn refuse_out_of_range(&self, address: &u16, write: bool) -> bool {
+        *address as usize >= self.memory.len() &&
+            !self.check(self.policy.out_of_range, MemoryFault::OutOfRange{address: *address, write})
     }
+}
 
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
 }
 
-static FONT_SET: [u8; 80] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0,  // 0
-    0x20, 0x60, 0x20, 0x20, 0x70,  // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0,  // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0,  // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10,  // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0,  // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0,  // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40,  // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0,  // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0,  // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90,  // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0,  // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0,  // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0,  // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0,  // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80   // F
-];
+impl MemoryDevice for Memory {
+    // Refused reads return 0
+    fn read(&self, address: &u16) -> u8 {
+        if self.refuse_out_of_range(address, false) {
+            return 0;
+        }
+        let address = self.wrap(address);
+        if let Some(tracker) = &self.tracker {
+            tracker.borrow_mut().read(&address);
+        }
+        self.memory[address as usize]
+    }
+
+    fn write(&mut self, address: &u16, data: &u8) {
+        if self.refuse_out_of_range(address, true) {
+            return;
+        }
+        let address = self.wrap(address);
+        if address < self.platform.program_start() &&
+            !self.check(self.policy.reserved_writes, MemoryFault::ReservedWrite{address}) {
+            return;
+        }
+        if let Some(tracker) = &self.tracker {
+            tracker.borrow_mut().write(&address);
+        }
+        self.memory[address as usize] = *data;
+        // The byte is the low half of the instruction before it and the high half of its own
+        if let Some(cache) = &self.cache {
+            cache[address as usize].set(None);
+            cache[(address as usize + cache.len() - 1) % cache.len()].set(None);
+        }
+    }
+
+    // Instruction fetches are counted as executions rather than reads. An instruction at the last address
+    // takes its low byte from the first.
+    fn read_instruction(&self, address: &u16) -> u16 {
+        self.record_execute(address);
+        let address = self.wrap(address);
+        let low = self.memory[(address as usize + 1) % self.memory.len()];
+        (self.memory[address as usize] as u16) << 8 | low as u16
+    }
+
+    fn decode_instruction(&self, address: &u16) -> Instruction {
+        self.record_execute(address);
+        self.peek_instruction(address)
+    }
+
+    fn peek_instruction(&self, address: &u16) -> Instruction {
+        let address = self.wrap(address) as usize;
+        let opcode = || (self.memory[address] as u16) << 8 | self.memory[(address + 1) % self.memory.len()] as u16;
+        let entry = match &self.cache {
+            Some(cache) => &cache[address],
+            None => return Instruction::fetch_opcode(&opcode())
+        };
+        entry.get().unwrap_or_else(|| {
+            let ins = Instruction::fetch_opcode(&opcode());
+            entry.set(Some(ins));
+            ins
+        })
+    }
+
+    fn record_execute(&self, address: &u16) {
+        if let Some(tracker) = &self.tracker {
+            tracker.borrow_mut().execute(&self.wrap(address));
+        }
+    }
+
+    fn get_font_address(&self, font: &u8) -> u16 {
+        self.font.small_glyph_address(*font)
+    }
+
+    fn get_large_font_address(&self, font: &u8) -> Option<u16> {
+        self.font.large_glyph_address(*font)
+    }
+
+    fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    fn take_fault(&mut self) -> Option<MemoryFault> {
+        self.fault.take()
+    }
+
+    fn check_range(&self, start: &u16, length: usize, write: bool) -> bool {
+        match (0..length).find_map(|i| self.trap_for(&start.wrapping_add(i as u16), write)) {
+            Some(fault) => self.check(Access::Trap, fault),
+            None => true
+        }
+    }
+}
 
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::heatmap::CodeEvent;
 
     #[test]
     fn test_write_read_write() {
@@ -99,6 +383,19 @@ mod test {
         assert_eq!(memory.read(&17), 0xF0);
     }
 
+    #[test]
+    fn test_reset() {
+        let mut memory = Memory::new();
+        memory.enable_tracking();
+        memory.load_rom(&[0x12, 0x00]).unwrap();
+        memory.read(&0x200);
+        memory.reset();
+        assert_eq!(memory.rom, None);
+        assert_eq!(memory.read(&0x200), 0);
+        assert_eq!(memory.read(&0), 0xF0);
+        assert_eq!(memory.heatmap().unwrap().total(0x200), 1);
+    }
+
     #[test]
     fn test_font_address() {
         let memory = Memory::new();
@@ -118,6 +415,26 @@ mod test {
         assert_eq!(memory.get_font_address(&0xD), 65);
         assert_eq!(memory.get_font_address(&0xE), 70);
         assert_eq!(memory.get_font_address(&0xF), 75);
+        assert_eq!(memory.get_large_font_address(&0x0), None);
+    }
+
+    #[test]
+    fn test_set_font() {
+        let mut memory = Memory::with_platform(Platform::SuperChip);
+        assert_eq!(memory.get_large_font_address(&0x9), Some(170));
+        assert_eq!(memory.get_large_font_address(&0xA), None);
+        let mut font = Font::new(FontSet::Octo);
+        font.address = 0x50;
+        memory.set_font(font).unwrap();
+        assert_eq!(memory.read(&0), 0x00);
+        assert_eq!(memory.read(&0x50), 0xF0);
+        assert_eq!(memory.get_font_address(&0x1), 0x55);
+        assert_eq!(memory.get_large_font_address(&0xA), Some(0x50 + 80 + 100));
+        assert_eq!(memory.read(&(0x50 + 80)), 0xFF);
+        let mut font = Font::new(FontSet::Octo);
+        font.address = 0x180;
+        assert!(matches!(memory.set_font(font), Err(FontError::DoesNotFit{..})));
+        assert_eq!(memory.font().address, 0x50);
     }
 
     #[test]
@@ -131,6 +448,190 @@ mod test {
         assert_eq!(memory.read(&0x203), 0xEF);
     }
 
+    #[test]
+    fn test_load_data_past_end() {
+        let mut memory = Memory::new();
+        memory.load_data(&0xFFE, &[0x01, 0x02, 0x03]);
+        memory.load_data(&0xFFFF, &[0x04]);
+        assert_eq!(memory.memory[0xFFE..], [0x01, 0x02]);
+        assert_eq!(memory.memory[0], 0xF0);
+    }
+
+    #[test]
+    fn test_addresses_wrap() {
+        let mut memory = Memory::new();
+        memory.write(&0x1200, &0xAB);
+        assert_eq!(memory.read(&0x200), 0xAB);
+        memory.write(&0xFFF, &0x12);
+        assert_eq!(memory.read_instruction(&0xFFF), 0x12F0);
+        memory.write(&0, &0x34);
+        assert_eq!(memory.decode_instruction(&0xFFF), Instruction::Jump{address: 0x234});
+    }
+
+    #[test]
+    fn test_load_rom() {
+        let mut memory = Memory::new();
+        let info = memory.load_rom(&[0x00, 0xE0, 0x12, 0x00]).unwrap();
+        assert_eq!(info.length, 4);
+        assert_eq!(info.sha1.len(), 40);
+        assert_eq!(memory.rom, Some(info));
+        assert_eq!(memory.read_instruction(&PROGRAM_START), 0x00E0);
+    }
+
+    #[test]
+    fn test_load_rom_clears_previous_rom() {
+        let mut memory = Memory::new();
+        memory.load_rom(&[0xAA; 16]).unwrap();
+        memory.load_rom(&[0xBB; 2]).unwrap();
+        assert_eq!(memory.read(&0x201), 0xBB);
+        assert_eq!(memory.read(&0x202), 0x00);
+        assert_eq!(memory.read(&0x000), 0xF0);
+    }
+
+    #[test]
+    fn test_load_empty_rom() {
+        let mut memory = Memory::new();
+        match memory.load_rom(&[]) {
+            Err(RomError::Empty) => {},
+            _ => panic!("expected an empty ROM error")
+        }
+        assert_eq!(memory.rom, None);
+    }
+
+    #[test]
+    fn test_load_rom_too_large() {
+        let mut memory = Memory::new();
+        assert!(memory.load_rom(&[0; 3584]).is_ok());
+        match memory.load_rom(&[0; 3585]) {
+            Err(RomError::TooLarge{size, max}) => assert_eq!((size, max), (3585, 3584)),
+            _ => panic!("expected a ROM too large error")
+        }
+    }
+
+    #[test]
+    fn test_load_rom_extended_platform() {
+        let mut memory = Memory::with_platform(Platform::XoChip);
+        assert!(memory.load_rom(&[0; 3585]).is_ok());
+        assert!(memory.load_rom(&[0; 0x10000 - 0x200]).is_ok());
+    }
+
+    #[test]
+    fn test_load_rom_file() {
+        let mut memory = Memory::new();
+        match memory.load_rom_file(Path::new("/nonexistent/rom.ch8")) {
+            Err(RomError::Io(_)) => {},
+            _ => panic!("expected an io error")
+        }
+    }
+
+    #[test]
+    fn test_tracking() {
+        let mut memory = Memory::new();
+        memory.load_data(&0x200, &[0x00, 0xE0]);
+        assert!(memory.heatmap().is_none());
+        memory.enable_tracking();
+        memory.read_instruction(&0x200);
+        memory.read(&0x300);
+        memory.write(&0x201, &0xEE);
+        memory.read_instruction(&0x200);
+        let heatmap = memory.heatmap().unwrap();
+        assert_eq!(heatmap.executes[0x200], 2);
+        assert_eq!(heatmap.reads[0x300], 1);
+        assert_eq!(heatmap.writes[0x201], 1);
+        assert_eq!(heatmap.events, vec![CodeEvent::WriteToCode{address: 0x201, pc: 0x200},
+                                        CodeEvent::SelfModifyingCode{pc: 0x200}]);
+    }
+
+    #[test]
+    fn test_load_is_not_tracked() {
+        let mut memory = Memory::new();
+        memory.enable_tracking();
+        memory.load_data(&0x200, &[0x00, 0xE0]);
+        assert_eq!(memory.heatmap().unwrap().writes[0x200], 0);
+    }
+
+    #[test]
+    fn test_decode_instruction_cached() {
+        let mut memory = Memory::new();
+        memory.load_data(&0x200, &[0x00, 0xE0, 0x12, 0x00]);
+        assert_eq!(memory.decode_instruction(&0x200), Instruction::ClearDisplay);
+        memory.write(&0x201, &0xEE);
+        assert_eq!(memory.decode_instruction(&0x200), Instruction::Return);
+        assert_eq!(memory.decode_instruction(&0x202), Instruction::Jump{address: 0x200});
+        memory.write(&0x202, &0x13);
+        assert_eq!(memory.decode_instruction(&0x202), Instruction::Jump{address: 0x300});
+    }
+
+    #[test]
+    fn test_load_clears_cache() {
+        let mut memory = Memory::new();
+        memory.load_rom(&[0x00, 0xE0]).unwrap();
+        memory.decode_instruction(&0x200);
+        memory.load_rom(&[0x00, 0xEE]).unwrap();
+        assert_eq!(memory.decode_instruction(&0x200), Instruction::Return);
+    }
+
+    #[test]
+    fn test_cached_instructions_are_tracked() {
+        let mut memory = Memory::new();
+        memory.enable_tracking();
+        memory.decode_instruction(&0x200);
+        memory.decode_instruction(&0x200);
+        assert_eq!(memory.heatmap().unwrap().executes[0x200], 2);
+    }
+
+    #[test]
+    fn test_peek_instruction_is_not_tracked() {
+        let mut memory = Memory::new();
+        memory.enable_tracking();
+        memory.load_data(&0x200, &[0x00, 0xEE]);
+        assert_eq!(memory.peek_instruction(&0x200), Instruction::Return);
+        assert_eq!(memory.heatmap().unwrap().executes[0x200], 0);
+        memory.record_execute(&0x200);
+        assert_eq!(memory.heatmap().unwrap().executes[0x200], 1);
+    }
+
+    #[test]
+    fn test_policy_allow() {
+        let mut memory = Memory::new();
+        memory.write(&0x100, &0x12);
+        memory.write(&0x1300, &0x34);
+        assert_eq!((memory.read(&0x100), memory.read(&0x300)), (0x12, 0x34));
+        assert_eq!(memory.take_fault(), None);
+        assert!(memory.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_policy_warn() {
+        let mut memory = Memory::new();
+        memory.policy = AccessPolicy{reserved_writes: Access::Warn, out_of_range: Access::Warn};
+        memory.write(&0x100, &0x12);
+        memory.write(&0x100, &0x13);
+        assert_eq!(memory.read(&0x1100), 0x13);
+        assert_eq!(memory.take_fault(), None);
+        assert_eq!(memory.take_warnings(), vec![MemoryFault::ReservedWrite{address: 0x100},
+                                                MemoryFault::OutOfRange{address: 0x1100, write: false}]);
+        assert!(memory.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_policy_trap() {
+        let mut memory = Memory::new();
+        memory.policy = AccessPolicy{reserved_writes: Access::Trap, out_of_range: Access::Trap};
+        memory.write(&0x001, &0x00);
+        assert_eq!(memory.read(&0x001), 0x90);
+        assert_eq!(memory.take_fault(), Some(MemoryFault::ReservedWrite{address: 0x001}));
+        assert_eq!(memory.read(&0x1000), 0);
+        memory.write(&0x1200, &0x12);
+        assert_eq!(memory.read(&0x200), 0);
+        // Only the first refused access is kept
+        assert_eq!(memory.take_fault(), Some(MemoryFault::OutOfRange{address: 0x1000, write: false}));
+        assert_eq!(memory.take_fault(), None);
+        // Loading is not a runtime access
+        memory.load_data(&0x000, &[0xFF]);
+        assert_eq!(memory.take_fault(), None);
+    }
+
     #[test]
     fn test_read_instruction() {
         let mut memory = Memory::new();
@@ -0,0 +1,139 @@
+// Component implementations for tests that need to observe or interfere with what the CPU does
+use std::collections::HashMap;
+use crate::memory::{Memory, MemoryDevice};
+use crate::graphics::GraphicsDevice;
+use crate::sound::SoundDevice;
+
+// Real memory that also records every write in order
+pub struct RecordingMemory {
+    pub memory: Memory,
+    pub writes: Vec<(u16, u8)>
+}
+
+impl RecordingMemory {
+    pub fn new() -> RecordingMemory {
+        RecordingMemory {
+            memory: Memory::new(),
+            writes: Vec::new()
+        }
+    }
+}
+
+impl MemoryDevice for RecordingMemory {
+    fn read(&self, address: &u16) -> u8 {
+        self.memory.read(address)
+    }
+
+    fn write(&mut self, address: &u16, data: &u8) {
+        self.writes.push((*address, *data));
+        self.memory.write(address, data);
+    }
+
+    fn read_instruction(&self, address: &u16) -> u16 {
+        self.memory.read_instruction(address)
+    }
+
+    fn get_font_address(&self, font: &u8) -> u16 {
+        self.memory.get_font_address(font)
+    }
+
+    fn get_large_font_address(&self, font: &u8) -> Option<u16> {
+        self.memory.get_large_font_address(font)
+    }
+
+    fn size(&self) -> usize {
+        self.memory.size()
+    }
+}
+
+// Real memory with stuck cells that always read back a fixed value and ignore writes
+pub struct FaultyMemory {
+    pub memory: Memory,
+    pub stuck: HashMap<u16, u8>
+}
+
+impl FaultyMemory {
+    pub fn new() -> FaultyMemory {
+        FaultyMemory {
+            memory: Memory::new(),
+            stuck: HashMap::new()
+        }
+    }
+}
+
+impl MemoryDevice for FaultyMemory {
+    fn read(&self, address: &u16) -> u8 {
+        match self.stuck.get(address) {
+            Some(value) => *value,
+            None => self.memory.read(address)
+        }
+    }
+
+    fn write(&mut self, address: &u16, data: &u8) {
+        if !self.stuck.contains_key(address) {
+            self.memory.write(address, data);
+        }
+    }
+
+    fn read_instruction(&self, address: &u16) -> u16 {
+        (self.read(address) as u16) << 8 | self.read(&(address + 1)) as u16
+    }
+
+    fn get_font_address(&self, font: &u8) -> u16 {
+        self.memory.get_font_address(font)
+    }
+
+    fn get_large_font_address(&self, font: &u8) -> Option<u16> {
+        self.memory.get_large_font_address(font)
+    }
+
+    fn size(&self) -> usize {
+        self.memory.size()
+    }
+}
+
+// Records draw calls instead of drawing, reports a collision for every sprite while collide is set
+pub struct RecordingGraphics {
+    pub clears: usize,
+    pub sprites: Vec<(u8, u8, Vec<u8>)>,
+    pub collide: bool
+}
+
+impl RecordingGraphics {
+    pub fn new() -> RecordingGraphics {
+        RecordingGraphics {
+            clears: 0,
+            sprites: Vec::new(),
+            collide: false
+        }
+    }
+}
+
+impl GraphicsDevice for RecordingGraphics {
+    fn clear(&mut self) {
+        self.clears += 1;
+    }
+
+    fn draw_sprite(&mut self, x: &u8, y: &u8, sprite: &[u8], _wrap: bool) -> bool {
+        self.sprites.push((*x, *y, sprite.to_vec()));
+        self.collide
+    }
+}
+
+pub struct RecordingSound {
+    pub changes: Vec<bool>
+}
+
+impl RecordingSound {
+    pub fn new() -> RecordingSound {
+        RecordingSound {
+            changes: Vec::new()
+        }
+    }
+}
+
+impl SoundDevice for RecordingSound {
+    fn set_playing(&mut self, playing: bool) {
+        self.changes.push(playing);
+    }
+}
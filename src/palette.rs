@@ -0,0 +1,157 @@
+use std::fmt;
+use serde::Deserialize;
+
+
+pub type Rgb = [u8; 3];
+
+#[derive(Debug, PartialEq)]
+pub enum PaletteError {
+    Unknown(String),
+    InvalidColor(String),
+    InvalidSize(usize)
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteError::Unknown(name) => write!(f, "unknown palette '{}'", name),
+            PaletteError::InvalidColor(color) => write!(f, "invalid color '{}', expected #RRGGBB", color),
+            PaletteError::InvalidSize(size) => write!(f, "palette has {} colors, expected 2, 4 or 16", size)
+        }
+    }
+}
+
+// A palette by name or as a list of "#RRGGBB" colors, as in the config and the ROM database
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PaletteConfig {
+    Name(String),
+    Colors(Vec<String>)
+}
+
+impl PaletteConfig {
+    pub fn palette(&self) -> Result<Palette, PaletteError> {
+        match self {
+            PaletteConfig::Name(name) => Palette::from_name(name).ok_or_else(|| PaletteError::Unknown(name.clone())),
+            PaletteConfig::Colors(colors) => Palette::parse(colors)
+        }
+    }
+}
+
+// Colors indexed by the planes a pixel is lit on: the background first, then plane 1, plane 2 and both for
+// four colors, and so on for sixteen. Two-color palettes show every lit pixel in the second color.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Palette {
+    pub colors: Vec<Rgb>
+}
+
+static PALETTES: [(&str, &[Rgb]); 8] = [
+    ("classic", &[[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF]]),
+    ("green", &[[0x0A, 0x1A, 0x0A], [0x33, 0xFF, 0x66]]),
+    ("amber", &[[0x1A, 0x0F, 0x00], [0xFF, 0xB0, 0x00]]),
+    ("lcd", &[[0x9B, 0xBC, 0x0F], [0x0F, 0x38, 0x0F]]),
+    ("high-contrast", &[[0x00, 0x00, 0x00], [0xFF, 0xFF, 0x00]]),
+    // Blue and orange from the Okabe-Ito set, told apart with any form of color blindness
+    ("colorblind", &[[0x00, 0x00, 0x00], [0xE6, 0x9F, 0x00], [0x56, 0xB4, 0xE9], [0xF0, 0xE4, 0x42]]),
+    ("octo", &[[0x99, 0x66, 0x00], [0xFF, 0xCC, 0x00], [0xFF, 0x66, 0x00], [0x66, 0x22, 0x00]]),
+    ("xo-chip", &[
+        [0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55],
+        [0xFF, 0x00, 0x00], [0x00, 0xFF, 0x00], [0x00, 0x00, 0xFF], [0xFF, 0xFF, 0x00],
+        [0x88, 0x00, 0x00], [0x00, 0x88, 0x00], [0x00, 0x00, 0x88], [0x88, 0x88, 0x00],
+        [0xFF, 0x00, 0xFF], [0x00, 0xFF, 0xFF], [0x88, 0x00, 0x88], [0x00, 0x88, 0x88]
+    ])
+];
+
+impl Palette {
+    pub fn from_name(name: &str) -> Option<Palette> {
+        let name = name.to_lowercase();
+        let name = match name.as_str() {
+            "default" | "mono" => "classic",
+            "phosphor" => "green",
+            "colourblind" => "colorblind",
+            "xochip" => "xo-chip",
+            name => name
+        };
+        PALETTES.iter().find(|(known, _)| *known == name).map(|(_, colors)| Palette {colors: colors.to_vec()})
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        PALETTES.iter().map(|(name, _)| *name).collect()
+    }
+
+    pub fn parse<S: AsRef<str>>(colors: &[S]) -> Result<Palette, PaletteError> {
+        if ![2, 4, 16].contains(&colors.len()) {
+            return Err(PaletteError::InvalidSize(colors.len()));
+        }
+        let colors = colors.iter()
+            .map(|color| parse_color(color.as_ref()).ok_or_else(|| PaletteError::InvalidColor(color.as_ref().to_string())))
+            .collect::<Result<Vec<Rgb>, PaletteError>>()?;
+        Ok(Palette {colors})
+    }
+
+    pub fn color(&self, index: u8) -> Rgb {
+        match self.colors.get(index as usize) {
+            Some(color) => *color,
+            None => self.colors[1]
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::from_name("classic").unwrap()
+    }
+}
+
+// "#RRGGBB"
+pub fn parse_color(text: &str) -> Option<Rgb> {
+    let hex = text.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_named_palettes() {
+        for name in Palette::names() {
+            let palette = Palette::from_name(name).unwrap();
+            assert!([2, 4, 16].contains(&palette.colors.len()), "{}", name);
+        }
+        assert_eq!(Palette::from_name("Amber").unwrap().color(1), [0xFF, 0xB0, 0x00]);
+        assert_eq!(Palette::from_name("default"), Some(Palette::default()));
+        assert_eq!(Palette::from_name("sepia"), None);
+    }
+
+    #[test]
+    fn test_color() {
+        let palette = Palette::default();
+        assert_eq!(palette.color(0), [0x00, 0x00, 0x00]);
+        assert_eq!(palette.color(3), [0xFF, 0xFF, 0xFF]);
+        assert_eq!(Palette::from_name("octo").unwrap().color(3), [0x66, 0x22, 0x00]);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Palette::parse(&["#000000", "#FF8000"]).unwrap().colors, vec![[0, 0, 0], [0xFF, 0x80, 0]]);
+        assert_eq!(Palette::parse(&["#000000"]), Err(PaletteError::InvalidSize(1)));
+        assert_eq!(Palette::parse(&["#000000", "red"]), Err(PaletteError::InvalidColor("red".to_string())));
+        let config = PaletteConfig::Name("lcd".to_string());
+        assert_eq!(config.palette().unwrap(), Palette::from_name("lcd").unwrap());
+        assert_eq!(PaletteConfig::Name("x".to_string()).palette(), Err(PaletteError::Unknown("x".to_string())));
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#1a2B3c"), Some([0x1A, 0x2B, 0x3C]));
+        assert_eq!(parse_color("1a2b3c"), None);
+        assert_eq!(parse_color("#1a2b3"), None);
+        assert_eq!(parse_color("#1a2b3g"), None);
+    }
+}
@@ -0,0 +1,152 @@
+use serde::Deserialize;
+
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Platform {
+    #[default]
+    Chip8,      // COSMAC VIP CHIP-8, 4 KiB of memory
+    SuperChip,  // SUPER-CHIP on the HP 48, 4 KiB of memory
+    XoChip      // XO-CHIP, 64 KiB of memory
+}
+
+impl Platform {
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name.to_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(Platform::Chip8),
+            "superchip" | "schip" => Some(Platform::SuperChip),
+            "xochip" | "xo-chip" => Some(Platform::XoChip),
+            _ => None
+        }
+    }
+
+    pub fn from_platform_id(id: &str) -> Option<Platform> {
+        match id {
+            "originalChip8" | "hybridVIP" | "modernChip8" => Some(Platform::Chip8),
+            "chip48" | "superchip1" | "superchip" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None
+        }
+    }
+
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => 0x1000,
+            Platform::XoChip => 0x10000
+        }
+    }
+
+    pub fn program_start(&self) -> u16 {
+        0x200
+    }
+
+    // Everything from the program start to the end of memory
+    pub fn max_rom_size(&self) -> usize {
+        self.memory_size() - self.program_start() as usize
+    }
+}
+
+// Behaviours that differ between interpreters. The names follow the CHIP-8 database.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Quirks {
+    pub shift: bool,                    // 8XY6/8XYE shift VX in place and ignore VY
+    pub memory_increment_by_x: bool,    // FX55/FX65 increment I by X instead of X + 1
+    pub memory_leave_i_unchanged: bool, // FX55/FX65 leave I unchanged
+    pub wrap: bool,                     // Sprites wrap around the screen edges instead of being clipped
+    pub jump: bool,                     // BXNN jumps to XNN + VX instead of NNN + V0
+    pub vblank: bool,                   // DXYN waits for the start of the next frame
+    pub logic: bool                     // 8XY1/8XY2/8XY3 reset VF
+}
+
+impl Quirks {
+    // Quirks of a platform id used by the CHIP-8 database
+    pub fn for_platform_id(id: &str) -> Option<Quirks> {
+        let quirks = |shift, memory_increment_by_x, memory_leave_i_unchanged, wrap, jump, vblank, logic| Quirks {
+            shift, memory_increment_by_x, memory_leave_i_unchanged, wrap, jump, vblank, logic
+        };
+        match id {
+            "originalChip8" | "hybridVIP" => Some(quirks(false, false, false, false, false, true, true)),
+            "modernChip8" => Some(quirks(false, false, false, false, false, false, false)),
+            "chip48" | "superchip1" => Some(quirks(true, true, false, false, true, false, false)),
+            "superchip" => Some(quirks(true, false, true, false, true, false, false)),
+            "xochip" => Some(quirks(false, false, false, true, false, false, false)),
+            _ => None
+        }
+    }
+
+    pub fn apply(&mut self, overrides: &QuirkOverrides) {
+        let set = |quirk: &mut bool, value: Option<bool>| if let Some(value) = value {
+            *quirk = value;
+        };
+        set(&mut self.shift, overrides.shift);
+        set(&mut self.memory_increment_by_x, overrides.memory_increment_by_x);
+        set(&mut self.memory_leave_i_unchanged, overrides.memory_leave_i_unchanged);
+        set(&mut self.wrap, overrides.wrap);
+        set(&mut self.jump, overrides.jump);
+        set(&mut self.vblank, overrides.vblank);
+        set(&mut self.logic, overrides.logic);
+    }
+}
+
+// The behaviour this interpreter had before quirks were configurable
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift: false,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: true,
+            wrap: false,
+            jump: false,
+            vblank: false,
+            logic: false
+        }
+    }
+}
+
+// Partial quirks, as given for a single ROM in the database or the config. Both the camelCase names of the
+// database and snake_case names are accepted.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuirkOverrides {
+    pub shift: Option<bool>,
+    #[serde(alias = "memory_increment_by_x")]
+    pub memory_increment_by_x: Option<bool>,
+    #[serde(alias = "memory_leave_i_unchanged")]
+    pub memory_leave_i_unchanged: Option<bool>,
+    pub wrap: Option<bool>,
+    pub jump: Option<bool>,
+    pub vblank: Option<bool>,
+    pub logic: Option<bool>
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_platform_ids() {
+        assert_eq!(Platform::from_platform_id("originalChip8"), Some(Platform::Chip8));
+        assert_eq!(Platform::from_platform_id("superchip"), Some(Platform::SuperChip));
+        assert_eq!(Platform::from_platform_id("xochip"), Some(Platform::XoChip));
+        assert_eq!(Platform::from_platform_id("megachip8"), None);
+        assert!(Quirks::for_platform_id("originalChip8").unwrap().vblank);
+        assert!(Quirks::for_platform_id("superchip").unwrap().shift);
+        assert_eq!(Quirks::for_platform_id("megachip8"), None);
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let mut quirks = Quirks::default();
+        let overrides: QuirkOverrides = serde_json::from_str(r#"{"shift": true, "memory_leave_i_unchanged": false}"#).unwrap();
+        quirks.apply(&overrides);
+        assert!(quirks.shift);
+        assert!(!quirks.memory_leave_i_unchanged);
+        assert!(!quirks.jump);
+    }
+
+    #[test]
+    fn test_max_rom_size() {
+        assert_eq!(Platform::Chip8.max_rom_size(), 3584);
+        assert_eq!(Platform::XoChip.max_rom_size(), 65024);
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use crate::cpu::STACK_SIZE;
+use crate::instructions::Instruction;
+
+
+// Counts every executed instruction by address and by kind, and attributes it to the chain of
+// subroutines that was active when it ran. Every instruction counts as one cycle.
+pub struct Profiler {
+    pc_counts: HashMap<u16, u64>,
+    instruction_counts: HashMap<&'static str, u64>,
+    call_stack: Vec<u16>,               // Entry addresses of the active subroutines, outermost first. Deeper
+                                        // calls drop the outermost, as the CPU's stack wraps.
+    stacks: Vec<Stack>,                 // Every call stack seen, the first is main
+    children: HashMap<(usize, u16), usize>,
+    current: usize,                     // Index of the call stack in call_stack
+    cycles: u64
+}
+
+// A call stack as the one it was called from and the subroutine entered
+struct Stack {
+    parent: usize,
+    address: u16,
+    cycles: u64                         // Spent directly in the stack
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Subroutine {
+    pub address: u16,
+    pub calls: u64,
+    pub self_cycles: u64,       // Spent in the subroutine itself
+    pub total_cycles: u64       // Including the subroutines it calls
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            pc_counts: HashMap::new(),
+            instruction_counts: HashMap::new(),
+            call_stack: Vec::new(),
+            stacks: vec![Stack {parent: 0, address: 0, cycles: 0}],
+            children: HashMap::new(),
+            current: 0,
+            cycles: 0
+        }
+    }
+
+    // Called with each instruction before it executes. A Call counts towards the caller and a Return
+    // towards the subroutine it leaves.
+    pub fn record(&mut self, pc: u16, ins: &Instruction) {
+        self.cycles += 1;
+        *self.pc_counts.entry(pc).or_insert(0) += 1;
+        *self.instruction_counts.entry(ins.name()).or_insert(0) += 1;
+        self.stacks[self.current].cycles += 1;
+        match ins {
+            Instruction::Call{address} => {
+                if self.call_stack.len() == STACK_SIZE {
+                    self.call_stack.remove(0);
+                    let call_stack = self.call_stack.clone();
+                    self.current = call_stack.iter().fold(0, |stack, address| self.child(stack, *address));
+                }
+                self.call_stack.push(*address);
+                self.current = self.child(self.current, *address);
+            },
+            Instruction::Return => {
+                // Main is its own parent
+                self.call_stack.pop();
+                self.current = self.stacks[self.current].parent;
+            },
+            _ => {}
+        }
+    }
+
+    fn child(&mut self, parent: usize, address: u16) -> usize {
+        let stacks = &mut self.stacks;
+        *self.children.entry((parent, address)).or_insert_with(|| {
+            stacks.push(Stack {parent, address, cycles: 0});
+            stacks.len() - 1
+        })
+    }
+
+    // The subroutines of each call stack that cycles were spent in, outermost first, with those cycles
+    fn call_stacks(&self) -> Vec<(Vec<u16>, u64)> {
+        self.stacks.iter().enumerate().filter(|(_, stack)| stack.cycles > 0).map(|(mut index, stack)| {
+            let mut addresses = Vec::new();
+            while index != 0 {
+                addresses.push(self.stacks[index].address);
+                index = self.stacks[index].parent;
+            }
+            addresses.reverse();
+            (addresses, stack.cycles)
+        }).collect()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Sorted by count, highest first
+    pub fn hotspots(&self, n: usize) -> Vec<(u16, u64)> {
+        let mut hotspots: Vec<(u16, u64)> = self.pc_counts.iter().map(|(pc, count)| (*pc, *count)).collect();
+        hotspots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hotspots.truncate(n);
+        hotspots
+    }
+
+    pub fn histogram(&self) -> Vec<(&'static str, u64)> {
+        let mut histogram: Vec<(&'static str, u64)> = self.instruction_counts.iter().map(|(name, count)| (*name, *count)).collect();
+        histogram.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        histogram
+    }
+
+    // Sorted by total cycles, highest first
+    pub fn subroutines(&self) -> Vec<Subroutine> {
+        let mut subroutines: HashMap<u16, Subroutine> = HashMap::new();
+        for (stack, cycles) in &self.call_stacks() {
+            for (depth, address) in stack.iter().enumerate() {
+                let subroutine = subroutines.entry(*address).or_insert(Subroutine {
+                    address: *address, calls: 0, self_cycles: 0, total_cycles: 0
+                });
+                // Recursive subroutines appear several times in a stack but only count once
+                if !stack[..depth].contains(address) {
+                    subroutine.total_cycles += cycles;
+                }
+                if depth == stack.len() - 1 {
+                    subroutine.self_cycles += cycles;
+                }
+            }
+        }
+        for (pc, count) in &self.pc_counts {
+            // Calls are counted at the entry address, the first instruction of each call
+            if let Some(subroutine) = subroutines.get_mut(pc) {
+                subroutine.calls = *count;
+            }
+        }
+        let mut subroutines: Vec<Subroutine> = subroutines.into_values().collect();
+        subroutines.sort_by(|a, b| b.total_cycles.cmp(&a.total_cycles).then(a.address.cmp(&b.address)));
+        subroutines
+    }
+
+    // One line per call stack in the folded format read by flamegraph.pl and inferno
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self.call_stacks().iter().map(|(stack, cycles)| {
+            let mut frames = vec!["main".to_string()];
+            frames.extend(stack.iter().map(|address| format!("sub_{:03X}", address)));
+            format!("{} {}", frames.join(";"), cycles)
+        }).collect();
+        lines.sort();
+        let mut folded = lines.join("\n");
+        folded.push('\n');
+        folded
+    }
+
+    pub fn report(&self) -> String {
+        let percent = |count: u64| count as f64 * 100.0 / self.cycles.max(1) as f64;
+        let mut report = String::new();
+        writeln!(report, "{} cycles\n\nHotspots", self.cycles).unwrap();
+        for (pc, count) in self.hotspots(20) {
+            writeln!(report, "  {:#05X} {:>12} {:>6.2}%", pc, count, percent(count)).unwrap();
+        }
+        writeln!(report, "\nInstructions").unwrap();
+        for (name, count) in self.histogram() {
+            writeln!(report, "  {:<36} {:>12} {:>6.2}%", name, count, percent(count)).unwrap();
+        }
+        writeln!(report, "\nSubroutines          calls         self        total").unwrap();
+        for subroutine in self.subroutines() {
+            writeln!(report, "  {:#05X} {:>16} {:>11.2}% {:>11.2}%", subroutine.address, subroutine.calls,
+                     percent(subroutine.self_cycles), percent(subroutine.total_cycles)).unwrap();
+        }
+        report
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // main: 0x200 loops calling 0x300, which calls 0x400
+    fn get_profiler() -> Profiler {
+        let mut profiler = Profiler::new();
+        for _ in 0..2 {
+            profiler.record(0x200, &Instruction::Call{address: 0x300});
+            profiler.record(0x300, &Instruction::LoadRegister{register: 0, value: 1});
+            profiler.record(0x302, &Instruction::Call{address: 0x400});
+            profiler.record(0x400, &Instruction::Return);
+            profiler.record(0x304, &Instruction::Return);
+            profiler.record(0x202, &Instruction::Jump{address: 0x200});
+        }
+        profiler
+    }
+
+    #[test]
+    fn test_hotspots() {
+        let profiler = get_profiler();
+        assert_eq!(profiler.cycles(), 12);
+        assert_eq!(profiler.hotspots(2), vec![(0x200, 2), (0x202, 2)]);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let profiler = get_profiler();
+        assert_eq!(profiler.histogram(), vec![("Call", 4), ("Return", 4), ("Jump", 2), ("LoadRegister", 2)]);
+    }
+
+    #[test]
+    fn test_subroutines() {
+        let profiler = get_profiler();
+        assert_eq!(profiler.subroutines(), vec![
+            Subroutine{address: 0x300, calls: 2, self_cycles: 6, total_cycles: 8},
+            Subroutine{address: 0x400, calls: 2, self_cycles: 2, total_cycles: 2}
+        ]);
+    }
+
+    #[test]
+    fn test_folded() {
+        let profiler = get_profiler();
+        assert_eq!(profiler.folded(), "main 4\nmain;sub_300 6\nmain;sub_300;sub_400 2\n");
+    }
+
+    #[test]
+    fn test_unbalanced_return() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x200, &Instruction::Return);
+        profiler.record(0x202, &Instruction::NOP);
+        assert_eq!(profiler.folded(), "main 2\n");
+    }
+
+    #[test]
+    fn test_unbalanced_call_loop() {
+        // 0x200: CALL 0x200, which never returns
+        let mut profiler = Profiler::new();
+        for _ in 0..1000 {
+            profiler.record(0x200, &Instruction::Call{address: 0x200});
+        }
+        assert_eq!(profiler.call_stack.len(), STACK_SIZE);
+        assert_eq!(profiler.stacks.len(), STACK_SIZE + 1);
+        let folded = profiler.folded();
+        let deepest = folded.lines().last().unwrap();
+        assert_eq!(deepest.matches("sub_200").count(), STACK_SIZE);
+        assert!(deepest.ends_with(&format!(" {}", 1000 - STACK_SIZE)));
+        assert_eq!(profiler.subroutines(), vec![Subroutine{address: 0x200, calls: 1000, self_cycles: 999, total_cycles: 999}]);
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt::Write;
+use crate::analysis::ControlFlowGraph;
+use crate::cpu::CPU;
+use crate::memory::MemoryDevice;
+use crate::input::InputDevice;
+use crate::graphics::GraphicsDevice;
+use crate::sound::SoundDevice;
+use crate::instructions::Instruction;
+use crate::threaded::{ends_block, written_range};
+
+
+static BOUNDS: &str = "<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice>";
+
+pub type BlockFn<M, I, G, S> = fn(&mut CPU<M, I, G, S>);
+pub type Lookup<M, I, G, S> = fn(u16) -> Option<CompiledBlock<M, I, G, S>>;
+
+// A block of a recompiled ROM. Running it leaves the CPU as the interpreter would after length instructions.
+pub struct CompiledBlock<M, I, G, S> {
+    pub start: u16,
+    pub length: usize,
+    pub run: BlockFn<M, I, G, S>
+}
+
+// I based memory accesses are left to the interpreter, so the runtime can see which code writes overwrite
+// and stop where the access policy traps
+fn accesses_memory(ins: &Instruction) -> bool {
+    matches!(ins, Instruction::StoreBCDValueOfRegisterToI{..} | Instruction::StoreNRegistersToMemory{..} |
+                  Instruction::ReadNRegistersFromMemory{..})
+}
+
+// The statement for instructions that are simple enough to inline, these leave the PC to the end of the block
+fn inline(ins: &Instruction) -> Option<String> {
+    match ins {
+        Instruction::NOP => Some(String::new()),
+        Instruction::LoadRegister{register, value} => Some(format!("cpu.set_register({}, {:#04X});", register, value)),
+        Instruction::AddToRegister{register, value} =>
+            Some(format!("cpu.set_register({0}, cpu.get_register({0}).wrapping_add({1:#04X}));", register, value)),
+        Instruction::SetRegisterToRegister{destination_register, source_register} =>
+            Some(format!("cpu.set_register({}, cpu.get_register({}));", destination_register, source_register)),
+        Instruction::SetAddressRegister{value} => Some(format!("cpu.I = {:#05X};", value)),
+        Instruction::AddRegisterToRegisterI{register} =>
+            Some(format!("cpu.I = cpu.I.wrapping_add(cpu.get_register({}) as u16);", register)),
+        Instruction::SetRegisterToDelayTimer{register} => Some(format!("cpu.set_register({}, cpu.DT);", register)),
+        Instruction::SetDelayTimerToRegister{register} => Some(format!("cpu.DT = cpu.get_register({});", register)),
+        _ => None
+    }
+}
+
+// Splits the blocks of the control-flow graph after draws and key waits and around memory accesses
+fn compiled_blocks(graph: &ControlFlowGraph) -> Vec<Vec<(u16, u16, Instruction)>> {
+    let mut blocks = Vec::new();
+    for block in graph.blocks.values() {
+        let mut current = Vec::new();
+        for (pc, opcode, ins) in &block.instructions {
+            if accesses_memory(ins) {
+                if !current.is_empty() {
+                    blocks.push(current);
+                }
+                current = Vec::new();
+                continue;
+            }
+            current.push((*pc, *opcode, *ins));
+            if ends_block(ins) {
+                blocks.push(current);
+                current = Vec::new();
+            }
+        }
+        if !current.is_empty() {
+            blocks.push(current);
+        }
+    }
+    blocks
+}
+
+// Rust source for a module with one function per block of the ROM, to be built against this crate and run
+// with Recompiled. name is only used in the header comment.
+pub fn recompile(rom: &[u8], start: u16, name: &str) -> String {
+    let graph = ControlFlowGraph::build(rom, start);
+    let blocks = compiled_blocks(&graph);
+    let mut source = String::new();
+    writeln!(source, "// Recompiled from {} by chip8 recompile, do not edit\n", name).unwrap();
+    writeln!(source, "use chip8::cpu::CPU;\nuse chip8::graphics::GraphicsDevice;\nuse chip8::input::InputDevice;").unwrap();
+    if blocks.iter().flatten().any(|(_, _, ins)| inline(ins).is_none() && !matches!(ins, Instruction::Jump{..})) {
+        writeln!(source, "use chip8::instructions::Instruction;").unwrap();
+    }
+    writeln!(source, "use chip8::memory::MemoryDevice;\nuse chip8::recompiler::{{BlockFn, CompiledBlock}};\nuse chip8::sound::SoundDevice;\n").unwrap();
+
+    writeln!(source, "pub static ROM: [u8; {}] = [", rom.len()).unwrap();
+    for line in rom.chunks(16) {
+        let bytes: Vec<String> = line.iter().map(|byte| format!("{:#04X}", byte)).collect();
+        writeln!(source, "    {},", bytes.join(", ")).unwrap();
+    }
+    writeln!(source, "];\n").unwrap();
+
+    writeln!(source, "pub fn lookup{}(pc: u16) -> Option<CompiledBlock<M, I, G, S>> {{", BOUNDS).unwrap();
+    writeln!(source, "    let (length, run): (usize, BlockFn<M, I, G, S>) = match pc {{").unwrap();
+    for block in &blocks {
+        writeln!(source, "        {:#05X} => ({}, block_{:03x}),", block[0].0, block.len(), block[0].0).unwrap();
+    }
+    writeln!(source, "        _ => return None\n    }};\n    Some(CompiledBlock {{start: pc, length, run}})\n}}").unwrap();
+
+    for block in &blocks {
+        writeln!(source, "\nfn block_{:03x}{}(cpu: &mut CPU<M, I, G, S>) {{", block[0].0, BOUNDS).unwrap();
+        let mut pc_is_set = false;
+        for (pc, opcode, ins) in block {
+            writeln!(source, "    // {:#05X}  {:04X}  {}", pc, opcode, ins.name()).unwrap();
+            match (inline(ins), ins) {
+                (Some(statement), _) => {
+                    if !statement.is_empty() {
+                        writeln!(source, "    {}", statement).unwrap();
+                    }
+                    pc_is_set = false;
+                },
+                (None, Instruction::Jump{address}) => {
+                    writeln!(source, "    cpu.PC = {:#05X};", address).unwrap();
+                    pc_is_set = true;
+                },
+                (None, ins) => {
+                    writeln!(source, "    cpu.PC = {:#05X};\n    cpu.execute_opcode(Instruction::{:?});", pc, ins).unwrap();
+                    pc_is_set = true;
+                }
+            }
+        }
+        if !pc_is_set {
+            // Past the end of the smallest memory the next address depends on the platform, as in the interpreter
+            let next = block.last().unwrap().0 as usize + 2;
+            if next < 0x1000 {
+                writeln!(source, "    cpu.PC = {:#05X};", next).unwrap();
+            } else {
+                writeln!(source, "    cpu.PC = ({:#06X} % cpu.bus.memory.size()) as u16;", next).unwrap();
+            }
+        }
+        writeln!(source, "}}").unwrap();
+    }
+    source
+}
+
+// Runs a recompiled ROM. The interpreter takes over where there is no compiled block, where a block would run
+// past the end of the frame, and for code that was written at runtime.
+pub struct Recompiled<M, I, G, S> {
+    lookup: Lookup<M, I, G, S>,
+    modified: Vec<bool>     // Per address, set by memory writes
+}
+
+impl<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice> Recompiled<M, I, G, S> {
+    pub fn new(lookup: Lookup<M, I, G, S>) -> Recompiled<M, I, G, S> {
+        Recompiled {
+            lookup,
+            modified: Vec::new()
+        }
+    }
+
+    fn is_modified(&self, start: u16, length: usize) -> bool {
+        self.modified.iter().skip(start as usize).take(2 * length).any(|modified| *modified)
+    }
+
+    // Same as CPU::run_frame. Only the instructions run by the interpreter are seen by the profiler.
+    pub fn run_frame(&mut self, cpu: &mut CPU<M, I, G, S>, instructions: usize) {
+        let mut remaining = instructions;
+        while remaining > 0 && !cpu.is_waiting_for_vblank() && cpu.fault().is_none() {
+            let block = (self.lookup)(cpu.PC).filter(|block| block.length <= remaining && !self.is_modified(block.start, block.length));
+            match block {
+                Some(block) => {
+                    (block.run)(cpu);
+                    remaining -= block.length;
+                },
+                None => {
+                    let ins = cpu.bus.memory.decode_instruction(&cpu.PC);
+                    let written = written_range(cpu, &ins);
+                    if let Some(profiler) = &mut cpu.profiler {
+                        profiler.record(cpu.PC, &ins);
+                    }
+                    cpu.execute_opcode(ins);
+                    remaining -= 1;
+                    if let Some((first, last)) = written {
+                        if self.modified.len() <= last as usize {
+                            self.modified.resize(last as usize + 1, false);
+                        }
+                        for modified in &mut self.modified[first as usize..=last as usize] {
+                            *modified = true;
+                        }
+                    }
+                }
+            }
+        }
+        cpu.tick_timers();
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compiled_blocks() {
+        // LD V0, 1; LD [I], V0; ADD V0, 1; DRW; JP 0x200
+        let rom = [0x60, 0x01, 0xF0, 0x55, 0x70, 0x01, 0xD0, 0x01, 0x12, 0x00];
+        let graph = ControlFlowGraph::build(&rom, 0x200);
+        let starts: Vec<(u16, usize)> = compiled_blocks(&graph).iter().map(|block| (block[0].0, block.len())).collect();
+        assert_eq!(starts, vec![(0x200, 1), (0x204, 2), (0x208, 1)]);
+    }
+
+    #[test]
+    fn test_recompile() {
+        let source = recompile(&[0x60, 0x01, 0x12, 0x00], 0x200, "loop.ch8");
+        assert!(source.starts_with("// Recompiled from loop.ch8"));
+        assert!(source.contains("        0x200 => (2, block_200),\n"));
+        assert!(source.contains("    // 0x200  6001  LoadRegister\n    cpu.set_register(0, 0x01);\n"));
+        assert!(source.contains("    // 0x202  1200  Jump\n    cpu.PC = 0x200;\n}\n"));
+        assert!(!source.contains("use chip8::instructions::Instruction;"));
+    }
+
+    #[test]
+    fn test_recompile_wraps_at_end_of_memory() {
+        let source = recompile(&[0x60, 0x01], 0xFFE, "end.ch8");
+        assert!(source.contains("    cpu.PC = (0x1000 % cpu.bus.memory.size()) as u16;\n"));
+        let source = recompile(&[0x60, 0x01, 0x60, 0x02], 0xFFFC, "end.ch8");
+        assert!(source.contains("    cpu.PC = (0x10000 % cpu.bus.memory.size()) as u16;\n"));
+    }
+
+    #[test]
+    fn test_recompile_falls_back_to_interpreter() {
+        let source = recompile(&[0x22, 0x04, 0x12, 0x02, 0x00, 0xEE], 0x200, "call.ch8");
+        assert!(source.contains("    cpu.PC = 0x200;\n    cpu.execute_opcode(Instruction::Call { address: 516 });\n"));
+        assert!(source.contains("use chip8::instructions::Instruction;"));
+    }
+}
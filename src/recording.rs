@@ -0,0 +1,266 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use crate::framebuffer::Framebuffer;
+use crate::graphics::{Graphics, HEIGHT, WIDTH};
+use crate::palette::{Palette, Rgb};
+use crate::screenshot::MAX_SCALE;
+
+
+const FRAME_RATE: usize = 60;
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(io::Error),
+    Gif(gif::EncodingError),
+    UnknownFormat(PathBuf),
+    InvalidScale(usize)
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordingError::Io(err) => write!(f, "could not write recording: {}", err),
+            RecordingError::Gif(err) => write!(f, "could not encode GIF: {}", err),
+            RecordingError::UnknownFormat(path) => write!(f, "{}: recordings are .gif or .y4m", path.display()),
+            RecordingError::InvalidScale(scale) => write!(f, "scale {} is not between 1 and {}", scale, MAX_SCALE)
+        }
+    }
+}
+
+impl From<io::Error> for RecordingError {
+    fn from(err: io::Error) -> RecordingError {
+        RecordingError::Io(err)
+    }
+}
+
+impl From<gif::EncodingError> for RecordingError {
+    fn from(err: gif::EncodingError) -> RecordingError {
+        RecordingError::Gif(err)
+    }
+}
+
+enum Encoder<W: Write> {
+    // Frames only cover the pixels that changed and are written once the next change gives their delay
+    Gif {
+        encoder: gif::Encoder<W>,
+        previous: Option<Framebuffer>,
+        pending: Option<(gif::Frame<'static>, usize)>  // With the frame it was recorded on
+    },
+    Y4m(W)
+}
+
+// Records one frame of the framebuffer per call to frame, at 60 frames per second. Recording starts when
+// the recorder is created and stops with finish, which frontends bind to a hotkey.
+pub struct Recorder<W: Write> {
+    encoder: Encoder<W>,
+    palette: Palette,
+    scale: usize,
+    frames: usize
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn gif(writer: W, palette: Palette, scale: usize) -> Result<Recorder<W>, RecordingError> {
+        check_scale(scale)?;
+        let colors: Vec<u8> = palette.colors.iter().flatten().copied().collect();
+        let mut encoder = gif::Encoder::new(writer, (WIDTH * scale) as u16, (HEIGHT * scale) as u16, &colors)?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        Ok(Recorder {encoder: Encoder::Gif{encoder, previous: None, pending: None}, palette, scale, frames: 0})
+    }
+
+    // Uncompressed 4:4:4 YUV with BT.601 limited range colors
+    pub fn y4m(mut writer: W, palette: Palette, scale: usize) -> Result<Recorder<W>, RecordingError> {
+        check_scale(scale)?;
+        writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", WIDTH * scale, HEIGHT * scale, FRAME_RATE)?;
+        Ok(Recorder {encoder: Encoder::Y4m(writer), palette, scale, frames: 0})
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn frame(&mut self, graphics: &Graphics) -> Result<(), RecordingError> {
+        let framebuffer = Framebuffer::from_graphics(graphics);
+        let frame = self.frames;
+        self.frames += 1;
+        let scale = self.scale;
+        match &mut self.encoder {
+            Encoder::Gif{encoder, previous, pending} => {
+                let (left, top, width, height) = match previous {
+                    Some(previous) => match changed_area(previous, &framebuffer) {
+                        Some(area) => area,
+                        None => return Ok(())
+                    },
+                    None => (0, 0, WIDTH, HEIGHT)
+                };
+                if let Some((mut pending, start)) = pending.take() {
+                    pending.delay = delay(start, frame);
+                    encoder.write_frame(&pending)?;
+                }
+                let mut buffer = Vec::with_capacity(width * height * scale * scale);
+                for y in top..top + height {
+                    let line: Vec<u8> = (left..left + width)
+                        .flat_map(|x| std::iter::repeat_n(framebuffer.get_pixel(x, y) as u8, scale))
+                        .collect();
+                    for _ in 0..scale {
+                        buffer.extend_from_slice(&line);
+                    }
+                }
+                let gif_frame = gif::Frame {
+                    left: (left * scale) as u16,
+                    top: (top * scale) as u16,
+                    width: (width * scale) as u16,
+                    height: (height * scale) as u16,
+                    buffer: Cow::Owned(buffer),
+                    ..gif::Frame::default()
+                };
+                *pending = Some((gif_frame, frame));
+                *previous = Some(framebuffer);
+            },
+            Encoder::Y4m(writer) => {
+                let (unlit, lit) = (to_yuv(self.palette.color(0)), to_yuv(self.palette.color(1)));
+                writer.write_all(b"FRAME\n")?;
+                for plane in (0..3).map(|i| [unlit[i], lit[i]]) {
+                    for row in framebuffer.pixels.chunks(WIDTH) {
+                        let line: Vec<u8> = row.iter()
+                            .flat_map(|pixel| std::iter::repeat_n(plane[*pixel as usize], scale))
+                            .collect();
+                        for _ in 0..scale {
+                            writer.write_all(&line)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Writes what is left and returns the writer
+    pub fn finish(self) -> Result<W, RecordingError> {
+        match self.encoder {
+            Encoder::Gif{mut encoder, pending, ..} => {
+                if let Some((mut pending, start)) = pending {
+                    pending.delay = delay(start, self.frames);
+                    encoder.write_frame(&pending)?;
+                }
+                Ok(encoder.into_inner()?)
+            },
+            Encoder::Y4m(mut writer) => {
+                writer.flush()?;
+                Ok(writer)
+            }
+        }
+    }
+}
+
+// The format follows the extension
+pub fn create(path: &Path, palette: Palette, scale: usize) -> Result<Recorder<BufWriter<File>>, RecordingError> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_lowercase);
+    let create = || File::create(path).map(BufWriter::new);
+    match extension.as_deref() {
+        Some("gif") => Recorder::gif(create()?, palette, scale),
+        Some("y4m") => Recorder::y4m(create()?, palette, scale),
+        _ => Err(RecordingError::UnknownFormat(path.to_path_buf()))
+    }
+}
+
+fn check_scale(scale: usize) -> Result<(), RecordingError> {
+    if scale == 0 || scale > MAX_SCALE {
+        return Err(RecordingError::InvalidScale(scale));
+    }
+    Ok(())
+}
+
+// GIF delays are in hundredths of a second, so frames alternate between 1 and 2 to keep 60 fps on average
+fn delay(start: usize, end: usize) -> u16 {
+    let time = |frame: usize| (frame * 100 + FRAME_RATE / 2) / FRAME_RATE;
+    (time(end) - time(start)).min(u16::MAX as usize) as u16
+}
+
+// Left, top, width and height of the pixels that differ, None if none do
+fn changed_area(previous: &Framebuffer, current: &Framebuffer) -> Option<(usize, usize, usize, usize)> {
+    let changed: Vec<(usize, usize)> = (0..current.height)
+        .flat_map(|y| (0..current.width).map(move |x| (x, y)))
+        .filter(|(x, y)| previous.get_pixel(*x, *y) != current.get_pixel(*x, *y))
+        .collect();
+    let left = changed.iter().map(|(x, _)| *x).min()?;
+    let right = changed.iter().map(|(x, _)| *x).max()?;
+    let top = changed.iter().map(|(_, y)| *y).min()?;
+    let bottom = changed.iter().map(|(_, y)| *y).max()?;
+    Some((left, top, right - left + 1, bottom - top + 1))
+}
+
+fn to_yuv(color: Rgb) -> [u8; 3] {
+    let [r, g, b] = [color[0] as i32, color[1] as i32, color[2] as i32];
+    [
+        (16 + ((66 * r + 129 * g + 25 * b + 128) >> 8)) as u8,
+        (128 + ((-38 * r - 74 * g + 112 * b + 128) >> 8)) as u8,
+        (128 + ((112 * r - 94 * g - 18 * b + 128) >> 8)) as u8
+    ]
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graphics::GraphicsDevice;
+
+    #[test]
+    fn test_gif() {
+        let mut recorder = Recorder::gif(Vec::new(), Palette {colors: vec![[1, 2, 3], [4, 5, 6]]}, 2).unwrap();
+        let mut graphics = Graphics::new();
+        recorder.frame(&graphics).unwrap();
+        graphics.draw_sprite(&3, &4, &[0xC0, 0x40], false);
+        recorder.frame(&graphics).unwrap();
+        recorder.frame(&graphics).unwrap();
+        recorder.frame(&graphics).unwrap();
+        assert_eq!(recorder.frames(), 4);
+        let data = recorder.finish().unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(&data[..]).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (128, 64));
+        assert_eq!(decoder.global_palette().unwrap()[..6], [1, 2, 3, 4, 5, 6]);
+        let first = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!((first.width, first.height, first.delay), (128, 64, 2));
+        // Only the sprite's area, shown for the three unchanged frames
+        let second = decoder.read_next_frame().unwrap().unwrap().clone();
+        assert_eq!((second.left, second.top, second.width, second.height, second.delay), (6, 8, 4, 4, 5));
+        assert_eq!(second.buffer[..4], [1, 1, 1, 1]);
+        assert_eq!(second.buffer[8..12], [0, 0, 1, 1]);
+        assert!(decoder.read_next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_y4m() {
+        let mut recorder = Recorder::y4m(Vec::new(), Palette::default(), 1).unwrap();
+        let mut graphics = Graphics::new();
+        graphics.draw_sprite(&1, &0, &[0x80], false);
+        recorder.frame(&graphics).unwrap();
+        recorder.frame(&graphics).unwrap();
+        let data = recorder.finish().unwrap();
+        let header = b"YUV4MPEG2 W64 H32 F60:1 Ip A1:1 C444\n";
+        assert!(data.starts_with(header));
+        let frame_size = 6 + WIDTH * HEIGHT * 3;
+        assert_eq!(data.len(), header.len() + 2 * frame_size);
+        let frame = &data[header.len()..header.len() + frame_size];
+        assert_eq!(frame[..9], *b"FRAME\n\x10\xEB\x10");
+        assert_eq!(frame[6 + WIDTH * HEIGHT + 1], 128);
+    }
+
+    #[test]
+    fn test_delay() {
+        assert_eq!((0..6).map(|frame| delay(frame, frame + 1)).collect::<Vec<u16>>(), vec![2, 1, 2, 2, 1, 2]);
+        assert_eq!(delay(0, 60), 100);
+    }
+
+    #[test]
+    fn test_create() {
+        let path = std::env::temp_dir().join("chip8-recording-test.mp4");
+        assert!(matches!(create(&path, Palette::default(), 1), Err(RecordingError::UnknownFormat(_))));
+        assert!(matches!(Recorder::y4m(Vec::new(), Palette::default(), 0), Err(RecordingError::InvalidScale(0))));
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+use std::io;
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RomInfo {
+    pub sha1: String,       // Lowercase hex digest, the key used by ROM databases
+    pub length: usize
+}
+
+impl RomInfo {
+    pub fn new(data: &[u8]) -> RomInfo {
+        RomInfo {
+            sha1: sha1_smol::Sha1::from(data).digest().to_string(),
+            length: data.len()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    Empty,
+    TooLarge{size: usize, max: usize}
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io(err) => write!(f, "could not read ROM: {}", err),
+            RomError::Empty => write!(f, "ROM is empty"),
+            RomError::TooLarge{size, max} =>
+                write!(f, "ROM is {} bytes but only {} bytes of program space are available", size, max)
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> RomError {
+        RomError::Io(err)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rom_info() {
+        let info = RomInfo::new(b"abc");
+        assert_eq!(info.sha1, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(info.length, 3);
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::BTreeSet;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::cpu::CPU;
+use crate::graphics::GraphicsDevice;
+use crate::input::InputDevice;
+use crate::memory::{MemoryDevice, MemoryFault};
+use crate::scheduler::FRAME_RATE;
+use crate::sound::SoundDevice;
+
+
+// Why the CPU stopped
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Stop {
+    Step,               // The requested instruction ran
+    Breakpoint(u16),
+    Fault(MemoryFault),
+    Interrupted
+}
+
+// Runs the CPU one instruction at a time for a debugger, so it can stop between any two of them. The timers
+// tick after every `instructions` steps as with run_frame, and frames are paced to 60 a second unless paced is
+// off. The position in the frame is kept across stops, so stepping does not change the timing a ROM sees.
+pub struct Runner {
+    pub breakpoints: BTreeSet<u16>,
+    pub instructions: usize,    // Per frame
+    pub paced: bool,
+    slot: usize,                // Instructions run in the current frame
+    frame_due: Option<Instant>  // When the current frame may end
+}
+
+impl Runner {
+    pub fn new(instructions: usize) -> Runner {
+        Runner {breakpoints: BTreeSet::new(), instructions: instructions.max(1), paced: true, slot: 0, frame_due: None}
+    }
+
+    // Runs one instruction, finishing the frame first while the CPU waits for vblank
+    pub fn step<M, I, G, S>(&mut self, cpu: &mut CPU<M, I, G, S>) -> Stop
+        where M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice {
+        loop {
+            if let Some(fault) = cpu.fault() {
+                return Stop::Fault(fault);
+            }
+            if self.tick(cpu) {
+                return Stop::Step;
+            }
+        }
+    }
+
+    // Runs until a breakpoint, a fault or interrupted returns true, which is asked once per frame. A
+    // breakpoint on the current instruction is stepped over.
+    pub fn run<M, I, G, S, F>(&mut self, cpu: &mut CPU<M, I, G, S>, mut interrupted: F) -> Stop
+        where M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice, F: FnMut() -> bool {
+        let mut started = false;
+        loop {
+            if let Some(fault) = cpu.fault() {
+                return Stop::Fault(fault);
+            }
+            if started && self.breakpoints.contains(&cpu.PC) {
+                return Stop::Breakpoint(cpu.PC);
+            }
+            if self.slot >= self.instructions && interrupted() {
+                return Stop::Interrupted;
+            }
+            started |= self.tick(cpu);
+        }
+    }
+
+    // Takes one instruction slot and returns whether an instruction ran in it
+    fn tick<M, I, G, S>(&mut self, cpu: &mut CPU<M, I, G, S>) -> bool
+        where M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice {
+        if self.slot >= self.instructions {
+            self.end_frame(cpu);
+        }
+        let ready = !cpu.is_waiting_for_vblank() && cpu.fault().is_none();
+        cpu.step();
+        self.slot += 1;
+        ready && cpu.fault().is_none()
+    }
+
+    fn end_frame<M, I, G, S>(&mut self, cpu: &mut CPU<M, I, G, S>)
+        where M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice {
+        cpu.tick_timers();
+        self.slot = 0;
+        if !self.paced {
+            return;
+        }
+        // After a stop the next frame is paced from now rather than caught up
+        let now = Instant::now();
+        let due = match self.frame_due {
+            Some(due) if due > now => {
+                thread::sleep(due - now);
+                due
+            },
+            _ => now
+        };
+        self.frame_due = Some(due + Duration::from_secs(1) / FRAME_RATE as u32);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::graphics::Graphics;
+    use crate::input::Input;
+    use crate::memory::{Access, Memory};
+    use crate::sound::Sound;
+
+    fn get_cpu() -> CPU {
+        let mut cpu = CPU::new(Bus::new());
+        // ADD V0, 1; ADD V1, 1; JP 0x200
+        cpu.bus.memory.load_data(&0x200, &[0x70, 0x01, 0x71, 0x01, 0x12, 0x00]);
+        cpu.set_pc(&0x200);
+        cpu
+    }
+
+    fn get_runner(instructions: usize) -> Runner {
+        Runner {paced: false, ..Runner::new(instructions)}
+    }
+
+    #[test]
+    fn test_step() {
+        let mut cpu = get_cpu();
+        let mut runner = get_runner(2);
+        cpu.DT = 5;
+        assert_eq!(runner.step(&mut cpu), Stop::Step);
+        assert_eq!(runner.step(&mut cpu), Stop::Step);
+        assert_eq!((cpu.PC, cpu.DT), (0x204, 5));
+        // The third instruction starts the next frame
+        assert_eq!(runner.step(&mut cpu), Stop::Step);
+        assert_eq!((cpu.PC, cpu.DT), (0x200, 4));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut cpu = get_cpu();
+        let mut runner = get_runner(10);
+        runner.breakpoints.insert(0x202);
+        assert_eq!(runner.run(&mut cpu, || false), Stop::Breakpoint(0x202));
+        assert_eq!(cpu.registers[..2], [1, 0]);
+        // Continuing steps over the breakpoint it stopped at
+        assert_eq!(runner.run(&mut cpu, || false), Stop::Breakpoint(0x202));
+        assert_eq!(cpu.registers[..2], [2, 1]);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut cpu = get_cpu();
+        let mut runner = get_runner(10);
+        let mut frames = 0;
+        let stop = runner.run(&mut cpu, || {
+            frames += 1;
+            frames == 3
+        });
+        assert_eq!(stop, Stop::Interrupted);
+        assert_eq!(cpu.registers[0] as usize + cpu.registers[1] as usize, 20);
+    }
+
+    #[test]
+    fn test_vblank_and_fault() {
+        let mut memory = Memory::new();
+        memory.policy.reserved_writes = Access::Trap;
+        let mut cpu = CPU::new(Bus::from_parts(memory, Input::new(), Graphics::new(), Sound::new()));
+        cpu.quirks.vblank = true;
+        // DRW V0, V0, 1; LD I, 0; LD [I], V0
+        cpu.bus.memory.load_data(&0x200, &[0xD0, 0x01, 0xA0, 0x00, 0xF0, 0x55]);
+        cpu.set_pc(&0x200);
+        let mut runner = get_runner(10);
+        assert_eq!(runner.step(&mut cpu), Stop::Step);
+        // Waits out the frame before running the next instruction
+        assert_eq!(runner.step(&mut cpu), Stop::Step);
+        assert_eq!(cpu.PC, 0x204);
+        assert!(matches!(runner.run(&mut cpu, || false), Stop::Fault(_)));
+        assert_eq!(cpu.PC, 0x204);
+    }
+}
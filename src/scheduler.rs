@@ -0,0 +1,264 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::cpu::CPU;
+use crate::graphics::GraphicsDevice;
+use crate::input::InputDevice;
+use crate::memory::MemoryDevice;
+use crate::sound::SoundDevice;
+
+
+pub const FRAME_RATE: u64 = 60;
+pub const MIN_SPEED: f64 = 0.125;
+pub const MAX_SPEED: f64 = 16.0;
+const MAX_CATCH_UP: usize = 10;    // Frames run at once after a stall before the rest are dropped
+
+// Instructions per frame, or per second spread over the frames
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Rate {
+    PerFrame(usize),
+    PerSecond(usize)
+}
+
+// Decides when frames run. Frame n is due n / 60 seconds after the start at normal speed, counted from the
+// last change of speed or pause rather than from the previous frame, so late frames do not add up to drift.
+// Each frame runs its instructions and ticks the timers once, so timers follow emulated rather than host
+// time and keep 60 Hz relative to the game at any speed. Times are given as durations since a fixed start,
+// such as Instant::elapsed.
+pub struct Scheduler {
+    pub rate: Rate,
+    speed: f64,
+    paused: bool,
+    advance: usize,         // Frames requested while paused
+    origin: Duration,       // When frame base was due
+    base: u64,
+    frames: u64             // Frames run
+}
+
+impl Scheduler {
+    pub fn new(rate: Rate) -> Scheduler {
+        Scheduler {rate, speed: 1.0, paused: false, advance: 0, origin: Duration::ZERO, base: 0, frames: 0}
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    // Above 1 fast-forwards, below 1 slows down. The next frame stays due when it was.
+    pub fn set_speed(&mut self, speed: f64, now: Duration) {
+        let next = self.due_time().max(now);
+        self.speed = if speed.is_nan() {1.0} else {speed.clamp(MIN_SPEED, MAX_SPEED)};
+        self.origin = next;
+        self.base = self.frames;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self, now: Duration) {
+        if self.paused {
+            self.paused = false;
+            self.advance = 0;
+            self.origin = now;
+            self.base = self.frames;
+        }
+    }
+
+    pub fn toggle_pause(&mut self, now: Duration) {
+        if self.paused {
+            self.resume(now);
+        } else {
+            self.pause();
+        }
+    }
+
+    // Runs one more frame while paused
+    pub fn advance(&mut self) {
+        if self.paused {
+            self.advance += 1;
+        }
+    }
+
+    // Instructions for the frame, a rate per second is split so that every 60 frames run exactly that many
+    pub fn instructions_for(&self, frame: u64) -> usize {
+        match self.rate {
+            Rate::PerFrame(instructions) => instructions,
+            Rate::PerSecond(instructions) => {
+                let total = |frame: u64| frame * instructions as u64 / FRAME_RATE;
+                (total(frame + 1) - total(frame)) as usize
+            }
+        }
+    }
+
+    // None while paused with nothing to advance
+    pub fn next_frame_at(&self) -> Option<Duration> {
+        match (self.paused, self.advance) {
+            (true, 0) => None,
+            (true, _) => Some(Duration::ZERO),
+            (false, _) => Some(self.due_time())
+        }
+    }
+
+    // Frames due by now, which can be many after a stall
+    pub fn frames_due(&self, now: Duration) -> usize {
+        if self.paused {
+            return self.advance;
+        }
+        // Rounding must not make a frame late at exactly its due time
+        let elapsed = now.saturating_sub(self.origin).as_secs_f64() * FRAME_RATE as f64 * self.speed + 1e-6;
+        (elapsed as u64 + 1).saturating_sub(self.frames - self.base) as usize
+    }
+
+    // Runs the frames due and returns how many ran. After a stall of more than MAX_CATCH_UP frames the rest
+    // are dropped and pacing continues from now.
+    pub fn update<M, I, G, S>(&mut self, cpu: &mut CPU<M, I, G, S>, now: Duration) -> usize
+        where M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice {
+        let due = self.frames_due(now);
+        let run = due.min(MAX_CATCH_UP);
+        for _ in 0..run {
+            cpu.run_frame(self.instructions_for(self.frames));
+            self.frames += 1;
+        }
+        if self.paused {
+            self.advance -= run;
+        } else if due > MAX_CATCH_UP {
+            // The last frame counts as due now
+            self.origin = now;
+            self.base = self.frames - 1;
+        }
+        run
+    }
+
+    // Sleeps until the next frame is due, or for a frame while paused so that input is still polled
+    pub fn wait(&self, start: Instant) {
+        let now = start.elapsed();
+        match self.next_frame_at() {
+            Some(at) => thread::sleep(at.saturating_sub(now)),
+            None => thread::sleep(Duration::from_secs(1) / FRAME_RATE as u32)
+        }
+    }
+
+    fn due_time(&self) -> Duration {
+        self.origin + Duration::from_secs_f64((self.frames - self.base) as f64 / (FRAME_RATE as f64 * self.speed))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn get_cpu() -> CPU {
+        let mut cpu = CPU::new(Bus::new());
+        // ADD V0, 1; JP 0x200
+        cpu.bus.memory.load_data(&0x200, &[0x70, 0x01, 0x12, 0x00]);
+        cpu.set_pc(&0x200);
+        cpu
+    }
+
+    // Updates every 5 ms, as a frontend polling input would
+    fn run(scheduler: &mut Scheduler, cpu: &mut CPU, from: u64, to: u64) -> usize {
+        (from..to).step_by(5).map(|time| scheduler.update(cpu, millis(time))).sum()
+    }
+
+    #[test]
+    fn test_pacing() {
+        let mut scheduler = Scheduler::new(Rate::PerFrame(10));
+        let mut cpu = get_cpu();
+        assert_eq!(scheduler.update(&mut cpu, millis(0)), 1);
+        assert_eq!(scheduler.update(&mut cpu, millis(10)), 0);
+        assert_eq!(scheduler.next_frame_at(), Some(Duration::from_secs_f64(1.0 / 60.0)));
+        assert_eq!(scheduler.update(&mut cpu, millis(40)), 2);
+        assert_eq!(cpu.get_register(0), 15);
+    }
+
+    #[test]
+    fn test_no_drift() {
+        let mut scheduler = Scheduler::new(Rate::PerFrame(1));
+        let mut cpu = get_cpu();
+        // Checking in slightly late every time still runs 60 frames a second
+        let mut now = Duration::ZERO;
+        for _ in 0..3600 {
+            now = scheduler.next_frame_at().unwrap() + millis(3);
+            scheduler.update(&mut cpu, now);
+        }
+        assert_eq!(scheduler.frames(), 3600);
+        assert!(now < Duration::from_secs(60));
+        assert_eq!(scheduler.frames_due(Duration::from_secs(60)), 1);
+    }
+
+    #[test]
+    fn test_speed() {
+        let mut scheduler = Scheduler::new(Rate::PerFrame(1));
+        let mut cpu = get_cpu();
+        scheduler.set_speed(2.0, Duration::ZERO);
+        assert_eq!(run(&mut scheduler, &mut cpu, 0, 1000), 120);
+        scheduler.set_speed(0.5, millis(1000));
+        assert_eq!(run(&mut scheduler, &mut cpu, 1000, 2000), 30);
+        scheduler.set_speed(100.0, millis(2000));
+        assert_eq!(scheduler.speed(), MAX_SPEED);
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let mut scheduler = Scheduler::new(Rate::PerFrame(1));
+        let mut cpu = get_cpu();
+        assert_eq!(scheduler.update(&mut cpu, Duration::from_secs(5)), MAX_CATCH_UP);
+        assert_eq!(scheduler.update(&mut cpu, Duration::from_secs(5)), 0);
+        assert_eq!(scheduler.update(&mut cpu, Duration::from_secs(5) + millis(17)), 1);
+    }
+
+    #[test]
+    fn test_pause_and_advance() {
+        let mut scheduler = Scheduler::new(Rate::PerFrame(2));
+        let mut cpu = get_cpu();
+        scheduler.update(&mut cpu, Duration::ZERO);
+        scheduler.pause();
+        assert_eq!(scheduler.next_frame_at(), None);
+        assert_eq!(scheduler.update(&mut cpu, Duration::from_secs(1)), 0);
+        scheduler.advance();
+        scheduler.advance();
+        assert_eq!(scheduler.update(&mut cpu, Duration::from_secs(2)), 2);
+        assert_eq!(scheduler.update(&mut cpu, Duration::from_secs(2)), 0);
+        assert_eq!(cpu.get_register(0), 3);
+        // Resuming continues from now instead of catching up on the pause
+        scheduler.toggle_pause(Duration::from_secs(3));
+        assert!(!scheduler.is_paused());
+        assert_eq!(scheduler.update(&mut cpu, Duration::from_secs(3)), 1);
+    }
+
+    #[test]
+    fn test_instructions_per_second() {
+        let scheduler = Scheduler::new(Rate::PerSecond(700));
+        let counts: Vec<usize> = (0..60).map(|frame| scheduler.instructions_for(frame)).collect();
+        assert_eq!(counts.iter().sum::<usize>(), 700);
+        assert!(counts.iter().all(|count| *count == 11 || *count == 12));
+        assert_eq!(Scheduler::new(Rate::PerFrame(9)).instructions_for(123), 9);
+    }
+
+    #[test]
+    fn test_timers_follow_frames() {
+        let mut scheduler = Scheduler::new(Rate::PerFrame(1));
+        let mut cpu = get_cpu();
+        cpu.DT = 60;
+        // A second of game time in a quarter of a second
+        scheduler.set_speed(4.0, Duration::ZERO);
+        run(&mut scheduler, &mut cpu, 0, 250);
+        assert_eq!((scheduler.frames(), cpu.DT), (59, 1));
+        scheduler.update(&mut cpu, millis(250));
+        assert_eq!(cpu.DT, 0);
+    }
+}
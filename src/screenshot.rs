@@ -0,0 +1,208 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::framebuffer::Framebuffer;
+use crate::graphics::Graphics;
+use crate::palette::Palette;
+
+
+pub const DEFAULT_SCALE: usize = 8;
+pub const MAX_SCALE: usize = 64;
+
+#[derive(Debug)]
+pub enum ScreenshotError {
+    Io(PathBuf, io::Error),
+    Encode(png::EncodingError),
+    InvalidScale(usize)
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScreenshotError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            ScreenshotError::Encode(err) => write!(f, "could not encode PNG: {}", err),
+            ScreenshotError::InvalidScale(scale) => write!(f, "scale {} is not between 1 and {}", scale, MAX_SCALE)
+        }
+    }
+}
+
+impl From<png::EncodingError> for ScreenshotError {
+    fn from(err: png::EncodingError) -> ScreenshotError {
+        ScreenshotError::Encode(err)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Png,
+    Pbm     // Plain PBM, one pixel per pixel and black for lit pixels, for diffing
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Pbm => "pbm"
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Screenshot {
+    pub palette: Palette,
+    pub scale: usize,           // Host pixels per CHIP-8 pixel, PNG only
+    pub rom: Option<String>     // Written into the file's metadata
+}
+
+impl Default for Screenshot {
+    fn default() -> Screenshot {
+        Screenshot {palette: Palette::default(), scale: DEFAULT_SCALE, rom: None}
+    }
+}
+
+impl Screenshot {
+    pub fn to_png(&self, framebuffer: &Framebuffer) -> Result<Vec<u8>, ScreenshotError> {
+        if self.scale == 0 || self.scale > MAX_SCALE {
+            return Err(ScreenshotError::InvalidScale(self.scale));
+        }
+        let (width, height) = (framebuffer.width * self.scale, framebuffer.height * self.scale);
+        let mut data = Vec::with_capacity(width * height * 3);
+        for row in framebuffer.pixels.chunks(framebuffer.width) {
+            let line: Vec<u8> = row.iter()
+                .flat_map(|pixel| std::iter::repeat_n(self.palette.color(*pixel as u8), self.scale).flatten())
+                .collect();
+            for _ in 0..self.scale {
+                data.extend_from_slice(&line);
+            }
+        }
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.add_text_chunk("Software".to_string(), "chip8".to_string())?;
+        if let Some(rom) = &self.rom {
+            encoder.add_text_chunk("Title".to_string(), rom.clone())?;
+        }
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(png)
+    }
+
+    // The ROM name goes into a comment after the magic number
+    pub fn to_pbm(&self, framebuffer: &Framebuffer) -> Vec<u8> {
+        let pbm = framebuffer.to_pbm();
+        match &self.rom {
+            Some(rom) => pbm.replacen("P1\n", &format!("P1\n# {}\n", rom.replace('\n', " ")), 1).into_bytes(),
+            None => pbm.into_bytes()
+        }
+    }
+
+    pub fn encode(&self, framebuffer: &Framebuffer, format: Format) -> Result<Vec<u8>, ScreenshotError> {
+        match format {
+            Format::Png => self.to_png(framebuffer),
+            Format::Pbm => Ok(self.to_pbm(framebuffer))
+        }
+    }
+
+    // Writes the screen into the directory under a timestamped name and returns the file's path
+    pub fn save(&self, graphics: &Graphics, directory: &Path, format: Format) -> Result<PathBuf, ScreenshotError> {
+        let data = self.encode(&Framebuffer::from_graphics(graphics), format)?;
+        let path = directory.join(file_name(self.rom.as_deref(), SystemTime::now(), format.extension()));
+        fs::write(&path, data).map_err(|err| ScreenshotError::Io(path.clone(), err))?;
+        Ok(path)
+    }
+}
+
+// "<rom>-YYYYMMDD-HHMMSS-mmm.<extension>" in UTC
+pub fn file_name(rom: Option<&str>, time: SystemTime, extension: &str) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (seconds, millis) = (elapsed.as_secs(), elapsed.subsec_millis());
+    let (year, month, day) = civil_date((seconds / 86400) as i64);
+    let time_of_day = seconds % 86400;
+    let rom: String = rom.unwrap_or("chip8").chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' {c} else {'_'})
+        .collect();
+    format!("{}-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}.{}", rom, year, month, day, time_of_day / 3600,
+            time_of_day / 60 % 60, time_of_day % 60, millis, extension)
+}
+
+// Year, month and day of a day count since 1970-01-01 (Howard Hinnant's civil_from_days)
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 {month + 3} else {month - 9} as u32;
+    let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+    (year, month, day)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use crate::graphics::GraphicsDevice;
+
+    fn get_framebuffer() -> Framebuffer {
+        let mut graphics = Graphics::new();
+        graphics.draw_sprite(&1, &0, &[0x80], false);
+        Framebuffer::from_graphics(&graphics)
+    }
+
+    #[test]
+    fn test_png() {
+        let screenshot = Screenshot {palette: Palette {colors: vec![[1, 2, 3], [4, 5, 6]]}, scale: 2, rom: Some("pong".to_string())};
+        let png = screenshot.to_png(&get_framebuffer()).unwrap();
+        let mut reader = png::Decoder::new(&png[..]).read_info().unwrap();
+        let texts: Vec<(String, String)> = reader.info().uncompressed_latin1_text.iter()
+            .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+            .collect();
+        assert!(texts.contains(&("Title".to_string(), "pong".to_string())));
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!((info.width, info.height), (128, 64));
+        let line = info.line_size;
+        assert_eq!(data[..12], [1, 2, 3, 1, 2, 3, 4, 5, 6, 4, 5, 6]);
+        assert_eq!(data[line..line + 12], data[..12]);
+        assert_eq!(data[2 * line + 6..2 * line + 9], [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_invalid_scale() {
+        let screenshot = Screenshot {scale: 0, ..Screenshot::default()};
+        assert!(matches!(screenshot.to_png(&get_framebuffer()), Err(ScreenshotError::InvalidScale(0))));
+    }
+
+    #[test]
+    fn test_pbm() {
+        let framebuffer = get_framebuffer();
+        let pbm = Screenshot {rom: Some("pong".to_string()), ..Screenshot::default()}.to_pbm(&framebuffer);
+        assert!(pbm.starts_with(b"P1\n# pong\n64 32\n0 1 0"));
+        assert_eq!(Framebuffer::parse(&pbm), Ok(framebuffer));
+    }
+
+    #[test]
+    fn test_file_name() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_792_418_112_345);
+        assert_eq!(file_name(Some("space invaders"), time, "png"), "space_invaders-20261019-135512-345.png");
+        assert_eq!(file_name(None, UNIX_EPOCH, "pbm"), "chip8-19700101-000000-000.pbm");
+        assert_eq!(civil_date(11016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn test_save() {
+        let directory = std::env::temp_dir();
+        let screenshot = Screenshot {rom: Some("chip8-screenshot-test".to_string()), ..Screenshot::default()};
+        let path = screenshot.save(&Graphics::new(), &directory, Format::Pbm).unwrap();
+        assert!(path.file_name().unwrap().to_str().unwrap().starts_with("chip8-screenshot-test-"));
+        assert!(fs::read(&path).unwrap().starts_with(b"P1\n"));
+        fs::remove_file(path).unwrap();
+    }
+}
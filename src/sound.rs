@@ -1,14 +1,28 @@
-pub struct Sound {
+pub trait SoundDevice {
+    fn set_playing(&mut self, playing: bool);
+}
 
+pub struct Sound {
+    pub playing: bool       // Buzzer is on while the sound timer is non zero
 }
 
 impl Sound {
 
-    pub fn mock() -> Sound {
-        Sound {}
+    pub fn new() -> Sound {
+        Sound {
+            playing: false
+        }
+    }
+}
+
+impl Default for Sound {
+    fn default() -> Sound {
+        Sound::new()
     }
+}
 
-    pub fn new() -> Sound {
-        Sound {}
+impl SoundDevice for Sound {
+    fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, serde_json::Error)
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            SymbolError::Parse(path, err) => write!(f, "{}: invalid symbol map: {}", path.display(), err)
+        }
+    }
+}
+
+// The source line an instruction was assembled from
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct Line {
+    pub address: u16,
+    pub source: PathBuf,    // Relative to the symbol map
+    pub line: usize         // From 1
+}
+
+// Addresses of labels and source lines, as written by an assembler:
+// {"labels": {"main": 512}, "lines": [{"address": 512, "source": "game.8o", "line": 3}]}
+#[derive(Debug, PartialEq, Clone, Default, Deserialize)]
+pub struct SymbolMap {
+    #[serde(default)]
+    pub labels: BTreeMap<String, u16>,
+    #[serde(default)]
+    pub lines: Vec<Line>
+}
+
+impl SymbolMap {
+    pub fn parse(text: &str) -> Result<SymbolMap, serde_json::Error> {
+        let mut symbols: SymbolMap = serde_json::from_str(text)?;
+        symbols.lines.sort_by_key(|line| line.address);
+        Ok(symbols)
+    }
+
+    // Sources are made relative to the map's directory
+    pub fn load(path: &Path) -> Result<SymbolMap, SymbolError> {
+        let text = fs::read_to_string(path).map_err(|err| SymbolError::Io(path.to_path_buf(), err))?;
+        let mut symbols = SymbolMap::parse(&text).map_err(|err| SymbolError::Parse(path.to_path_buf(), err))?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        for line in symbols.lines.iter_mut() {
+            line.source = directory.join(&line.source);
+        }
+        Ok(symbols)
+    }
+
+    // The first instruction on the line, or on the next line with code as a breakpoint on a comment would
+    pub fn address_for(&self, source: &Path, line: usize) -> Option<&Line> {
+        self.lines.iter()
+            .filter(|entry| same_file(&entry.source, source) && entry.line >= line)
+            .min_by_key(|entry| (entry.line, entry.address))
+    }
+
+    // The line of the instruction at or before the address
+    pub fn line_for(&self, address: u16) -> Option<&Line> {
+        self.lines.iter().rev().find(|entry| entry.address <= address)
+    }
+
+    // The closest label at or before the address, with the offset from it
+    pub fn label_for(&self, address: u16) -> Option<(&str, u16)> {
+        self.labels.iter()
+            .filter(|(_, label)| **label <= address)
+            .max_by_key(|(_, label)| **label)
+            .map(|(name, label)| (name.as_str(), address - label))
+    }
+}
+
+// Editors send absolute paths while maps may hold relative ones
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || a.ends_with(b) || b.ends_with(a) ||
+        matches!((fs::canonicalize(a), fs::canonicalize(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn get_symbols() -> SymbolMap {
+        SymbolMap::parse(r#"{
+            "labels": {"main": 512, "loop": 516},
+            "lines": [
+                {"address": 516, "source": "game.8o", "line": 7},
+                {"address": 512, "source": "game.8o", "line": 3},
+                {"address": 514, "source": "game.8o", "line": 4}
+            ]
+        }"#).unwrap()
+    }
+
+    #[test]
+    fn test_address_for() {
+        let symbols = get_symbols();
+        assert_eq!(symbols.address_for(Path::new("game.8o"), 4).unwrap().address, 514);
+        // Comments and blank lines move to the next instruction
+        assert_eq!(symbols.address_for(Path::new("/home/me/game.8o"), 5).unwrap().address, 516);
+        assert_eq!(symbols.address_for(Path::new("game.8o"), 8), None);
+        assert_eq!(symbols.address_for(Path::new("other.8o"), 3), None);
+    }
+
+    #[test]
+    fn test_line_for() {
+        let symbols = get_symbols();
+        assert_eq!(symbols.line_for(515).unwrap().line, 4);
+        assert_eq!(symbols.line_for(600).unwrap().line, 7);
+        assert_eq!(symbols.line_for(0x100), None);
+    }
+
+    #[test]
+    fn test_label_for() {
+        let symbols = get_symbols();
+        assert_eq!(symbols.label_for(514), Some(("main", 2)));
+        assert_eq!(symbols.label_for(516), Some(("loop", 0)));
+        assert_eq!(symbols.label_for(0), None);
+    }
+}
@@ -0,0 +1,389 @@
+use crate::cpu::CPU;
+use crate::memory::MemoryDevice;
+use crate::input::InputDevice;
+use crate::graphics::GraphicsDevice;
+use crate::sound::SoundDevice;
+use crate::instructions::Instruction;
+use crate::platform::Quirks;
+
+
+const MAX_BLOCK_LENGTH: usize = 64;
+
+type Handler<M, I, G, S> = Box<dyn Fn(&mut CPU<M, I, G, S>)>;
+
+struct Op<M, I, G, S> {
+    pc: u16,
+    ins: Instruction,
+    handler: Handler<M, I, G, S>
+}
+
+// Straight-line run of instructions. Only the last one may jump, skip, wait, draw or write memory.
+struct Block<M, I, G, S> {
+    ops: Vec<Op<M, I, G, S>>,
+    end: u16                    // Last byte of the last instruction
+}
+
+pub(crate) fn ends_block(ins: &Instruction) -> bool {
+    matches!(ins,
+        Instruction::Jump{..} | Instruction::Call{..} | Instruction::Return | Instruction::JumpToLocationAndOffset0{..} |
+        Instruction::SkipIfEqual{..} | Instruction::SkipIfNotEqual{..} | Instruction::SkipIfRegistersEqual{..} |
+        Instruction::SkipIfRegistersNotEqual{..} | Instruction::SkipIfPressedKeyEqualToRegister{..} |
+        Instruction::DontSkipIfPressedKeyEqualToRegister{..} | Instruction::WaitForKeyPressAndStoreValue{..} |
+        Instruction::DisplaySpriteAtLocation{..} | Instruction::StoreBCDValueOfRegisterToI{..} |
+        Instruction::StoreNRegistersToMemory{..} | Instruction::InvalidInstruction)
+}
+
+// Handlers with the operands bound in. The common register operations get their own closure, everything
+// else goes through the reference interpreter so the two cannot drift apart.
+fn handler<M, I, G, S>(ins: Instruction) -> Handler<M, I, G, S>
+    where M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice {
+    match ins {
+        Instruction::NOP => Box::new(|cpu| cpu.inc_pc()),
+        Instruction::LoadRegister{register, value} => Box::new(move |cpu| {
+            cpu.set_register(register, value);
+            cpu.inc_pc();
+        }),
+        Instruction::AddToRegister{register, value} => Box::new(move |cpu| {
+            cpu.set_register(register, cpu.get_register(register).wrapping_add(value));
+            cpu.inc_pc();
+        }),
+        Instruction::SetRegisterToRegister{destination_register, source_register} => Box::new(move |cpu| {
+            cpu.set_register(destination_register, cpu.get_register(source_register));
+            cpu.inc_pc();
+        }),
+        Instruction::SetAddressRegister{value} => Box::new(move |cpu| {
+            cpu.I = value;
+            cpu.inc_pc();
+        }),
+        Instruction::AddRegisterToRegisterI{register} => Box::new(move |cpu| {
+            cpu.I = cpu.I.wrapping_add(cpu.get_register(register) as u16);
+            cpu.inc_pc();
+        }),
+        Instruction::SetRegisterToDelayTimer{register} => Box::new(move |cpu| {
+            cpu.set_register(register, cpu.DT);
+            cpu.inc_pc();
+        }),
+        Instruction::SetDelayTimerToRegister{register} => Box::new(move |cpu| {
+            cpu.DT = cpu.get_register(register);
+            cpu.inc_pc();
+        }),
+        ins => Box::new(move |cpu| cpu.execute_opcode(ins))
+    }
+}
+
+// Addresses an instruction is about to write, first and last. Writes that wrap around the end of memory
+// are reported as all of it.
+pub(crate) fn written_range<M: MemoryDevice, I, G, S>(cpu: &CPU<M, I, G, S>, ins: &Instruction) -> Option<(u16, u16)> {
+    let length = match ins {
+        Instruction::StoreBCDValueOfRegisterToI{..} => 3,
+        Instruction::StoreNRegistersToMemory{n} => *n as usize + 1,
+        _ => return None
+    };
+    let size = cpu.bus.memory.size();
+    let first = cpu.I as usize % size;
+    if first + length > size {
+        return Some((0, (size - 1) as u16));
+    }
+    Some((first as u16, (first + length - 1) as u16))
+}
+
+// Executes compiled basic blocks instead of decoding and dispatching one instruction at a time. Blocks are
+// dropped when an instruction writes over them or the quirks change; memory changed from outside the CPU
+// needs a call to invalidate.
+pub struct Threaded<M, I, G, S> {
+    blocks: Vec<Option<Block<M, I, G, S>>>,     // By start address
+    covered: Vec<bool>,                         // Bytes that belong to a compiled block
+    quirks: Quirks              // The quirks the blocks were compiled for
+}
+
+impl<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice> Threaded<M, I, G, S> {
+    pub fn new() -> Threaded<M, I, G, S> {
+        Threaded {
+            blocks: Vec::new(),
+            covered: Vec::new(),
+            quirks: Quirks::default()
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.blocks.clear();
+        self.covered.clear();
+    }
+
+    fn invalidate_range(&mut self, first: u16, last: u16) {
+        let (first, last) = (first as usize, last as usize);
+        if !self.covered.iter().take(last + 1).skip(first).any(|covered| *covered) {
+            return;
+        }
+        for byte in self.covered.iter_mut() {
+            *byte = false;
+        }
+        for (start, entry) in self.blocks.iter_mut().enumerate() {
+            let end = match entry {
+                Some(block) if (block.end as usize) < first || start > last => block.end as usize,
+                _ => {
+                    *entry = None;
+                    continue;
+                }
+            };
+            for byte in start..=end {
+                self.covered[byte] = true;
+            }
+        }
+    }
+
+    fn compile(&mut self, cpu: &CPU<M, I, G, S>, start: u16) {
+        let size = cpu.bus.memory.size();
+        let mut ops = Vec::new();
+        let mut pc = start;
+        loop {
+            let ins = cpu.bus.memory.peek_instruction(&pc);
+            ops.push(Op {pc, ins, handler: handler(ins)});
+            // Stop before reading past the end of memory, the interpreter only does that when it gets there
+            if ends_block(&ins) || ops.len() == MAX_BLOCK_LENGTH || pc as usize + 4 > size {
+                break;
+            }
+            pc += 2;
+        }
+        let end = pc + 1;
+        if self.covered.len() < size {
+            self.covered.resize(size, false);
+            self.blocks.resize_with(size, || None);
+        }
+        for byte in start as usize..=end as usize {
+            self.covered[byte] = true;
+        }
+        self.blocks[start as usize] = Some(Block {ops, end});
+    }
+
+    // Runs the given number of instructions, or fewer when the CPU starts waiting for the next frame
+    pub fn run(&mut self, cpu: &mut CPU<M, I, G, S>, instructions: usize) {
+        if cpu.quirks != self.quirks {
+            self.invalidate();
+            self.quirks = cpu.quirks;
+        }
+        let mut remaining = instructions;
+        while remaining > 0 && !cpu.is_waiting_for_vblank() && cpu.fault().is_none() {
+            // An instruction at the last address wraps around, the interpreter takes care of it
+            if cpu.PC as usize + 2 > cpu.bus.memory.size() {
+                let written = written_range(cpu, &cpu.bus.memory.peek_instruction(&cpu.PC));
+                cpu.step();
+                remaining -= 1;
+                if let Some((first, last)) = written {
+                    self.invalidate_range(first, last);
+                }
+                continue;
+            }
+            if self.blocks.get(cpu.PC as usize).is_none_or(Option::is_none) {
+                self.compile(cpu, cpu.PC);
+            }
+            let block = self.blocks[cpu.PC as usize].as_ref().unwrap();
+            let mut written = None;
+            for op in block.ops.iter().take(remaining) {
+                cpu.bus.memory.record_execute(&op.pc);
+                if let Some(profiler) = &mut cpu.profiler {
+                    profiler.record(op.pc, &op.ins);
+                }
+                written = written_range(cpu, &op.ins);
+                (op.handler)(cpu);
+                remaining -= 1;
+                if cpu.fault().is_some() {
+                    break;
+                }
+            }
+            if let Some((first, last)) = written {
+                self.invalidate_range(first, last);
+            }
+        }
+    }
+}
+
+impl<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice> Default for Threaded<M, I, G, S> {
+    fn default() -> Threaded<M, I, G, S> {
+        Threaded::new()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use crate::bus::Bus;
+    use crate::cpu::Backend;
+    use crate::memory::MemoryFault;
+
+    fn assert_same_state(reference: &CPU, threaded: &CPU) {
+        assert_eq!(reference.registers, threaded.registers);
+        assert_eq!((reference.I, reference.PC, reference.SP), (threaded.I, threaded.PC, threaded.SP));
+        assert_eq!(reference.stack, threaded.stack);
+        assert_eq!((reference.DT, reference.ST), (threaded.DT, threaded.ST));
+        assert_eq!(reference.bus.memory.memory, threaded.bus.memory.memory);
+        assert_eq!(reference.bus.graphics.buffer, threaded.bus.graphics.buffer);
+    }
+
+    fn get_cpus(program: &[u8]) -> (CPU, CPU) {
+        let mut cpus = Vec::new();
+        for backend in &[Backend::Interpreter, Backend::Threaded] {
+            let mut cpu = CPU::new(Bus::new());
+            cpu.bus.memory.load_data(&0x200, program);
+            cpu.set_pc(&0x200);
+            cpu.set_backend(*backend);
+            cpus.push(cpu);
+        }
+        let threaded = cpus.pop().unwrap();
+        (cpus.pop().unwrap(), threaded)
+    }
+
+    fn run_both(program: &[u8], frames: usize, instructions_per_frame: usize) -> (CPU, CPU) {
+        let (mut reference, mut threaded) = get_cpus(program);
+        for _ in 0..frames {
+            reference.run_frame(instructions_per_frame);
+            threaded.run_frame(instructions_per_frame);
+            assert_same_state(&reference, &threaded);
+        }
+        (reference, threaded)
+    }
+
+    // Random straight-line and branching code over V0-V7 that never calls, returns or writes over itself,
+    // followed by a jump back to the start
+    fn random_program(rng: &mut StdRng, length: usize) -> Vec<u8> {
+        let mut program = Vec::new();
+        for _ in 0..length {
+            let x = rng.gen_range(0, 8) as u16;
+            let y = rng.gen_range(0, 8) as u16;
+            let nn = rng.gen::<u8>() as u16;
+            let opcode = match rng.gen_range(0, 16) {
+                0 => 0x6000 | x << 8 | nn,
+                1 => 0x7000 | x << 8 | nn,
+                2 => 0x8000 | x << 8 | y << 4 | [0, 1, 2, 3, 4, 5, 6, 7, 0xE][rng.gen_range(0, 9)],
+                3 => 0x3000 | x << 8 | nn,
+                4 => 0x4000 | x << 8 | nn,
+                5 => 0x5000 | x << 8 | y << 4,
+                6 => 0x9000 | x << 8 | y << 4,
+                7 => 0x1200 | (2 * rng.gen_range(0, length as u16)),
+                8 => 0xA300 | nn,
+                9 => 0xD000 | x << 8 | y << 4 | rng.gen_range(0, 16),
+                10 => 0xF007 | x << 8,
+                11 => 0xF015 | x << 8,
+                12 => 0xF029 | x << 8,
+                13 => 0xF033 | x << 8,
+                14 => 0xF055 | x << 8,
+                _ => 0xF065 | x << 8
+            };
+            program.extend_from_slice(&opcode.to_be_bytes());
+        }
+        // Skips on the last instruction land on the second jump
+        program.extend_from_slice(&[0x12, 0x00, 0x12, 0x00]);
+        program
+    }
+
+    #[test]
+    fn test_random_programs() {
+        let mut rng = StdRng::seed_from_u64(8);
+        for _ in 0..200 {
+            let program = random_program(&mut rng, 48);
+            run_both(&program, 20, 37);
+        }
+    }
+
+    #[test]
+    fn test_quirks() {
+        let mut rng = StdRng::seed_from_u64(16);
+        for platform in &["originalChip8", "superchip1", "superchip", "xochip"] {
+            let program = random_program(&mut rng, 48);
+            let (mut reference, mut threaded) = get_cpus(&program);
+            reference.quirks = Quirks::for_platform_id(platform).unwrap();
+            threaded.quirks = reference.quirks;
+            for _ in 0..20 {
+                reference.run_frame(11);
+                threaded.run_frame(11);
+                assert_same_state(&reference, &threaded);
+            }
+        }
+    }
+
+    #[test]
+    fn test_subroutines() {
+        // Calls 0x20A three times, which counts V0 up, then loops
+        let program = [0x22, 0x0A, 0x22, 0x0A, 0x22, 0x0A, 0x12, 0x06, 0x00, 0x00, 0x70, 0x01, 0x00, 0xEE];
+        let (reference, _) = run_both(&program, 3, 5);
+        assert_eq!(reference.registers[0], 3);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // Increments the low byte of the instruction at 0x20A each loop, so the block must be recompiled
+        let program = [
+            0xA2, 0x0B,     // 0x200 LD I, 0x20B
+            0xF0, 0x65,     // 0x202 LD V0, [I]
+            0x70, 0x01,     // 0x204 ADD V0, 1
+            0xF0, 0x55,     // 0x206 LD [I], V0
+            0x00, 0x00,     // 0x208
+            0x61, 0x00,     // 0x20A LD V1, 0 (rewritten)
+            0x12, 0x00      // 0x20C JP 0x200
+        ];
+        let (reference, threaded) = run_both(&program, 4, 7);
+        assert_eq!(reference.registers[1], 4);
+        assert_eq!(threaded.registers[1], 4);
+    }
+
+    #[test]
+    fn test_tracking() {
+        // The self-modifying loop, stopping part of the way through a block
+        let program = [0xA2, 0x0B, 0xF0, 0x65, 0x70, 0x01, 0xF0, 0x55, 0x00, 0x00, 0x61, 0x00, 0x12, 0x00];
+        let (mut reference, mut threaded) = get_cpus(&program);
+        reference.bus.memory.enable_tracking();
+        threaded.bus.memory.enable_tracking();
+        for _ in 0..4 {
+            reference.run_frame(5);
+            threaded.run_frame(5);
+        }
+        let (reference, threaded) = (reference.bus.memory.heatmap().unwrap(), threaded.bus.memory.heatmap().unwrap());
+        assert_eq!(reference.executes, threaded.executes);
+        assert_eq!(reference.reads, threaded.reads);
+        assert_eq!(reference.writes, threaded.writes);
+        assert_eq!(reference.events, threaded.events);
+        assert_eq!(threaded.executes[0x20C], 2);
+    }
+
+    #[test]
+    fn test_wait_for_key() {
+        let program = [0xF3, 0x0A, 0x12, 0x00];
+        let (mut reference, mut threaded) = run_both(&program, 2, 10);
+        assert_eq!(threaded.PC, 0x200);
+        reference.bus.input.press(&0x7);
+        threaded.bus.input.press(&0x7);
+        reference.run_frame(1);
+        threaded.run_frame(1);
+        assert_same_state(&reference, &threaded);
+        assert_eq!(threaded.registers[3], 0x7);
+    }
+
+    #[test]
+    fn test_memory_trap() {
+        // LD I, 0xFFF; LD V0, [I]; ADD V0, 1; LD V1, [I]; ADD V1, 1; JP 0x200
+        let program = [0xAF, 0xFF, 0xF0, 0x65, 0x70, 0x01, 0xF1, 0x65, 0x71, 0x01, 0x12, 0x00];
+        let (mut reference, mut threaded) = get_cpus(&program);
+        for cpu in [&mut reference, &mut threaded].iter_mut() {
+            cpu.bus.memory.policy.out_of_range = crate::memory::Access::Trap;
+            cpu.run_frame(10);
+        }
+        assert_same_state(&reference, &threaded);
+        assert_eq!(threaded.fault(), Some(MemoryFault::OutOfRange{address: 0x1000, write: false}));
+        // The trapped read loads no register, V0 keeps the 1 added to it
+        assert_eq!((threaded.PC, threaded.registers[0]), (0x206, 1));
+    }
+
+    #[test]
+    fn test_profiler() {
+        let program = [0x22, 0x06, 0x60, 0x01, 0x12, 0x02, 0x70, 0x01, 0x00, 0xEE];
+        let (mut reference, mut threaded) = get_cpus(&program);
+        reference.profiler = Some(crate::profiler::Profiler::new());
+        threaded.profiler = Some(crate::profiler::Profiler::new());
+        reference.run_frame(25);
+        threaded.run_frame(25);
+        assert_eq!(reference.profiler.unwrap().folded(), threaded.profiler.unwrap().folded());
+    }
+}
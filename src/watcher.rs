@@ -0,0 +1,148 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::cpu::CPU;
+use crate::graphics::GraphicsDevice;
+use crate::input::InputDevice;
+use crate::memory::Memory;
+use crate::rom::{RomError, RomInfo};
+use crate::sound::SoundDevice;
+
+
+// Polls a ROM file for changes. The modification time is checked first and the contents are only read
+// once it stayed the same for a poll, so a file still being written by an assembler is not picked up
+// half way. A change of time with the same contents (a rebuild without changes) is not reported.
+pub struct RomWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    pending: Option<SystemTime>,     // Changed time, waiting to settle
+    sha1: String
+}
+
+impl RomWatcher {
+    pub fn new(path: &Path) -> Result<RomWatcher, RomError> {
+        let modified = fs::metadata(path)?.modified().ok();
+        let sha1 = RomInfo::new(&fs::read(path)?).sha1;
+        Ok(RomWatcher {path: path.to_path_buf(), modified, pending: None, sha1})
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The new contents if the file changed since the last call. A missing file, as during a rebuild that
+    // deletes it first, counts as unchanged.
+    pub fn poll(&mut self) -> Result<Option<Vec<u8>>, RomError> {
+        let modified = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.modified().ok(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into())
+        };
+        // Without modification times every poll reads the file
+        if modified.is_some() && modified == self.modified {
+            self.pending = None;
+            return Ok(None);
+        }
+        if modified.is_some() && modified != self.pending {
+            self.pending = modified;
+            return Ok(None);
+        }
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into())
+        };
+        self.modified = modified;
+        self.pending = None;
+        let sha1 = RomInfo::new(&data).sha1;
+        if sha1 == self.sha1 {
+            return Ok(None);
+        }
+        self.sha1 = sha1;
+        Ok(Some(data))
+    }
+}
+
+// Loads the ROM into cleared memory and resets the CPU. The screen is cleared, while the quirks, the
+// backend and anything the frontend keeps, such as its window and palette, stay as they are. On error the
+// previous ROM keeps running.
+pub fn reload<I, G, S>(cpu: &mut CPU<Memory, I, G, S>, data: &[u8]) -> Result<RomInfo, RomError>
+    where I: InputDevice, G: GraphicsDevice, S: SoundDevice {
+    let max = cpu.bus.memory.platform.max_rom_size();
+    if data.is_empty() {
+        return Err(RomError::Empty);
+    }
+    if data.len() > max {
+        return Err(RomError::TooLarge{size: data.len(), max});
+    }
+    cpu.bus.memory.reset();
+    let info = cpu.bus.memory.load_rom(data)?;
+    let start = cpu.bus.memory.platform.program_start();
+    cpu.reset(&start);
+    Ok(info)
+}
+
+// The notification to show after a reload
+pub fn describe(path: &Path, info: &RomInfo) -> String {
+    format!("Reloaded {} ({} bytes, SHA-1 {})", path.display(), info.length, &info.sha1[..8])
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use crate::bus::Bus;
+    use crate::memory::MemoryDevice;
+
+    fn get_path(name: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("chip8-watcher-{}-{}.ch8", name, std::process::id()));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn touch(path: &Path, data: &[u8], seconds: u64) {
+        fs::write(path, data).unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)).unwrap();
+    }
+
+    #[test]
+    fn test_poll() {
+        let path = get_path("poll", &[0x12, 0x00]);
+        let mut watcher = RomWatcher::new(&path).unwrap();
+        assert_eq!(watcher.poll().unwrap(), None);
+        touch(&path, &[0x12, 0x02], 1000);
+        // Reported once the time settled
+        assert_eq!(watcher.poll().unwrap(), None);
+        assert_eq!(watcher.poll().unwrap(), Some(vec![0x12, 0x02]));
+        assert_eq!(watcher.poll().unwrap(), None);
+        // Same contents with a new time
+        touch(&path, &[0x12, 0x02], 2000);
+        assert_eq!(watcher.poll().unwrap(), None);
+        assert_eq!(watcher.poll().unwrap(), None);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(watcher.poll().unwrap(), None);
+    }
+
+    #[test]
+    fn test_reload() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.bus.memory.load_rom(&[0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55]).unwrap();
+        cpu.set_pc(&0x200);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!(cpu.bus.memory.read(&0x300), 0x05);
+        let info = reload(&mut cpu, &[0x12, 0x00]).unwrap();
+        assert_eq!(info.length, 2);
+        assert_eq!((cpu.PC, cpu.I, cpu.get_register(0)), (0x200, 0, 0));
+        assert_eq!(cpu.bus.memory.read(&0x300), 0x00);
+        assert_eq!(cpu.bus.memory.read(&0x202), 0x00);
+        assert!(describe(Path::new("pong.ch8"), &info).starts_with("Reloaded pong.ch8 (2 bytes, SHA-1 "));
+        // A ROM that does not fit leaves the previous one running
+        assert!(matches!(reload(&mut cpu, &[]), Err(RomError::Empty)));
+        assert_eq!(cpu.bus.memory.read(&0x200), 0x12);
+    }
+}
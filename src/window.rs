@@ -0,0 +1,299 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use crate::controller::{ControllerProfile, Controllers};
+use crate::cpu::CPU;
+use crate::filter::{Filter, FilterConfig, MAX_INTENSITY};
+use crate::graphics::{Graphics, HEIGHT, WIDTH};
+use crate::keymap::Keymap;
+use crate::palette::Palette;
+use crate::recording::{self, Recorder};
+use crate::scheduler::{Rate, Scheduler};
+use crate::screenshot::{self, Format, Screenshot};
+use crate::watcher::{self, RomWatcher};
+
+
+pub const DEFAULT_SCALE: u32 = 10;
+const NOTICE: Duration = Duration::from_secs(3);     // How long a notification stays in the title bar
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+// What the window shows and where its input goes, from the config and the ROM database
+pub struct Options {
+    pub title: String,          // Of the ROM, also written into screenshots
+    pub scale: u32,             // Host pixels per CHIP-8 pixel
+    pub rate: Rate,
+    pub palette: Palette,
+    pub filter: FilterConfig,
+    pub keymap: Keymap,
+    pub controller: ControllerProfile,
+    pub screenshots: PathBuf,   // Directory screenshots and recordings are saved in
+    pub rom: PathBuf            // Watched for changes
+}
+
+// Fills a row of an RGB24 texture with the palette's color for each pixel
+pub fn fill_row(palette: &Palette, pixels: &[u8], row: &mut [u8]) {
+    for (pixel, rgb) in pixels.iter().zip(row.chunks_mut(3)) {
+        rgb.copy_from_slice(&palette.color(*pixel));
+    }
+}
+
+// Fills a row of an RGB24 texture from filtered intensities, which go from the background color at 0 to the
+// color of a lit pixel at MAX_INTENSITY
+pub fn shade_row(palette: &Palette, intensities: &[u8], row: &mut [u8]) {
+    let (background, lit) = (palette.color(0), palette.color(1));
+    for (intensity, rgb) in intensities.iter().zip(row.chunks_mut(3)) {
+        let (on, off) = (*intensity as u32, (MAX_INTENSITY - intensity) as u32);
+        for ((channel, background), lit) in rgb.iter_mut().zip(background).zip(lit) {
+            *channel = ((background as u32 * off + lit as u32 * on + 127) / 255) as u8;
+        }
+    }
+}
+
+// Screenshots and the recording the hotkeys save, each reported with a notification
+pub struct Capture {
+    screenshot: Screenshot,
+    directory: PathBuf,
+    recording: Option<(PathBuf, Recorder<BufWriter<File>>)>
+}
+
+impl Capture {
+    pub fn new(screenshot: Screenshot, directory: PathBuf) -> Capture {
+        Capture {screenshot, directory, recording: None}
+    }
+
+    pub fn screenshot(&self, graphics: &Graphics, format: Format) -> String {
+        match self.screenshot.save(graphics, &self.directory, format) {
+            Ok(path) => format!("saved {}", path.display()),
+            Err(err) => err.to_string()
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // Starts a GIF in the screenshots' scale and palette, or finishes the one being recorded
+    pub fn toggle_recording(&mut self) -> String {
+        if let Some(message) = self.finish() {
+            return message;
+        }
+        let name = screenshot::file_name(self.screenshot.rom.as_deref(), SystemTime::now(), "gif");
+        let path = self.directory.join(name);
+        match recording::create(&path, self.screenshot.palette.clone(), self.screenshot.scale) {
+            Ok(recorder) => {
+                let message = format!("recording {}", path.display());
+                self.recording = Some((path, recorder));
+                message
+            },
+            Err(err) => format!("{}: {}", path.display(), err)
+        }
+    }
+
+    // Adds a frame to the recording, if there is one. A recording that fails is dropped and reported.
+    pub fn frame(&mut self, graphics: &Graphics) -> Option<String> {
+        let (path, recorder) = self.recording.as_mut()?;
+        let err = recorder.frame(graphics).err()?;
+        let message = format!("{}: {}", path.display(), err);
+        self.recording = None;
+        Some(message)
+    }
+
+    pub fn finish(&mut self) -> Option<String> {
+        let (path, recorder) = self.recording.take()?;
+        let frames = recorder.frames();
+        Some(match recorder.finish() {
+            Ok(_) => format!("recorded {} frames to {}", frames, path.display()),
+            Err(err) => format!("{}: {}", path.display(), err)
+        })
+    }
+}
+
+// A recording still open when the window fails is finished all the same
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+// Notifications are shown in the title bar for a few seconds
+fn notify(canvas: &mut WindowCanvas, title: &str, message: Option<&str>) {
+    let title = match message {
+        Some(message) => format!("{} - chip8 - {}", title, message),
+        None => format!("{} - chip8", title)
+    };
+    canvas.window_mut().set_title(&title).ok();
+}
+
+// Runs the CPU in a window until it is closed or Escape is pressed. F1 pauses, F2 advances a frame while
+// paused, F3 and F4 halve and double the speed and F5 goes back to normal speed. F12 saves a screenshot as a
+// PNG in the palette and scale of the window, Shift+F12 as a PBM. F11 starts recording every frame shown into
+// a GIF and stops it again, as does closing the window. The ROM is reloaded when its file changes,
+// keeping the window, palette, speed and bindings. Only the rows that changed
+// are uploaded to the texture. With a filter they are the rows whose intensities changed, as a fading pixel
+// changes without a draw. Frames run at once to catch up are filtered as one.
+pub fn run(cpu: &mut CPU, options: Options) -> Result<(), String> {
+    let Options {title, scale, rate, palette, filter, keymap, controller, screenshots, rom} = options;
+    let sdl = sdl2::init()?;
+    let window = sdl.video()?
+        .window(&format!("{} - chip8", title), WIDTH as u32 * scale, HEIGHT as u32 * scale)
+        .position_centered()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let mut canvas = window.into_canvas().build().map_err(|err| err.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator.create_texture_static(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
+        .map_err(|err| err.to_string())?;
+    let mut row = [0; WIDTH * 3];
+    let mut filter = (filter != FilterConfig::None).then(|| Filter::new(filter));
+    let mut shown = vec![0; WIDTH * HEIGHT];     // Filtered intensities in the texture
+    let screenshot = Screenshot {rom: Some(title.clone()), palette: palette.clone(), scale: scale as usize};
+    let mut capture = Capture::new(screenshot, screenshots);
+    let mut notice = None;      // When the notification in the title goes away
+    let mut watcher = RomWatcher::new(&rom).map_err(|err| format!("{}: {}", rom.display(), err))?;
+    let mut next_poll = WATCH_INTERVAL;
+    let mut controllers = Controllers::new(sdl.game_controller()?);
+    controllers.set_profile(controller);
+    let mut events = sdl.event_pump()?;
+    let mut scheduler = Scheduler::new(rate);
+    let start = Instant::now();
+    cpu.bus.graphics.invalidate();
+    loop {
+        for event in events.poll_iter() {
+            let now = start.elapsed();
+            match event {
+                Event::Quit{..} | Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
+                    if let Some(message) = capture.finish() {
+                        println!("{}", message);
+                    }
+                    return Ok(());
+                },
+                // The texture's contents are gone
+                Event::RenderTargetsReset{..} | Event::RenderDeviceReset{..} => cpu.bus.graphics.invalidate(),
+                Event::KeyDown{keycode: Some(Keycode::F1), repeat: false, ..} => scheduler.toggle_pause(now),
+                Event::KeyDown{keycode: Some(Keycode::F2), ..} => scheduler.advance(),
+                Event::KeyDown{keycode: Some(Keycode::F3), ..} => scheduler.set_speed(scheduler.speed() / 2.0, now),
+                Event::KeyDown{keycode: Some(Keycode::F4), ..} => scheduler.set_speed(scheduler.speed() * 2.0, now),
+                Event::KeyDown{keycode: Some(Keycode::F5), ..} => scheduler.set_speed(1.0, now),
+                Event::KeyDown{keycode: Some(Keycode::F12), keymod, repeat: false, ..} => {
+                    let format = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {Format::Pbm} else {Format::Png};
+                    let message = capture.screenshot(&cpu.bus.graphics, format);
+                    notify(&mut canvas, &title, Some(&message));
+                    notice = Some(now + NOTICE);
+                },
+                Event::KeyDown{keycode: Some(Keycode::F11), repeat: false, ..} => {
+                    let message = capture.toggle_recording();
+                    notify(&mut canvas, &title, Some(&message));
+                    notice = Some(now + NOTICE);
+                },
+                event => {
+                    if !keymap.handle_event(&event, &mut cpu.bus.input) {
+                        controllers.handle_event(&event, &mut cpu.bus.input);
+                    }
+                }
+            }
+        }
+        if notice.is_some_and(|until| start.elapsed() >= until) {
+            notify(&mut canvas, &title, None);
+            notice = None;
+        }
+        if start.elapsed() >= next_poll {
+            next_poll = start.elapsed() + WATCH_INTERVAL;
+            let message = match watcher.poll() {
+                Ok(Some(data)) => match watcher::reload(cpu, &data) {
+                    Ok(info) => {
+                        filter = filter.map(|filter| Filter::new(filter.config()));
+                        Some(watcher::describe(watcher.path(), &info))
+                    },
+                    Err(err) => Some(format!("{}: {}", watcher.path().display(), err))
+                },
+                Ok(None) => None,
+                Err(err) => Some(format!("{}: {}", watcher.path().display(), err))
+            };
+            if let Some(message) = message {
+                notify(&mut canvas, &title, Some(&message));
+                notice = Some(start.elapsed() + NOTICE);
+            }
+        }
+        let ran = scheduler.update(cpu, start.elapsed());
+        let mut rows = [false; HEIGHT];
+        if let Some(changes) = cpu.bus.graphics.take_changes() {
+            changes.rows.iter().for_each(|y| rows[*y] = true);
+        }
+        if let Some(filter) = filter.as_mut().filter(|_| ran > 0) {
+            let intensities = filter.frame(&cpu.bus.graphics);
+            for (y, changed) in rows.iter_mut().enumerate() {
+                let range = y * WIDTH..(y + 1) * WIDTH;
+                *changed |= intensities[range.clone()] != shown[range.clone()];
+                shown[range.clone()].copy_from_slice(&intensities[range]);
+            }
+        }
+        for y in (0..HEIGHT).filter(|y| rows[*y]) {
+            match filter {
+                Some(_) => shade_row(&palette, &shown[y * WIDTH..(y + 1) * WIDTH], &mut row),
+                None => fill_row(&palette, &cpu.bus.graphics.buffer[y], &mut row)
+            }
+            texture.update(Rect::new(0, y as i32, WIDTH as u32, 1), &row, row.len())
+                .map_err(|err| err.to_string())?;
+        }
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+        if let Some(message) = capture.frame(&cpu.bus.graphics) {
+            notify(&mut canvas, &title, Some(&message));
+            notice = Some(start.elapsed() + NOTICE);
+        }
+        scheduler.wait(start);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use crate::graphics::GraphicsDevice;
+
+    #[test]
+    fn test_fill_row() {
+        let palette = Palette::parse(&["#000000", "#FF8000"]).unwrap();
+        let mut row = [0xAA; 9];
+        fill_row(&palette, &[1, 0, 1], &mut row);
+        assert_eq!(row, [0xFF, 0x80, 0, 0, 0, 0, 0xFF, 0x80, 0]);
+    }
+
+    #[test]
+    fn test_shade_row() {
+        let palette = Palette::parse(&["#204060", "#FF8000"]).unwrap();
+        let mut row = [0; 9];
+        shade_row(&palette, &[0, 128, MAX_INTENSITY], &mut row);
+        assert_eq!(row, [0x20, 0x40, 0x60, 0x90, 0x60, 0x30, 0xFF, 0x80, 0]);
+    }
+
+    #[test]
+    fn test_capture() {
+        let screenshot = Screenshot {rom: Some("chip8-capture-test".to_string()), ..Screenshot::default()};
+        let mut capture = Capture::new(screenshot, std::env::temp_dir());
+        let mut graphics = Graphics::new();
+        let message = capture.screenshot(&graphics, Format::Pbm);
+        let path = PathBuf::from(message.strip_prefix("saved ").unwrap());
+        assert!(fs::read(&path).unwrap().starts_with(b"P1\n"));
+        fs::remove_file(path).unwrap();
+        assert!(capture.finish().is_none());
+        let message = capture.toggle_recording();
+        let path = PathBuf::from(message.strip_prefix("recording ").unwrap());
+        assert!(capture.is_recording() && path.extension().unwrap() == "gif");
+        for _ in 0..3 {
+            assert!(capture.frame(&graphics).is_none());
+            graphics.draw_sprite(&0, &0, &[0x80], false);
+        }
+        assert_eq!(capture.toggle_recording(), format!("recorded 3 frames to {}", path.display()));
+        assert!(!capture.is_recording());
+        assert!(fs::read(&path).unwrap().starts_with(b"GIF89a"));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,43 @@
+// Runs every test case in tests/fixtures/conformance and compares the screen with its golden image. Golden
+// images are regenerated with chip8 conformance tests/fixtures/conformance/*.toml --update
+use std::fs;
+use std::path::{Path, PathBuf};
+use chip8::conformance::Case;
+
+fn cases() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/conformance");
+    let mut cases: Vec<PathBuf> = fs::read_dir(directory).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+        .collect();
+    cases.sort();
+    cases
+}
+
+#[test]
+fn test_golden_images() {
+    let cases = cases();
+    assert!(!cases.is_empty());
+    let failures: Vec<String> = cases.iter()
+        .filter_map(|path| Case::load(path).and_then(|case| case.check(false)).err()
+            .map(|err| format!("{}: {}", path.display(), err)))
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn test_cases_halt() {
+    for path in cases() {
+        let outcome = Case::load(&path).unwrap().check(false).unwrap();
+        assert!(outcome.halted, "{} ran for all {} frames", path.display(), outcome.frames);
+    }
+}
+
+#[test]
+fn test_mismatch_shows_diff() {
+    let mut case = Case::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/conformance/font.toml")).unwrap();
+    case.golden = case.golden.with_file_name("keypad.txt");
+    let err = case.check(false).err().unwrap().to_string();
+    assert!(err.starts_with("framebuffer does not match the golden image\n"));
+    assert!(err.contains(" 0 ####......+.....++++"));
+}
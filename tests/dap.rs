@@ -0,0 +1,69 @@
+// Replays the transcript in tests/fixtures/dap, as written by `chip8 dap --record`, against the adapter. Client
+// messages are sent in their recorded order once everything the adapter sent before them arrived, and the
+// adapter must send the same messages. $FIXTURES stands for the fixture directory.
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use serde_json::Value;
+use chip8::dap::{self, Adapter};
+
+struct ChannelWriter(Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.send(data.to_vec()).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct ChannelReader(Receiver<Vec<u8>>, Vec<u8>);
+
+impl Read for ChannelReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.1.is_empty() {
+            match self.0.recv() {
+                Ok(data) => self.1 = data,
+                Err(_) => return Ok(0)
+            }
+        }
+        let length = buffer.len().min(self.1.len());
+        buffer[..length].copy_from_slice(&self.1[..length]);
+        self.1.drain(..length);
+        Ok(length)
+    }
+}
+
+#[test]
+fn test_recorded_session() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/dap");
+    let text = std::fs::read_to_string(fixtures.join("session.jsonl")).unwrap()
+        .replace("$FIXTURES", fixtures.to_str().unwrap());
+    let transcript: Vec<Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+    let (requests, received) = mpsc::channel();
+    let (sender, output) = mpsc::channel();
+    let client = thread::spawn(move || {
+        let mut output = BufReader::new(ChannelReader(output, Vec::new()));
+        let mut last = Value::Null;
+        for entry in transcript {
+            if entry["from"] == "client" {
+                last = entry["message"].clone();
+                requests.send(last.clone()).unwrap();
+            } else {
+                let message = dap::read_message(&mut output).unwrap().expect("adapter stopped");
+                assert_eq!(message, entry["message"], "after {}", last);
+            }
+        }
+        drop(requests);
+        assert_eq!(dap::read_message(&mut output).unwrap(), None);
+    });
+    let mut adapter = Adapter::new(ChannelWriter(sender), Box::new(dap::load));
+    adapter.run(received).unwrap();
+    drop(adapter);
+    client.join().unwrap();
+}
@@ -0,0 +1,82 @@
+// Recompiled from counter.ch8 by chip8 recompile, do not edit
+
+use chip8::cpu::CPU;
+use chip8::graphics::GraphicsDevice;
+use chip8::input::InputDevice;
+use chip8::instructions::Instruction;
+use chip8::memory::MemoryDevice;
+use chip8::recompiler::{BlockFn, CompiledBlock};
+use chip8::sound::SoundDevice;
+
+pub static ROM: [u8; 40] = [
+    0x60, 0x00, 0x61, 0x05, 0x22, 0x20, 0x71, 0x01, 0xF1, 0x29, 0xD0, 0x15, 0xA2, 0x16, 0xF0, 0x55,
+    0xB2, 0x14, 0x00, 0x00, 0x12, 0x04, 0x73, 0x01, 0x12, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x70, 0x02, 0x40, 0x04, 0x60, 0x00, 0x00, 0xEE,
+];
+
+pub fn lookup<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice>(pc: u16) -> Option<CompiledBlock<M, I, G, S>> {
+    let (length, run): (usize, BlockFn<M, I, G, S>) = match pc {
+        0x200 => (3, block_200),
+        0x206 => (3, block_206),
+        0x20C => (1, block_20c),
+        0x210 => (1, block_210),
+        0x220 => (2, block_220),
+        0x224 => (1, block_224),
+        0x226 => (1, block_226),
+        _ => return None
+    };
+    Some(CompiledBlock {start: pc, length, run})
+}
+
+fn block_200<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice>(cpu: &mut CPU<M, I, G, S>) {
+    // 0x200  6000  LoadRegister
+    cpu.set_register(0, 0x00);
+    // 0x202  6105  LoadRegister
+    cpu.set_register(1, 0x05);
+    // 0x204  2220  Call
+    cpu.PC = 0x204;
+    cpu.execute_opcode(Instruction::Call { address: 544 });
+}
+
+fn block_206<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice>(cpu: &mut CPU<M, I, G, S>) {
+    // 0x206  7101  AddToRegister
+    cpu.set_register(1, cpu.get_register(1).wrapping_add(0x01));
+    // 0x208  F129  SetIToFontAddress
+    cpu.PC = 0x208;
+    cpu.execute_opcode(Instruction::SetIToFontAddress { digit: 1 });
+    // 0x20A  D015  DisplaySpriteAtLocation
+    cpu.PC = 0x20A;
+    cpu.execute_opcode(Instruction::DisplaySpriteAtLocation { x: 0, y: 1, n: 5 });
+}
+
+fn block_20c<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice>(cpu: &mut CPU<M, I, G, S>) {
+    // 0x20C  A216  SetAddressRegister
+    cpu.I = 0x216;
+    cpu.PC = 0x20E;
+}
+
+fn block_210<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice>(cpu: &mut CPU<M, I, G, S>) {
+    // 0x210  B214  JumpToLocationAndOffset0
+    cpu.PC = 0x210;
+    cpu.execute_opcode(Instruction::JumpToLocationAndOffset0 { address: 532 });
+}
+
+fn block_220<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice>(cpu: &mut CPU<M, I, G, S>) {
+    // 0x220  7002  AddToRegister
+    cpu.set_register(0, cpu.get_register(0).wrapping_add(0x02));
+    // 0x222  4004  SkipIfNotEqual
+    cpu.PC = 0x222;
+    cpu.execute_opcode(Instruction::SkipIfNotEqual { register: 0, value: 4 });
+}
+
+fn block_224<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice>(cpu: &mut CPU<M, I, G, S>) {
+    // 0x224  6000  LoadRegister
+    cpu.set_register(0, 0x00);
+    cpu.PC = 0x226;
+}
+
+fn block_226<M: MemoryDevice, I: InputDevice, G: GraphicsDevice, S: SoundDevice>(cpu: &mut CPU<M, I, G, S>) {
+    // 0x226  00EE  Return
+    cpu.PC = 0x226;
+    cpu.execute_opcode(Instruction::Return);
+}
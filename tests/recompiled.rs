@@ -0,0 +1,40 @@
+// Runs a recompiled ROM next to the interpreter. The fixture is regenerated with
+// chip8 recompile counter.ch8 --out tests/fixtures/recompiled_counter.rs
+use chip8::bus::Bus;
+use chip8::cpu::CPU;
+use chip8::recompiler::{recompile, Recompiled};
+
+mod counter {
+    include!("fixtures/recompiled_counter.rs");
+}
+
+fn get_cpu() -> CPU {
+    let mut cpu = CPU::new(Bus::new());
+    cpu.bus.memory.load_rom(&counter::ROM).unwrap();
+    cpu.set_pc(&0x200);
+    cpu
+}
+
+#[test]
+fn test_fixture_is_up_to_date() {
+    assert_eq!(recompile(&counter::ROM, 0x200, "counter.ch8"), include_str!("fixtures/recompiled_counter.rs"));
+}
+
+#[test]
+fn test_same_state_as_interpreter() {
+    let mut reference = get_cpu();
+    let mut cpu = get_cpu();
+    let mut recompiled = Recompiled::new(counter::lookup);
+    for _ in 0..200 {
+        reference.run_frame(7);
+        recompiled.run_frame(&mut cpu, 7);
+        assert_eq!(reference.registers, cpu.registers);
+        assert_eq!((reference.I, reference.PC, reference.SP), (cpu.I, cpu.PC, cpu.SP));
+        assert_eq!(reference.stack, cpu.stack);
+        assert_eq!(reference.bus.memory.memory, cpu.bus.memory.memory);
+        assert_eq!(reference.bus.graphics.buffer, cpu.bus.graphics.buffer);
+    }
+    // The ADD V3 at 0x216 is overwritten with a NOP before the computed jump first reaches it
+    assert_ne!(cpu.bus.memory.memory[0x216], 0x73);
+    assert_eq!(cpu.registers[3], 0);
+}